@@ -0,0 +1,171 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashSet,
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+// The server is a binary crate, so its module is compiled into the benchmark
+#[allow(dead_code, unused_imports)]
+#[path = "../src/domain_set.rs"]
+mod domain_set;
+
+use domain_set::DomainSet;
+
+/// The sizes of combinations of popular blocklists, the largest of which list more than a million domains
+const SIZES: [usize; 2] = [100_000, 1_000_000];
+
+/// Counts the bytes allocated on the heap, to compare the memory the sets take
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Domains like those of blocklists, with random labels under a few thousand parent domains and common top-level
+/// domains. A xorshift generator keeps them the same across runs.
+fn domains(count: usize) -> Vec<String> {
+    const TLDS: [&str; 6] = ["com", "net", "org", "io", "de", "co.uk"];
+    let mut state = 0x9e3779b97f4a7c15_u64;
+    let mut label = |len: u64| {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                char::from(b"abcdefghijklmnopqrstuvwxyz0123456789"[(state % 36) as usize])
+            })
+            .collect::<String>()
+    };
+    let parents: Vec<String> = (0..5_000)
+        .map(|i| format!("{}.{}", label(6 + i % 8), TLDS[i as usize % TLDS.len()]))
+        .collect();
+    (0..count)
+        .map(|i| {
+            format!(
+                "{}.{}",
+                label(4 + i as u64 % 12),
+                parents[i % parents.len()]
+            )
+        })
+        .collect()
+}
+
+fn domain_set(domains: &[String]) -> DomainSet {
+    let mut set = DomainSet::default();
+    for (i, domain) in domains.iter().enumerate() {
+        if i % 10 == 0 {
+            set.insert_subdomains(domain);
+        }
+        set.insert_domain(domain);
+    }
+    set.commit();
+    set
+}
+
+/// The bytes of the heap that `build` keeps allocated, which are negative if it frees more than it allocates
+fn heap_size<T>(build: impl FnOnce() -> T) -> (T, isize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let built = build();
+    let size = ALLOCATED.load(Ordering::Relaxed).wrapping_sub(before) as isize;
+    (built, size)
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    for size in SIZES {
+        let domains = domains(size);
+        group.bench_with_input(
+            BenchmarkId::new("DomainSet", size),
+            &domains,
+            |b, domains| {
+                b.iter(|| domain_set(black_box(domains)));
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("HashSet", size), &domains, |b, domains| {
+            b.iter(|| black_box(domains).iter().cloned().collect::<HashSet<_>>());
+        });
+    }
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    for size in SIZES {
+        let domains = domains(size);
+        let set = domain_set(&domains);
+        let hash_set: HashSet<_> = domains.iter().cloned().collect();
+        let queries = [
+            ("listed", domains[size / 2 + 1].clone()),
+            ("subdomain", format!("a.b.{}", domains[size / 2])),
+            ("unlisted", "www.example.com".to_string()),
+        ];
+        for (kind, query) in &queries {
+            assert_eq!(set.contains(query), *kind != "unlisted", "{query}");
+            group.bench_with_input(
+                BenchmarkId::new(format!("DomainSet/{kind}"), size),
+                query,
+                |b, query| b.iter(|| set.contains(black_box(query))),
+            );
+        }
+        // Without subdomains, which would take one lookup per label
+        group.bench_with_input(
+            BenchmarkId::new("HashSet/listed", size),
+            &queries[0].1,
+            |b, query| b.iter(|| hash_set.contains(black_box(query))),
+        );
+    }
+    group.finish();
+}
+
+/// Criterion only measures time, so the memory of the sets is printed along with the time to map a persisted set
+fn memory(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memory");
+    let path = std::env::temp_dir().join(format!("domain-set-bench-{}.fst", std::process::id()));
+    for size in SIZES {
+        let domains = domains(size);
+        let (mut set, set_bytes) = heap_size(|| domain_set(&domains));
+        let (hash_set, hash_set_bytes) =
+            heap_size(|| domains.iter().cloned().collect::<HashSet<_>>());
+        let ((), mapped_bytes) = heap_size(|| set.persist(&path).unwrap());
+        println!(
+            "{size} domains: DomainSet {} KiB on the heap, {} KiB on the heap once mapped from a {} KiB file, HashSet<String> {} KiB",
+            set_bytes / 1024,
+            (set_bytes + mapped_bytes) / 1024,
+            set.size() / 1024,
+            hash_set_bytes / 1024,
+        );
+        drop(hash_set);
+
+        group.bench_function(BenchmarkId::new("DomainSet::open", size), |b| {
+            b.iter(|| DomainSet::open(black_box(&path)).unwrap());
+        });
+    }
+    let _ = std::fs::remove_file(&path);
+    group.finish();
+}
+
+criterion_group!(benches, build, lookup, memory);
+criterion_main!(benches);
@@ -6,11 +6,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use dns::{
-    parser::{DnsPacketBuffer, DnsParser},
-    protocol::packet::DnsPacket,
-    protocol::record_type::RecordType,
-};
+use dns::{parser::DnsParser, protocol::packet::DnsPacket, protocol::record_type::RecordType};
 
 #[derive(Debug, Clone, Default)]
 pub(crate) struct RequestCache {
@@ -23,7 +19,7 @@ impl RequestCache {
     }
 
     /// Reads a valid, existing entry for the cache key and automatically invalidates outdated cache entries.
-    pub fn get(&mut self, key: CacheKey, new_request_id: u16) -> Option<DnsPacketBuffer> {
+    pub fn get(&mut self, key: CacheKey, new_request_id: u16) -> Option<Vec<u8>> {
         match self.inner.entry(key) {
             Vacant(_) => None,
             Occupied(mut entry) => {
@@ -33,7 +29,7 @@ impl RequestCache {
                     let parser = DnsParser::new(&cached.packet);
                     // We construct a version of the cached DNS reply that has up-to-date answer TTL values
                     // and is compatible with the given `new_request_id`, but we never update the cached data.
-                    let updated_packet: DnsPacketBuffer = parser
+                    let updated_packet = parser
                         .update_cached_packet(cached.get_remaining_ttl(), new_request_id)
                         .expect("Could not parse and reduce ttl of DNS packet answers");
 
@@ -46,7 +42,7 @@ impl RequestCache {
         }
     }
 
-    pub fn set(&mut self, key: CacheKey, buffer: DnsPacketBuffer) {
+    pub fn set(&mut self, key: CacheKey, buffer: Vec<u8>) {
         self.inner.insert(key, CacheValue::new(buffer));
     }
 }
@@ -74,14 +70,14 @@ impl CacheKey {
 pub(crate) struct CacheValue {
     /// The point in time when the cached entry expires. We need this to quickly check if an entry can be discarded or not.
     pub(crate) expires_at: Instant,
-    /// The point in time when the entry was cached. We need this to calculate how much TTL is left when updating the cached `DnsPacketBuffer` buffer.
+    /// The point in time when the entry was cached. We need this to calculate how much TTL is left when updating the cached packet.
     pub(crate) cached_at: Instant,
-    /// We cache the entire original buffer
-    pub(crate) packet: DnsPacketBuffer,
+    /// We cache the entire original packet, which may exceed 512 bytes if it was received via TCP
+    pub(crate) packet: Vec<u8>,
 }
 
 impl CacheValue {
-    pub fn new(reply: DnsPacketBuffer) -> Self {
+    pub fn new(reply: Vec<u8>) -> Self {
         // We use the minimum TTL over all records in the DNS answer to calculate until when
         // the cached entry should still be usable
         let parsed = DnsParser::new(&reply).parse().unwrap();
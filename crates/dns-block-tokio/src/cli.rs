@@ -1,13 +1,38 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, error::ErrorKind, parser::ValueSource};
 
-use crate::domain_rewrite::DomainRewrite;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{
+    doh::DohMethod,
+    domain_rewrite::{DomainRewrite, Rewrites},
+    filter::BlockMode,
+    list_cache::ListCache,
+    upstream::Upstream,
+};
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub(crate) struct ServerArgs {
-    /// DNS server to forward to
-    #[arg(short, long, default_value_t = String::from("1.1.1.1:53"))]
-    pub dns_relay: String, // TODO: Add support for multiple DNS servers
+    /// DNS server to forward to, either a plain address like `1.1.1.1:53`, a DNS-over-TLS server like
+    /// `tls://1.1.1.1:853#cloudflare-dns.com`, where the fragment is the name to validate the certificate for,
+    /// a DNS-over-QUIC server like `quic://dns.adguard-dns.com` with the same optional fragment,
+    /// or a DNS-over-HTTPS server like `https://cloudflare-dns.com/dns-query`.
+    /// Defaults to the first name server of /etc/resolv.conf that is neither a loopback address nor the address the
+    /// blocker listens on, so it never forwards to itself, or `1.1.1.1:53` if there is none
+    #[arg(short, long, default_value_t = Upstream::system(None), hide_default_value = true, value_parser = clap::value_parser!(Upstream))]
+    pub dns_relay: Upstream, // TODO: Add support for multiple DNS servers
+
+    /// HTTP method to send queries to a DNS-over-HTTPS server with
+    #[arg(long, value_enum, default_value_t = DohMethod::Post)]
+    pub doh_method: DohMethod,
+
+    /// Plain DNS server to resolve the host name of a DNS-over-HTTPS server with
+    #[arg(long, default_value = "1.1.1.1:53")]
+    pub bootstrap_dns: SocketAddr,
+
+    /// Whether to resolve queries on its own, starting at the root servers, instead of forwarding them to `--dns-relay`
+    #[arg(long, default_value_t = false)]
+    pub recursive: bool,
 
     /// Port to listen on
     #[arg(long, default_value_t = String::from("0.0.0.0") )]
@@ -17,21 +42,58 @@ pub(crate) struct ServerArgs {
     #[arg(long, default_value_t = 53000)]
     pub bind_port: u16,
 
-    /// Domains to block from being resolved
+    /// Domains to block from being resolved. Entries like `*.example.com` block all subdomains of `example.com`,
+    /// and adblock-style rules like `||example.com^` or `@@||www.example.com^` are supported as well
     #[arg(long, value_parser, use_value_delimiter = true)]
     pub blocked_domains: Vec<String>,
 
-    /// Source URLs for domain lists to block from being resolved
+    /// Whether blocked and allowed domains also cover their subdomains
+    #[arg(long, value_enum, default_value_t = BlockMode::Subdomains)]
+    pub block_mode: BlockMode,
+
+    /// URLs or paths of domain lists to block from being resolved. Paths like `/etc/dns/lists/*.txt`, or directories
+    /// like `file:///etc/dns/lists`, may cover several files, which are read again whenever files in them change
     #[arg(long, value_parser, use_value_delimiter = true)]
     pub domain_blacklists: Vec<String>,
 
-    /// Comma-separated tuples of `<domain>:<ip>` that describe how to resolve
-    /// a domain to a static IP.
+    /// Domains to never block, even if a blocklist or rule blocks them. Entries like `*.example.com` allow all
+    /// subdomains of `example.com`
+    #[arg(long, value_parser, use_value_delimiter = true)]
+    pub allowed_domains: Vec<String>,
+
+    /// URLs or paths of domain lists to never block, which take precedence over all blocklists. Paths may cover
+    /// several files like those of `--domain-blacklists`
+    #[arg(long, value_parser, use_value_delimiter = true)]
+    pub domain_allowlists: Vec<String>,
+
+    /// Seconds between re-fetching the URLs of `--domain-blacklists` and `--domain-allowlists`, which are only
+    /// downloaded again if they changed, or 0 to only fetch them on startup. A list that fails to refresh keeps its
+    /// last version
+    #[arg(long, default_value_t = 86400)]
+    pub list_refresh_interval: u64,
+
+    /// Directory to store the fetched lists in, whose copies are used when a list can't be fetched on startup.
+    /// Defaults to `lists` in systemd's `$STATE_DIRECTORY`, or in `$XDG_STATE_HOME/dns-block-tokio`
+    #[arg(long, default_value_t = ListCache::default_directory(), hide_default_value = true)]
+    pub list_cache_dir: String,
+
+    /// Whether to refuse to start if none of `--domain-blacklists` could be fetched or loaded from the cache
+    #[arg(long, default_value_t = false)]
+    pub require_blocklists: bool,
+
+    /// Rewrites of the form `<domain>:<ips>` with comma-separated IPv4 and IPv6 addresses, or
+    /// `<domain>:<type> <data>` with a record in zone file format, e.g. `www.home:CNAME nas.home`.
     ///
-    /// The IPs can only be in IPv4 format as of now. The domains are not validated.
+    /// A domain like `*.dev.lan` matches all subdomains of `dev.lan`, and the most specific rewrite of a
+    /// domain wins. Queries for other record types of a rewritten domain are answered without records. A domain with
+    /// a CNAME rewrite can't have other rewrites.
     #[arg(long, value_parser = clap::value_parser!(DomainRewrite))]
     pub domain_rewrites: Vec<DomainRewrite>,
 
+    /// TTL in seconds of the records that answer queries for `--domain-rewrites`
+    #[arg(long, default_value_t = 300)]
+    pub rewrite_ttl: u32,
+
     /// Whether to disable logging
     #[arg(short, long, default_value_t = false)]
     pub quiet: bool,
@@ -55,6 +117,21 @@ pub(crate) struct ServerArgs {
 
 impl ServerArgs {
     pub fn from_env() -> Self {
-        Self::parse()
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        // The default is picked before the address to listen on is known
+        if matches.value_source("dns_relay") == Some(ValueSource::DefaultValue) {
+            let bind = args
+                .bind_address
+                .parse::<IpAddr>()
+                .ok()
+                .map(|ip| SocketAddr::new(ip, args.bind_port));
+            args.dns_relay = Upstream::system(bind);
+        }
+        // Rewrites can only conflict with each other once all of them are parsed
+        if let Err(e) = Rewrites::new(&args.domain_rewrites) {
+            Self::command().error(ErrorKind::ValueValidation, e).exit();
+        }
+        args
     }
 }
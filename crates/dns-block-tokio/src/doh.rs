@@ -0,0 +1,427 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use dns::{
+    client::Client,
+    parser::DnsParser,
+    protocol::{answer::ResourceRecordData, query::Query, record_type::RecordType},
+};
+use reqwest::{
+    StatusCode, Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::{ACCEPT, AGE, CACHE_CONTROL, CONTENT_TYPE, HeaderMap},
+};
+
+/// The media type of DNS messages in requests and responses, see https://datatracker.ietf.org/doc/html/rfc8484#section-6
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// How a DNS query is sent to a DNS-over-HTTPS server, see https://datatracker.ietf.org/doc/html/rfc8484#section-4.1
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum DohMethod {
+    /// The query is the request body
+    Post,
+    /// The query is encoded into the `dns` URL parameter, which makes answers cacheable by HTTP caches
+    Get,
+}
+
+/// Sends DNS messages to a single DNS-over-HTTPS server, see https://datatracker.ietf.org/doc/html/rfc8484
+///
+/// All exchanges share one HTTP client, which keeps its connections open and multiplexes concurrent exchanges over a
+/// single HTTP/2 connection, if the server supports it. The server's host name is resolved via the plain DNS
+/// `bootstrap` server, since the system resolver may well be this very DNS server.
+#[derive(Debug)]
+pub(crate) struct HttpsConnection {
+    client: reqwest::Client,
+    url: Url,
+    method: DohMethod,
+}
+
+impl HttpsConnection {
+    pub fn new(url: &str, method: DohMethod, bootstrap: SocketAddr) -> Result<Self, io::Error> {
+        let url = Url::parse(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(2))
+            .timeout(Duration::from_secs(5))
+            // A proxy would resolve the host name on its own and bypass the bootstrap server
+            .no_proxy()
+            .dns_resolver(BootstrapResolver {
+                client: Client::new(bootstrap),
+            })
+            .build()
+            .map_err(io::Error::other)?;
+
+        Ok(Self {
+            client,
+            url,
+            method,
+        })
+    }
+
+    /// Sends the raw DNS message `request` and returns the server's answer with the same request ID
+    ///
+    /// The TTLs of the answer are capped to its remaining HTTP freshness lifetime, so that it is not cached for
+    /// longer than the server allows.
+    pub async fn exchange(&self, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        if request.len() < 12 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "dns https: request is too short",
+            ));
+        }
+
+        // HTTP already matches the answer to the query, and a request ID of 0 lets HTTP caches serve
+        // identical queries, see https://datatracker.ietf.org/doc/html/rfc8484#section-4.1
+        let mut message = request.to_vec();
+        message[..2].copy_from_slice(&[0, 0]);
+
+        let http_request = match self.method {
+            DohMethod::Post => self
+                .client
+                .post(self.url.clone())
+                .header(CONTENT_TYPE, DNS_MESSAGE)
+                .body(message),
+            DohMethod::Get => {
+                let mut url = self.url.clone();
+                url.query_pairs_mut()
+                    .append_pair("dns", &URL_SAFE_NO_PAD.encode(&message));
+                self.client.get(url)
+            }
+        };
+        let response = http_request
+            .header(ACCEPT, DNS_MESSAGE)
+            .send()
+            .await
+            .map_err(into_io_error)?;
+
+        if response.status() != StatusCode::OK {
+            return Err(io::Error::other(format!(
+                "dns https: server responded with {}",
+                response.status()
+            )));
+        }
+        if !is_dns_message(response.headers()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "dns https: answer is not a DNS message",
+            ));
+        }
+        let freshness_lifetime = freshness_lifetime(response.headers());
+
+        let mut answer = response.bytes().await.map_err(into_io_error)?.to_vec();
+        if answer.len() < 12 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "dns https: answer is too short",
+            ));
+        }
+        answer[..2].copy_from_slice(&request[..2]);
+
+        match freshness_lifetime {
+            Some(max_ttl) => DnsParser::new(&answer).cap_ttls(max_ttl).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "dns https: answer is malformed")
+            }),
+            None => Ok(answer),
+        }
+    }
+}
+
+fn into_io_error(error: reqwest::Error) -> io::Error {
+    if error.is_timeout() {
+        io::Error::new(io::ErrorKind::TimedOut, "dns https: answer timed out")
+    } else {
+        io::Error::other(error)
+    }
+}
+
+fn is_dns_message(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(DNS_MESSAGE))
+}
+
+/// Returns the seconds an HTTP response stays fresh according to its `Cache-Control: max-age` directive, minus
+/// the time it already spent in HTTP caches, see https://datatracker.ietf.org/doc/html/rfc9111#section-4.2
+fn freshness_lifetime(headers: &HeaderMap) -> Option<u32> {
+    let max_age = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|directive| {
+            let (name, seconds) = directive.trim().split_once('=')?;
+            name.eq_ignore_ascii_case("max-age")
+                .then(|| seconds.trim_matches('"').parse::<u32>().ok())?
+        })?;
+    let age = headers
+        .get(AGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .unwrap_or(0);
+
+    Some(max_age.saturating_sub(age))
+}
+
+/// Resolves host names via a plain DNS server
+struct BootstrapResolver {
+    client: Client,
+}
+
+impl Resolve for BootstrapResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let client = self.client.clone();
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let (query_ipv4, query_ipv6) = (
+                Query::new(&host, RecordType::A),
+                Query::new(&host, RecordType::AAAA),
+            );
+            let (ipv4, ipv6) = tokio::join!(client.query(&query_ipv4), client.query(&query_ipv6));
+            // The port is replaced by the one of the URL
+            let addresses: Vec<SocketAddr> = ipv4
+                .into_iter()
+                .chain(ipv6)
+                .flat_map(|packet| packet.answers)
+                .filter_map(|answer| match answer.value {
+                    ResourceRecordData::A { ipv4 } => Some(SocketAddr::new(ipv4.into(), 0)),
+                    ResourceRecordData::AAAA { ipv6 } => Some(SocketAddr::new(ipv6.into(), 0)),
+                    _ => None,
+                })
+                .collect();
+
+            if addresses.is_empty() {
+                return Err(format!(
+                    "dns https: could not resolve {host} via the bootstrap server"
+                )
+                .into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use dns::{
+        parser::DnsParser,
+        protocol::{
+            answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+            query::Query,
+            record_type::RecordType,
+        },
+        serialize::{encode_resource_record, serialize_query},
+    };
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, UdpSocket},
+    };
+
+    use crate::doh::{DohMethod, HttpsConnection, freshness_lifetime};
+
+    /// Turns a query into an answer with a single A record for `ipv4`, or none if it is not an A query
+    fn answer(query: &[u8], ipv4: [u8; 4]) -> Vec<u8> {
+        let mut padded = query.to_vec();
+        padded.resize(512, 0);
+        let packet = DnsParser::new(&padded).parse().unwrap();
+        let is_a_query = packet.question.r#type == RecordType::A;
+
+        let mut header = packet.header;
+        header.flags.query = false;
+        header.answer_count = is_a_query as u16;
+        header.additional_count = 0;
+
+        let question_end = 12 + query[12..].iter().position(|&b| b == 0).unwrap() + 1 + 4;
+        let h: [u8; 12] = header.into();
+        let mut response = h.to_vec();
+        response.extend_from_slice(&query[12..question_end]);
+        if is_a_query {
+            encode_resource_record(
+                &mut response,
+                &ResourceRecord::new(
+                    ResourceRecordMeta {
+                        name: packet.question.domain_name,
+                        record_type: RecordType::A,
+                        class: 1,
+                        ttl: 300,
+                        len: 4,
+                    },
+                    ResourceRecordData::A { ipv4: ipv4.into() },
+                ),
+            )
+            .unwrap();
+        }
+        response
+    }
+
+    /// A minimal HTTP/1.1 DoH server with keep-alive, which records the request line of every request
+    async fn stub_server(
+        cache_control: Option<&'static str>,
+    ) -> (SocketAddr, Arc<AtomicUsize>, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(Mutex::new(vec![]));
+        let (counter, log) = (Arc::clone(&connections), Arc::clone(&requests));
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let log = Arc::clone(&log);
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    loop {
+                        let mut request_line = String::new();
+                        if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let mut content_length = 0;
+                        loop {
+                            let mut line = String::new();
+                            stream.read_line(&mut line).await.unwrap();
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                            let (name, value) = line.split_once(':').unwrap();
+                            if name.eq_ignore_ascii_case("content-length") {
+                                content_length = value.trim().parse().unwrap();
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        stream.read_exact(&mut body).await.unwrap();
+
+                        let query = match request_line.split_once("?dns=") {
+                            Some((_, rest)) => URL_SAFE_NO_PAD
+                                .decode(rest.split(' ').next().unwrap())
+                                .unwrap(),
+                            None => body,
+                        };
+                        assert_eq!(query[..2], [0, 0], "request ID has to be 0");
+                        log.lock().unwrap().push(request_line.trim().to_string());
+
+                        let body = answer(&query, [192, 0, 2, 1]);
+                        let mut response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\n",
+                            body.len()
+                        );
+                        if let Some(cache_control) = cache_control {
+                            response.push_str(&format!(
+                                "cache-control: {cache_control}\r\nage: 10\r\n"
+                            ));
+                        }
+                        response.push_str("\r\n");
+                        stream.write_all(response.as_bytes()).await.unwrap();
+                        stream.write_all(&body).await.unwrap();
+                    }
+                });
+            }
+        });
+        (address, connections, requests)
+    }
+
+    fn unused_bootstrap() -> SocketAddr {
+        "127.0.0.1:9".parse().unwrap()
+    }
+
+    async fn exchange_ttls(connection: &HttpsConnection, id: u16) -> Vec<u32> {
+        let request = serialize_query(&Query::new("example.com", RecordType::A), id);
+        let response = connection.exchange(&request).await.unwrap();
+        let packet = DnsParser::new(&response).parse().unwrap();
+        assert_eq!(packet.header.request_id, id);
+        packet.answers.iter().map(|a| a.meta.ttl).collect()
+    }
+
+    #[tokio::test]
+    async fn test_exchange_post_reuses_connection() {
+        let (address, connections, requests) = stub_server(None).await;
+        let url = format!("http://{address}/dns-query");
+        let connection = HttpsConnection::new(&url, DohMethod::Post, unused_bootstrap()).unwrap();
+
+        for id in 1..=3 {
+            assert_eq!(exchange_ttls(&connection, id).await, [300]);
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert!(
+            requests
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|line| line == "POST /dns-query HTTP/1.1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exchange_get_honours_max_age() {
+        let (address, _, requests) = stub_server(Some("public, max-age=60")).await;
+        let url = format!("http://{address}/dns-query");
+        let connection = HttpsConnection::new(&url, DohMethod::Get, unused_bootstrap()).unwrap();
+
+        // Capped to the max-age minus the age of 10 seconds
+        assert_eq!(exchange_ttls(&connection, 0x1337).await, [50]);
+        assert!(requests.lock().unwrap()[0].starts_with("GET /dns-query?dns="));
+    }
+
+    #[tokio::test]
+    async fn test_exchange_resolves_host_via_bootstrap_server() {
+        let (address, _, _) = stub_server(None).await;
+        let bootstrap = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bootstrap_address = bootstrap.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            loop {
+                let (len, client) = bootstrap.recv_from(&mut query).await.unwrap();
+                let response = answer(&query[..len], [127, 0, 0, 1]);
+                bootstrap.send_to(&response, client).await.unwrap();
+            }
+        });
+
+        let url = format!("http://doh.test:{}/dns-query", address.port());
+        let connection = HttpsConnection::new(&url, DohMethod::Post, bootstrap_address).unwrap();
+        assert_eq!(exchange_ttls(&connection, 1).await, [300]);
+    }
+
+    #[test]
+    fn test_freshness_lifetime() {
+        let headers = |values: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in values {
+                headers.append(*name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+
+        assert_eq!(freshness_lifetime(&headers(&[])), None);
+        assert_eq!(
+            freshness_lifetime(&headers(&[("cache-control", "no-transform")])),
+            None
+        );
+        assert_eq!(
+            freshness_lifetime(&headers(&[("cache-control", "public, Max-Age=120")])),
+            Some(120)
+        );
+        assert_eq!(
+            freshness_lifetime(&headers(&[
+                ("cache-control", "public"),
+                ("cache-control", "max-age=120"),
+                ("age", "20")
+            ])),
+            Some(100)
+        );
+        assert_eq!(
+            freshness_lifetime(&headers(&[("cache-control", "max-age=10"), ("age", "20")])),
+            Some(0)
+        );
+    }
+}
@@ -1,28 +1,51 @@
-use std::{error::Error, fmt::Display, str::FromStr};
+use std::{collections::HashMap, error::Error, fmt::Display, net::IpAddr, str::FromStr};
+
+use dns::protocol::{
+    answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+    class::CLASS_IN,
+    record_type::RecordType,
+    response_code::ResponseCode,
+};
+
+/// How many CNAME rewrites are followed for one query, which stops rewrites that point at each other in a loop
+const MAX_CNAME_CHAIN: usize = 8;
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct DomainRewrite {
-    ip: std::net::Ipv4Addr,
-    domain: String,
+    /// A domain, or a wildcard like `*.dev.lan` that matches all subdomains of `dev.lan`
+    pattern: String,
+    records: Vec<ResourceRecordData>,
 }
 
 #[derive(Debug, PartialEq)]
 pub(crate) enum DomainRewriteError {
     Format,
     DomainMissing,
+    PatternInvalid(String),
     IpInvalid(String),
+    RecordInvalid(String),
+    CnameConflict(String),
 }
 
 impl Display for DomainRewriteError {
     // Could use thiserror but this is fine
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DomainRewriteError::Format => {
-                f.write_str("domain rewrite: required format is <domain>:<ip>")
-            }
+            DomainRewriteError::Format => f.write_str(
+                "domain rewrite: required format is <domain>:<ip>[,<ip>...] or <domain>:<type> <data>",
+            ),
             DomainRewriteError::DomainMissing => f.write_str("domain rewrite: domain is missing"),
+            DomainRewriteError::PatternInvalid(pattern) => f.write_fmt(format_args!(
+                "domain rewrite: '{pattern}' is neither a domain nor a wildcard like *.<domain>"
+            )),
             DomainRewriteError::IpInvalid(ip) => f.write_fmt(format_args!(
-                "domain rewrite: given IP address '{ip}' is not a valid IPv4 address"
+                "domain rewrite: given IP address '{ip}' is not a valid IPv4 or IPv6 address"
+            )),
+            DomainRewriteError::RecordInvalid(record) => f.write_fmt(format_args!(
+                "domain rewrite: given record '{record}' is not valid"
+            )),
+            DomainRewriteError::CnameConflict(pattern) => f.write_fmt(format_args!(
+                "domain rewrite: '{pattern}' has a CNAME record, which cannot be combined with other records"
             )),
         }
     }
@@ -33,28 +56,332 @@ impl Error for DomainRewriteError {}
 impl FromStr for DomainRewrite {
     type Err = DomainRewriteError;
 
+    /// Parses `<domain>:<ips>` with comma-separated IPv4 and IPv6 addresses, or `<domain>:<type> <data>` with a
+    /// record in zone file format. Only the first ':' separates the domain, so IPv6 addresses need no escaping.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (domain, raw_ip) = s.split_once(":").ok_or(DomainRewriteError::Format)?;
+        let (pattern, target) = s.split_once(":").ok_or(DomainRewriteError::Format)?;
 
-        if domain.is_empty() {
+        if pattern.is_empty() {
             return Err(DomainRewriteError::DomainMissing);
         }
+        let domain = pattern.strip_prefix("*.").unwrap_or(pattern);
+        if domain.is_empty() || domain.contains('*') {
+            return Err(DomainRewriteError::PatternInvalid(pattern.into()));
+        }
 
-        let ip = std::net::Ipv4Addr::from_str(raw_ip)
-            .map_err(|_| DomainRewriteError::IpInvalid(raw_ip.into()))?;
+        let target = target.trim();
+        let records = match target.split_once(char::is_whitespace) {
+            Some((record_type, data)) if RecordType::from_str(record_type).is_ok() => {
+                let record_type = RecordType::from_str(record_type).unwrap();
+                let record = parse_record(record_type, data.trim())
+                    .ok_or_else(|| DomainRewriteError::RecordInvalid(target.into()))?;
+                vec![record]
+            }
+            _ => target
+                .split(',')
+                .map(|raw_ip| match IpAddr::from_str(raw_ip.trim()) {
+                    Ok(IpAddr::V4(ipv4)) => Ok(ResourceRecordData::A { ipv4 }),
+                    Ok(IpAddr::V6(ipv6)) => Ok(ResourceRecordData::AAAA { ipv6 }),
+                    Err(_) => Err(DomainRewriteError::IpInvalid(raw_ip.into())),
+                })
+                .collect::<Result<_, _>>()?,
+        };
 
         Ok(DomainRewrite {
-            ip,
-            domain: domain.to_string(),
+            pattern: pattern.trim_end_matches('.').to_ascii_lowercase(),
+            records,
         })
     }
 }
 
+/// Parses the RR data of a record in zone file format, or in the generic `\# <length> <hex>` format of
+/// https://datatracker.ietf.org/doc/html/rfc3597#section-5 for any record type
+fn parse_record(record_type: RecordType, data: &str) -> Option<ResourceRecordData> {
+    if let Some(generic) = data.strip_prefix("\\#") {
+        let mut fields = generic.split_whitespace();
+        let len: usize = fields.next()?.parse().ok()?;
+        let hex: String = fields.collect();
+        if hex.len() != 2 * len || !hex.is_ascii() {
+            return None;
+        }
+        let data = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<_>>()?;
+        return Some(ResourceRecordData::Raw { record_type, data });
+    }
+
+    let record = match record_type {
+        RecordType::A => ResourceRecordData::A {
+            ipv4: data.parse().ok()?,
+        },
+        RecordType::AAAA => ResourceRecordData::AAAA {
+            ipv6: data.parse().ok()?,
+        },
+        RecordType::CNAME => ResourceRecordData::CNAME {
+            cname: parse_name(data)?,
+        },
+        RecordType::NS => ResourceRecordData::NS {
+            ns: parse_name(data)?,
+        },
+        RecordType::PTR => ResourceRecordData::PTR {
+            domain_name: parse_name(data)?,
+        },
+        RecordType::MX => {
+            let (preference, exchange) = data.split_once(char::is_whitespace)?;
+            ResourceRecordData::MX {
+                preference: preference.parse().ok()?,
+                exchange: parse_name(exchange.trim())?,
+            }
+        }
+        RecordType::TXT => ResourceRecordData::Raw {
+            record_type,
+            data: parse_character_strings(data)?,
+        },
+        _ => return None,
+    };
+    Some(record)
+}
+
+fn parse_name(data: &str) -> Option<String> {
+    let name = data.trim_end_matches('.');
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+/// Encodes quoted or unquoted strings, separated by whitespace, as `<character-string>`s, see
+/// https://datatracker.ietf.org/doc/html/rfc1035#section-3.3
+fn parse_character_strings(data: &str) -> Option<Vec<u8>> {
+    let mut encoded = vec![];
+    let mut chars = data.trim().chars().peekable();
+    while chars.peek().is_some() {
+        let mut text = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => text.push(chars.next()?),
+                    c => text.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                text.push(c);
+            }
+        }
+        if text.len() > u8::MAX as usize {
+            return None;
+        }
+        encoded.push(text.len() as u8);
+        encoded.extend_from_slice(text.as_bytes());
+
+        // Strings have to be separated by whitespace
+        if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return None;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+    (!encoded.is_empty()).then_some(encoded)
+}
+
+/// The records of all rewrites grouped by their pattern, which is lowercase since domains are case-insensitive
+#[derive(Debug, Default)]
+pub(crate) struct Rewrites(HashMap<String, Vec<ResourceRecordData>>);
+
+/// The local answer to a query for a rewritten domain
+#[derive(Debug, PartialEq)]
+pub(crate) struct RewriteAnswers {
+    /// NOERROR, or SERVFAIL without records if the CNAME chain is longer than `MAX_CNAME_CHAIN`
+    pub response_code: u8,
+    pub records: Vec<ResourceRecord>,
+    /// The target of the last CNAME record, if no rewrite covers it, so its records have to be resolved upstream
+    pub unresolved_cname: Option<String>,
+}
+
+impl Rewrites {
+    /// Merges the records of rewrites for the same pattern. A CNAME record can't be combined with other records,
+    /// including other CNAME records, see https://datatracker.ietf.org/doc/html/rfc1034#section-3.6.2
+    pub fn new(rewrites: &[DomainRewrite]) -> Result<Self, DomainRewriteError> {
+        let is_cname =
+            |record: &ResourceRecordData| matches!(record, ResourceRecordData::CNAME { .. });
+        let mut patterns: HashMap<String, Vec<ResourceRecordData>> = HashMap::new();
+        for rewrite in rewrites {
+            let records = patterns.entry(rewrite.pattern.clone()).or_default();
+            for record in &rewrite.records {
+                if records.contains(record) {
+                    continue;
+                }
+                if (is_cname(record) && !records.is_empty()) || records.iter().any(is_cname) {
+                    return Err(DomainRewriteError::CnameConflict(rewrite.pattern.clone()));
+                }
+                records.push(record.clone());
+            }
+        }
+        Ok(Self(patterns))
+    }
+
+    /// Returns the records of the most specific rewrite for `domain`, which is the domain's own rewrite, or else the
+    /// wildcard rewrite of its closest parent domain
+    pub fn find(&self, domain: &str) -> Option<&[ResourceRecordData]> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(records) = self.0.get(&domain) {
+            return Some(records);
+        }
+
+        let mut parent = domain.as_str();
+        while let Some((_, rest)) = parent.split_once('.') {
+            if let Some(records) = self.0.get(&format!("*.{rest}")) {
+                return Some(records);
+            }
+            parent = rest;
+        }
+        None
+    }
+
+    /// Answers a query for `domain` from the rewrites, if any rewrite matches it.
+    ///
+    /// Only the records of the queried type are answered, so queries for other types get no records, which tells
+    /// clients that the domain itself exists. A CNAME rewrite answers queries of all types with the CNAME record and
+    /// the records of its target, which either is rewritten as well, or has to be resolved upstream. A chain of more
+    /// than `MAX_CNAME_CHAIN` CNAME rewrites, which most likely is a loop, is answered with SERVFAIL.
+    pub fn answers(
+        &self,
+        domain: &str,
+        record_type: RecordType,
+        ttl: u32,
+    ) -> Option<RewriteAnswers> {
+        let mut records = self.find(domain)?;
+        let mut owner = domain.to_string();
+        let mut answers = RewriteAnswers {
+            response_code: ResponseCode::NOERROR.into(),
+            records: vec![],
+            unresolved_cname: None,
+        };
+
+        loop {
+            let cname = records.iter().find_map(|record| match record {
+                ResourceRecordData::CNAME { cname } => Some(cname),
+                _ => None,
+            });
+            match cname {
+                Some(cname) if record_type != RecordType::CNAME => {
+                    if answers.records.len() == MAX_CNAME_CHAIN {
+                        return Some(RewriteAnswers {
+                            response_code: ResponseCode::SERVFAIL.into(),
+                            records: vec![],
+                            unresolved_cname: None,
+                        });
+                    }
+                    answers.records.push(rewrite_record(
+                        &owner,
+                        ttl,
+                        ResourceRecordData::CNAME {
+                            cname: cname.clone(),
+                        },
+                    ));
+                    let Some(target_records) = self.find(cname) else {
+                        answers.unresolved_cname = Some(cname.clone());
+                        return Some(answers);
+                    };
+                    owner = cname.clone();
+                    records = target_records;
+                }
+                _ => {
+                    answers.records.extend(
+                        records
+                            .iter()
+                            .filter(|record| {
+                                record_type == RecordType::ANY
+                                    || record.record_type() == Some(record_type)
+                            })
+                            .map(|record| rewrite_record(&owner, ttl, record.clone())),
+                    );
+                    return Some(answers);
+                }
+            }
+        }
+    }
+}
+
+fn rewrite_record(owner: &str, ttl: u32, value: ResourceRecordData) -> ResourceRecord {
+    ResourceRecord::new(
+        ResourceRecordMeta {
+            name: owner.to_string(),
+            record_type: value.record_type().unwrap(),
+            class: CLASS_IN,
+            ttl,
+            len: 0,
+        },
+        value,
+    )
+}
+
+/// Groups the rewritten domains by the name that PTR queries for their IP use, so local hosts resolve in reverse too.
+/// Wildcard rewrites are skipped, since there is no single domain to answer with.
+pub(crate) fn reverse_rewrites(rewrites: &[DomainRewrite]) -> HashMap<String, Vec<String>> {
+    let mut reverse: HashMap<String, Vec<String>> = HashMap::new();
+    for rewrite in rewrites
+        .iter()
+        .filter(|rewrite| !rewrite.pattern.starts_with("*."))
+    {
+        for record in &rewrite.records {
+            let ip = match record {
+                ResourceRecordData::A { ipv4 } => IpAddr::V4(*ipv4),
+                ResourceRecordData::AAAA { ipv6 } => IpAddr::V6(*ipv6),
+                _ => continue,
+            };
+            let domains = reverse.entry(dns::reverse_name(ip)).or_default();
+            if !domains.contains(&rewrite.pattern) {
+                domains.push(rewrite.pattern.clone());
+            }
+        }
+    }
+    reverse
+}
+
 #[cfg(test)]
 mod test {
-    use std::{net::Ipv4Addr, str::FromStr};
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        str::FromStr,
+    };
 
-    use crate::domain_rewrite::{DomainRewrite, DomainRewriteError};
+    use dns::protocol::{
+        answer::ResourceRecordData, record_type::RecordType, response_code::ResponseCode,
+    };
+
+    use crate::domain_rewrite::{DomainRewrite, DomainRewriteError, Rewrites, reverse_rewrites};
+
+    fn rewrites(rewrites: &[&str]) -> Rewrites {
+        Rewrites::new(
+            &rewrites
+                .iter()
+                .map(|rewrite| DomainRewrite::from_str(rewrite).unwrap())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    /// The owner and value of the answered records, and the CNAME target left to resolve upstream
+    type Answers = (Vec<(String, ResourceRecordData)>, Option<String>);
+
+    fn answers(rewrites: &Rewrites, domain: &str, record_type: RecordType) -> Option<Answers> {
+        let answers = rewrites.answers(domain, record_type, 60)?;
+        Some((
+            answers
+                .records
+                .into_iter()
+                .map(|record| (record.meta.name, record.value))
+                .collect(),
+            answers.unresolved_cname,
+        ))
+    }
+
+    fn a(ip: [u8; 4]) -> ResourceRecordData {
+        ResourceRecordData::A { ipv4: ip.into() }
+    }
 
     #[test]
     fn test_domain_rewrite_parse() {
@@ -66,6 +393,10 @@ mod test {
             DomainRewrite::from_str("google.com8.8.8.8"),
             Err(DomainRewriteError::Format)
         );
+        assert_eq!(
+            DomainRewrite::from_str(":8.8.8.8"),
+            Err(DomainRewriteError::DomainMissing)
+        );
         assert_eq!(
             DomainRewrite::from_str("google.com:8.8.8"),
             Err(DomainRewriteError::IpInvalid("8.8.8".into()))
@@ -74,20 +405,236 @@ mod test {
             DomainRewrite::from_str("google.com:8.8.8.8.8"),
             Err(DomainRewriteError::IpInvalid("8.8.8.8.8".into()))
         );
+        assert_eq!(
+            DomainRewrite::from_str("google.com:8.8.8.8,nope"),
+            Err(DomainRewriteError::IpInvalid("nope".into()))
+        );
+        for pattern in ["*", "*.", "a.*.dev.lan", "**.dev.lan"] {
+            assert_eq!(
+                DomainRewrite::from_str(&format!("{pattern}:8.8.8.8")),
+                Err(DomainRewriteError::PatternInvalid(pattern.into()))
+            );
+        }
+        for record in ["MX mail.home", "CNAME a b", "TXT \"open", "TYPE99 \\# 2 01"] {
+            assert_eq!(
+                DomainRewrite::from_str(&format!("home:{record}")),
+                Err(DomainRewriteError::RecordInvalid(record.into()))
+            );
+        }
 
         assert_eq!(
             DomainRewrite::from_str("google:8.8.8.8"),
             Ok(DomainRewrite {
-                ip: Ipv4Addr::from_str("8.8.8.8").unwrap(),
-                domain: "google".to_string()
+                pattern: "google".to_string(),
+                records: vec![a([8, 8, 8, 8])],
+            })
+        );
+        assert_eq!(
+            DomainRewrite::from_str("Google.com.:8.8.8.8, 2001:4860:4860::8888"),
+            Ok(DomainRewrite {
+                pattern: "google.com".to_string(),
+                records: vec![
+                    a([8, 8, 8, 8]),
+                    ResourceRecordData::AAAA {
+                        ipv6: Ipv6Addr::from_str("2001:4860:4860::8888").unwrap()
+                    }
+                ],
+            })
+        );
+        assert_eq!(
+            DomainRewrite::from_str("*.dev.lan:cname proxy.lan."),
+            Ok(DomainRewrite {
+                pattern: "*.dev.lan".to_string(),
+                records: vec![ResourceRecordData::CNAME {
+                    cname: "proxy.lan".to_string()
+                }],
             })
         );
         assert_eq!(
-            DomainRewrite::from_str("google.com:8.8.8.8"),
+            DomainRewrite::from_str("home:MX 10 mail.home"),
             Ok(DomainRewrite {
-                ip: Ipv4Addr::from_str("8.8.8.8").unwrap(),
-                domain: "google.com".to_string()
+                pattern: "home".to_string(),
+                records: vec![ResourceRecordData::MX {
+                    preference: 10,
+                    exchange: "mail.home".to_string()
+                }],
             })
         );
+        assert_eq!(
+            DomainRewrite::from_str(r#"home:TXT "v=spf1 -all" "say \"hi\"" plain"#),
+            Ok(DomainRewrite {
+                pattern: "home".to_string(),
+                records: vec![ResourceRecordData::Raw {
+                    record_type: RecordType::TXT,
+                    data: b"\x0bv=spf1 -all\x08say \"hi\"\x05plain".to_vec()
+                }],
+            })
+        );
+        assert_eq!(
+            DomainRewrite::from_str(r"home:TYPE65534 \# 3 01 0aFF"),
+            Ok(DomainRewrite {
+                pattern: "home".to_string(),
+                records: vec![ResourceRecordData::Raw {
+                    record_type: RecordType::Unknown(65534),
+                    data: vec![0x01, 0x0a, 0xff]
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_rewrites() {
+        let rewrites = rewrites(&[
+            "NAS.home.:10.0.0.2",
+            "nas.home:10.0.0.3,fd00::3",
+            "printer.home:10.0.0.4",
+        ]);
+
+        assert_eq!(
+            answers(&rewrites, "NAS.home", RecordType::A),
+            Some((
+                vec![
+                    ("NAS.home".into(), a([10, 0, 0, 2])),
+                    ("NAS.home".into(), a([10, 0, 0, 3]))
+                ],
+                None
+            ))
+        );
+        assert_eq!(
+            answers(&rewrites, "nas.home", RecordType::AAAA),
+            Some((
+                vec![(
+                    "nas.home".into(),
+                    ResourceRecordData::AAAA {
+                        ipv6: Ipv6Addr::from_str("fd00::3").unwrap()
+                    }
+                )],
+                None
+            ))
+        );
+        // Other types of records do not exist
+        assert_eq!(
+            answers(&rewrites, "printer.home", RecordType::AAAA),
+            Some((vec![], None))
+        );
+        assert_eq!(answers(&rewrites, "scanner.home", RecordType::A), None);
+    }
+
+    #[test]
+    fn test_rewrites_wildcards() {
+        let rewrites = rewrites(&[
+            "*.lan:10.0.0.1",
+            "*.dev.lan:10.0.0.2",
+            "api.dev.lan:10.0.0.3",
+        ]);
+
+        for (domain, ip) in [
+            ("api.dev.lan", [10, 0, 0, 3]),
+            ("web.dev.lan", [10, 0, 0, 2]),
+            ("v1.api.dev.lan", [10, 0, 0, 2]),
+            ("dev.lan", [10, 0, 0, 1]),
+            ("nas.lan", [10, 0, 0, 1]),
+        ] {
+            assert_eq!(rewrites.find(domain), Some([a(ip)].as_slice()), "{domain}");
+        }
+        // A wildcard does not match the domain itself
+        assert_eq!(rewrites.find("lan"), None);
+        assert_eq!(rewrites.find("lan.home"), None);
+    }
+
+    #[test]
+    fn test_rewrites_cnames() {
+        let rewrites = rewrites(&[
+            "www.home:CNAME web.home",
+            "web.home:CNAME nas.home",
+            "nas.home:10.0.0.2",
+            "*.dev.lan:CNAME proxy.example.com",
+            "loop.home:CNAME loop.home",
+        ]);
+
+        let cname = |cname: &str| ResourceRecordData::CNAME {
+            cname: cname.into(),
+        };
+        assert_eq!(
+            answers(&rewrites, "www.home", RecordType::A),
+            Some((
+                vec![
+                    ("www.home".into(), cname("web.home")),
+                    ("web.home".into(), cname("nas.home")),
+                    ("nas.home".into(), a([10, 0, 0, 2]))
+                ],
+                None
+            ))
+        );
+        assert_eq!(
+            answers(&rewrites, "www.home", RecordType::CNAME),
+            Some((vec![("www.home".into(), cname("web.home"))], None))
+        );
+        assert_eq!(
+            answers(&rewrites, "api.dev.lan", RecordType::AAAA),
+            Some((
+                vec![("api.dev.lan".into(), cname("proxy.example.com"))],
+                Some("proxy.example.com".into())
+            ))
+        );
+
+        // A loop fails instead of being answered with the CNAME records up to the limit
+        let answers = rewrites.answers("loop.home", RecordType::A, 60).unwrap();
+        assert_eq!(answers.response_code, u8::from(ResponseCode::SERVFAIL));
+        assert!(answers.records.is_empty());
+        assert_eq!(answers.unresolved_cname, None);
+
+        // A chain of exactly `MAX_CNAME_CHAIN` CNAME records is answered
+        let chain = (1..=8)
+            .map(|i| format!("{i}.chain:CNAME {}.chain", i + 1))
+            .chain(["9.chain:10.0.0.9".to_string()])
+            .collect::<Vec<_>>();
+        let chain = self::rewrites(&chain.iter().map(String::as_str).collect::<Vec<_>>());
+        let answers = chain.answers("1.chain", RecordType::A, 60).unwrap();
+        assert_eq!(answers.response_code, u8::from(ResponseCode::NOERROR));
+        assert_eq!(answers.records.len(), 9);
+    }
+
+    #[test]
+    fn test_rewrites_cname_conflicts() {
+        for conflict in [
+            ["www.home:CNAME nas.home", "www.home:10.0.0.2"],
+            ["www.home:10.0.0.2", "www.home:CNAME nas.home"],
+            ["www.home:CNAME nas.home", "WWW.home.:CNAME web.home"],
+            ["*.dev.lan:CNAME proxy.lan", "*.dev.lan:TXT hello"],
+        ] {
+            let conflict = conflict.map(|rewrite| DomainRewrite::from_str(rewrite).unwrap());
+            assert_eq!(
+                Rewrites::new(&conflict).err(),
+                Some(DomainRewriteError::CnameConflict(
+                    conflict[0].pattern.clone()
+                ))
+            );
+        }
+
+        // Repeating the same CNAME rewrite, or rewriting a wildcard's subdomains differently, is fine
+        rewrites(&["www.home:CNAME nas.home", "www.home:CNAME nas.home"]);
+        rewrites(&["*.dev.lan:CNAME proxy.lan", "api.dev.lan:10.0.0.3"]);
+    }
+
+    #[test]
+    fn test_reverse_rewrites() {
+        let rewrites = [
+            "nas.home:10.0.0.2",
+            "printer.home:10.0.0.3,fd00::3",
+            "files.home:10.0.0.2",
+            "*.dev.lan:10.0.0.4",
+            "www.home:CNAME nas.home",
+        ]
+        .map(|rewrite| DomainRewrite::from_str(rewrite).unwrap());
+        let reverse = reverse_rewrites(&rewrites);
+        assert_eq!(reverse.len(), 3);
+        assert_eq!(reverse["2.0.0.10.in-addr.arpa"], ["nas.home", "files.home"]);
+        assert_eq!(reverse["3.0.0.10.in-addr.arpa"], ["printer.home"]);
+        assert_eq!(
+            reverse[&dns::reverse_name(Ipv6Addr::from_str("fd00::3").unwrap().into())],
+            ["printer.home"]
+        );
+        assert!(!reverse.contains_key(&dns::reverse_name(Ipv4Addr::new(10, 0, 0, 4).into())));
     }
 }
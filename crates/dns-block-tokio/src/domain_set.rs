@@ -0,0 +1,221 @@
+use std::{
+    fmt::Debug,
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use fst::{Map, MapBuilder, Streamer, raw::Output};
+use memmap2::Mmap;
+
+/// The flag of a domain that is part of the set itself
+const DOMAIN: u64 = 1;
+/// The flag of a domain whose subdomains are part of the set
+const SUBDOMAINS: u64 = 2;
+
+/// The bytes of a `DomainSet`, either built in memory or mapped from a file
+enum Bytes {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Bytes::Owned(bytes) => bytes,
+            Bytes::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+/// A set of domains, and of parent domains whose subdomains are part of the set, which are lowercase since domains are
+/// case-insensitive.
+///
+/// The domains are kept reversed in a finite state transducer, see https://blog.burntsushi.net/transducers/, whose
+/// values are the `DOMAIN` and `SUBDOMAINS` flags. Since reversed domains share the prefixes of their parent domains,
+/// the set takes a fraction of the memory of the domains as `String`s, and a lookup of a domain and all its parent
+/// domains is a single walk through the transducer. Inserted domains are only looked up once they are committed.
+pub(crate) struct DomainSet {
+    map: Map<Bytes>,
+    /// The reversed domains and flags inserted since the last commit
+    pending: Vec<(Vec<u8>, u64)>,
+}
+
+impl Default for DomainSet {
+    fn default() -> Self {
+        Self {
+            map: Map::new(Bytes::Owned(MapBuilder::memory().into_inner().unwrap())).unwrap(),
+            pending: vec![],
+        }
+    }
+}
+
+impl Debug for DomainSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DomainSet")
+            .field("len", &self.len())
+            .field("bytes", &self.size())
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl DomainSet {
+    /// Maps a set that `persist` wrote to `path`
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: `persist` never changes a written file, but replaces it by renaming a new one, so the mapped file
+        // stays the same as long as no other process writes to it
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self {
+            map: Map::new(Bytes::Mapped(mmap))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            pending: vec![],
+        })
+    }
+
+    pub fn insert_domain(&mut self, domain: &str) {
+        self.pending.push((reversed(domain), DOMAIN));
+    }
+
+    pub fn insert_subdomains(&mut self, domain: &str) {
+        self.pending.push((reversed(domain), SUBDOMAINS));
+    }
+
+    /// Merges the inserted domains into the set, which builds it anew
+    pub fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.sort_unstable();
+        pending.dedup_by(|next, kept| {
+            let duplicate = next.0 == kept.0;
+            if duplicate {
+                kept.1 |= next.1;
+            }
+            duplicate
+        });
+        let pending = Map::from_iter(pending).expect("sorted without duplicates");
+
+        let mut builder = MapBuilder::memory();
+        let mut union = self.map.op().add(&pending).union();
+        while let Some((domain, flags)) = union.next() {
+            let flags = flags.iter().fold(0, |all, flags| all | flags.value);
+            builder
+                .insert(domain, flags)
+                .expect("sorted without duplicates");
+        }
+        drop(union);
+        self.map = Map::new(Bytes::Owned(builder.into_inner().unwrap())).unwrap();
+    }
+
+    /// Writes the set to `path` and maps it from there, so it takes no memory of its own but the pages of the file
+    /// that lookups hit
+    pub fn persist(&mut self, path: &Path) -> io::Result<()> {
+        self.commit();
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, self.map.as_fst().as_bytes())?;
+        fs::rename(&temporary, path)?;
+        *self = Self::open(path)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// The number of bytes the set takes in memory or on disk
+    pub fn size(&self) -> usize {
+        self.map.as_fst().size()
+    }
+
+    /// Whether `domain` is part of the set itself, or as a subdomain of one of its parent domains
+    pub fn contains(&self, domain: &str) -> bool {
+        let fst = self.map.as_fst();
+        let mut node = fst.root();
+        let mut output = Output::zero();
+        for byte in domain.trim_end_matches('.').bytes().rev() {
+            let byte = byte.to_ascii_lowercase();
+            // The domain up to here is a parent domain of `domain`
+            if byte == b'.'
+                && node.is_final()
+                && output.cat(node.final_output()).value() & SUBDOMAINS != 0
+            {
+                return true;
+            }
+            let Some(index) = node.find_input(byte) else {
+                return false;
+            };
+            let transition = node.transition(index);
+            output = output.cat(transition.out);
+            node = fst.node(transition.addr);
+        }
+        node.is_final() && output.cat(node.final_output()).value() & DOMAIN != 0
+    }
+}
+
+/// The lowercase domain in reverse, like `moc.elpmaxe.sda` for `ads.example.com`, so parent domains come first
+fn reversed(domain: &str) -> Vec<u8> {
+    domain
+        .bytes()
+        .rev()
+        .map(|byte| byte.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::domain_set::DomainSet;
+
+    #[test]
+    fn test_domain_set() {
+        let mut domains = DomainSet::default();
+        domains.insert_domain("tracker.com");
+        domains.insert_domain("Example.org");
+        domains.insert_subdomains("example.org");
+        domains.insert_subdomains("ads.net");
+        domains.commit();
+        assert_eq!(domains.len(), 3);
+
+        assert!(domains.contains("tracker.com"));
+        assert!(domains.contains("TRACKER.com."));
+        assert!(!domains.contains("ads.tracker.com"));
+        assert!(!domains.contains("nottracker.com"));
+
+        assert!(domains.contains("example.org"));
+        assert!(domains.contains("a.b.example.org"));
+        assert!(!domains.contains("org"));
+
+        assert!(domains.contains("banner.ads.net"));
+        assert!(!domains.contains("ads.net"));
+        assert!(!domains.contains("badads.net"));
+
+        // Later commits add to the set, and domains are counted once even if their subdomains are part of it too
+        domains.insert_domain("ads.net");
+        domains.insert_subdomains("tracker.com");
+        assert!(!domains.contains("ads.net"));
+        domains.commit();
+        assert_eq!(domains.len(), 3);
+        assert!(domains.contains("ads.net"));
+        assert!(domains.contains("pixel.tracker.com"));
+        assert!(domains.contains("example.org"));
+    }
+
+    #[test]
+    fn test_domain_set_persist() {
+        let path = std::env::temp_dir().join(format!("dns-block-set-{}.fst", std::process::id()));
+        let mut domains = DomainSet::default();
+        domains.insert_domain("tracker.com");
+        domains.insert_subdomains("ads.net");
+        domains.persist(&path).unwrap();
+
+        for domains in [domains, DomainSet::open(&path).unwrap()] {
+            assert_eq!(domains.len(), 2);
+            assert!(domains.contains("tracker.com"));
+            assert!(domains.contains("banner.ads.net"));
+            assert!(!domains.contains("ads.net"));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1007 @@
+use std::{
+    collections::HashMap, error::Error, fmt::Display, io, net::IpAddr, path::Path, str::FromStr,
+};
+
+use dns::protocol::record_type::RecordType;
+use regex::{RegexBuilder, RegexSet, RegexSetBuilder};
+
+use crate::{domain_set::DomainSet, list_format::ListFormat};
+
+/// Whether a listed domain without a pattern also covers its subdomains. Entries like `*.example.com` always cover all
+/// subdomains of `example.com`, but not `example.com` itself, and `||example.com^` covers both.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum BlockMode {
+    /// Only the listed domains are blocked or allowed
+    Exact,
+    /// The listed domains and all of their subdomains are blocked or allowed
+    Subdomains,
+}
+
+/// The upper bound of the size of all compiled regular expressions, which is much higher than the default of the
+/// `regex` crate, since lists may contain many of them
+const REGEX_SIZE_LIMIT: usize = 256 * (1 << 20);
+
+/// A rule of a list that is skipped, because it is invalid, or because blocking with only part of its meaning could
+/// block the wrong queries
+#[derive(Debug, PartialEq)]
+pub(crate) struct RejectedRule {
+    /// The list, like its URL
+    pub source: String,
+    /// The line number within the list, if the rule was rejected on its own
+    pub line: Option<usize>,
+    pub rule: String,
+    pub reason: String,
+}
+
+impl Display for RejectedRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => f.write_fmt(format_args!(
+                "filter: {}:{line}: rejected rule '{}': {}",
+                self.source, self.rule, self.reason
+            )),
+            None => f.write_fmt(format_args!(
+                "filter: {}: rejected {}: {}",
+                self.source, self.rule, self.reason
+            )),
+        }
+    }
+}
+
+impl Error for RejectedRule {}
+
+/// The domains a rule matches
+#[derive(Debug, Clone, PartialEq)]
+enum Pattern {
+    /// `*`, which is only accepted with modifiers that restrict the queries it applies to
+    Any,
+    /// `|example.com^`, or `example.com` with `BlockMode::Exact`
+    Domain(String),
+    /// `||example.com^`, or `example.com` with `BlockMode::Subdomains`
+    DomainAndSubdomains(String),
+    /// `*.example.com`
+    Subdomains(String),
+    /// `/^ads?[0-9]*\./`, or a domain with `*` wildcards like `track*.example.*`, which is turned into a regular
+    /// expression where `*` matches any characters, including dots
+    Regex(String),
+}
+
+impl Pattern {
+    fn domain(&self) -> Option<&str> {
+        match self {
+            Pattern::Any | Pattern::Regex(_) => None,
+            Pattern::Domain(domain)
+            | Pattern::DomainAndSubdomains(domain)
+            | Pattern::Subdomains(domain) => Some(domain),
+        }
+    }
+
+    /// Whether the pattern matches a query for its own domain, or for a subdomain of it if `subdomain` is set
+    fn matches(&self, subdomain: bool) -> bool {
+        match self {
+            Pattern::Any | Pattern::DomainAndSubdomains(_) | Pattern::Regex(_) => true,
+            Pattern::Domain(_) => !subdomain,
+            Pattern::Subdomains(_) => subdomain,
+        }
+    }
+}
+
+/// A subnet like `192.168.0.0/16`, or a single IP
+#[derive(Debug, Clone, PartialEq)]
+struct Subnet {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix_len) = s.split_once('/').unwrap_or((s, ""));
+        let network = IpAddr::from_str(network).map_err(|_| ())?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            "" => max_len,
+            prefix_len => prefix_len.parse().map_err(|_| ())?,
+        };
+        if prefix_len > max_len {
+            return Err(());
+        }
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+/// The values of a modifier like `$dnstype=A|~AAAA`, where values with `~` exclude and all others include
+#[derive(Debug, Clone, PartialEq)]
+struct Restriction<T> {
+    include: Vec<T>,
+    exclude: Vec<T>,
+}
+
+impl<T> Default for Restriction<T> {
+    fn default() -> Self {
+        Self {
+            include: vec![],
+            exclude: vec![],
+        }
+    }
+}
+
+impl<T> Restriction<T> {
+    fn parse(values: &str, parse: impl Fn(&str) -> Option<T>) -> Option<Self> {
+        let mut restriction = Self::default();
+        for value in values.split('|') {
+            match value.strip_prefix('~') {
+                Some(value) => restriction.exclude.push(parse(value)?),
+                None => restriction.include.push(parse(value)?),
+            }
+        }
+        Some(restriction)
+    }
+
+    /// Whether the restriction allows a value, given a check of whether the value matches a restriction's value
+    fn allows(&self, matches: impl Fn(&T) -> bool) -> bool {
+        (self.include.is_empty() || self.include.iter().any(&matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// A rule in the adblock-style filter syntax of AdGuard DNS, see
+/// https://adguard-dns.io/kb/general/dns-filtering-syntax/#adblock-style-syntax
+#[derive(Debug, Clone, PartialEq)]
+struct FilterRule {
+    pattern: Pattern,
+    /// `@@`, which unblocks the matched domains
+    exception: bool,
+    /// `$important`, which takes precedence over rules without it
+    important: bool,
+    /// `$client=`, the clients the rule applies to
+    clients: Restriction<Subnet>,
+    /// `$dnstype=`, the record types of the queries the rule applies to
+    record_types: Restriction<RecordType>,
+    /// `$denyallow=`, domains that the rule does not apply to, along with their subdomains
+    denyallow: Vec<String>,
+}
+
+impl FilterRule {
+    fn has_modifiers(&self) -> bool {
+        self.important || self.is_restricted()
+    }
+
+    /// Whether modifiers restrict the queries the rule applies to, beyond those of its pattern
+    fn is_restricted(&self) -> bool {
+        self.clients != Restriction::default()
+            || self.record_types != Restriction::default()
+            || !self.denyallow.is_empty()
+    }
+
+    /// Whether the rule only consists of a pattern, so it can be looked up in a `DomainSet`
+    fn is_plain(&self) -> bool {
+        !self.exception && !self.has_modifiers()
+    }
+
+    /// Whether the rule applies to a query, whose domain is already known to match the rule's pattern
+    fn applies(&self, domain: &str, record_type: RecordType, client: IpAddr) -> bool {
+        self.clients.allows(|subnet| subnet.contains(client))
+            && self.record_types.allows(|&allowed| allowed == record_type)
+            && !self
+                .denyallow
+                .iter()
+                .any(|allowed| is_same_or_subdomain(domain, allowed))
+    }
+}
+
+fn is_same_or_subdomain(domain: &str, parent: &str) -> bool {
+    domain == parent
+        || domain
+            .strip_suffix(parent)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+pub(crate) fn is_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Whether a domain with `*` wildcards has a label without any, since patterns like `*.*` match almost all domains
+pub(crate) fn has_literal_label(pattern: &str) -> bool {
+    pattern
+        .split('.')
+        .any(|label| !label.is_empty() && !label.contains('*'))
+}
+
+/// Whether a line of a filter list carries no rule
+pub(crate) fn is_comment(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty()
+        || line.starts_with('!')
+        || line.starts_with('[')
+        || (line.starts_with('#') && !is_cosmetic(line))
+}
+
+/// Element hiding and other cosmetic rules only apply to web pages, see
+/// https://adguard.com/kb/general/ad-filtering/create-own-filters/#cosmetic-rules. Comments like `## Section` are no
+/// cosmetic rules, since a selector follows the separator right away.
+fn is_cosmetic(line: &str) -> bool {
+    ["##", "#@#", "#?#", "#$#", "#%#"].iter().any(|separator| {
+        line.split_once(separator).is_some_and(|(_, selector)| {
+            selector.starts_with(|c: char| !c.is_whitespace() && c != '#')
+        })
+    })
+}
+
+/// Parses the pattern of a rule, which may only be empty if the rule has modifiers
+fn parse_pattern(pattern: &str, has_modifiers: bool, mode: BlockMode) -> Result<Pattern, String> {
+    let pattern = pattern.to_ascii_lowercase();
+    let pattern = if let Some(domain) = pattern.strip_prefix("||") {
+        let domain = domain.strip_suffix('^').unwrap_or(domain);
+        if domain == "*" {
+            Pattern::Any
+        } else {
+            Pattern::DomainAndSubdomains(domain.to_string())
+        }
+    } else if let Some(domain) = pattern.strip_prefix('|') {
+        let domain = domain.strip_suffix('|').unwrap_or(domain);
+        Pattern::Domain(domain.strip_suffix('^').unwrap_or(domain).to_string())
+    } else if pattern == "*" || (pattern.is_empty() && has_modifiers) {
+        Pattern::Any
+    } else if let Some(domain) = pattern.strip_prefix("*.") {
+        Pattern::Subdomains(domain.to_string())
+    } else {
+        let domain = pattern.strip_suffix('^').unwrap_or(&pattern);
+        let domain = domain.strip_suffix('.').unwrap_or(domain).to_string();
+        match mode {
+            BlockMode::Exact => Pattern::Domain(domain),
+            BlockMode::Subdomains => Pattern::DomainAndSubdomains(domain),
+        }
+    };
+
+    match pattern.domain() {
+        Some(domain) if domain.contains('*') && !has_literal_label(domain) => {
+            Err("wildcards need a label without '*', or they match almost all domains".into())
+        }
+        Some(domain) if domain.contains('*') && is_domain(&domain.replace('*', "a")) => {
+            let glob = domain
+                .split('*')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(".*");
+            Ok(Pattern::Regex(match pattern {
+                Pattern::DomainAndSubdomains(_) => format!("(?:^|\\.){glob}$"),
+                Pattern::Subdomains(_) => format!("\\.{glob}$"),
+                _ => format!("^{glob}$"),
+            }))
+        }
+        Some(domain) if !is_domain(domain) => Err(
+            "patterns other than domains, wildcards and regular expressions are not supported"
+                .into(),
+        ),
+        // One stray `*` line in a list would otherwise block all queries
+        None if pattern == Pattern::Any && !has_modifiers => Err(
+            "rules for all domains need modifiers that restrict the queries they apply to".into(),
+        ),
+        _ => Ok(pattern),
+    }
+}
+
+/// Parses a line of a filter list, which is `None` for comments, or returns why the rule is rejected
+fn parse_rule(line: &str, mode: BlockMode) -> Result<Option<FilterRule>, String> {
+    let line = line.trim();
+    if is_comment(line) {
+        return Ok(None);
+    }
+    if is_cosmetic(line) {
+        return Err("cosmetic rules only apply to web pages".into());
+    }
+
+    let (exception, rule) = match line.strip_prefix("@@") {
+        Some(rule) => (true, rule),
+        None => (false, line),
+    };
+    let (pattern, modifiers) = match rule.strip_prefix('/') {
+        Some(regex) => {
+            let (regex, modifiers) = match regex.rsplit_once("/$") {
+                Some(split) => split,
+                None => (
+                    regex
+                        .strip_suffix('/')
+                        .ok_or("regular expressions have to end with '/'")?,
+                    "",
+                ),
+            };
+            RegexBuilder::new(regex)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| {
+                    // Syntax errors span several lines that point at the error, which ends with its description
+                    let e = e.to_string();
+                    let message = e.lines().last().unwrap_or_default();
+                    let message = message.strip_prefix("error: ").unwrap_or(message);
+                    format!("invalid regular expression: {message}")
+                })?;
+            (Pattern::Regex(regex.to_string()), modifiers)
+        }
+        None => {
+            let (pattern, modifiers) = rule.split_once('$').unwrap_or((rule, ""));
+            (
+                parse_pattern(pattern, !modifiers.is_empty(), mode)?,
+                modifiers,
+            )
+        }
+    };
+
+    let mut rule = FilterRule {
+        pattern,
+        exception,
+        important: false,
+        clients: Restriction::default(),
+        record_types: Restriction::default(),
+        denyallow: vec![],
+    };
+    for modifier in modifiers.split(',').filter(|modifier| !modifier.is_empty()) {
+        let (name, values) = modifier.split_once('=').unwrap_or((modifier, ""));
+        match name {
+            "important" if values.is_empty() => rule.important = true,
+            "client" => {
+                rule.clients = Restriction::parse(values, |client| {
+                    client.trim_matches(['\'', '"']).parse().ok()
+                })
+                .ok_or("only client IPs and subnets are supported")?
+            }
+            "dnstype" => {
+                rule.record_types =
+                    Restriction::parse(values, |record_type| RecordType::from_str(record_type).ok())
+                        .ok_or("unknown record type")?
+            }
+            "denyallow" => {
+                rule.denyallow = values
+                    .split('|')
+                    .map(|domain| domain.to_ascii_lowercase())
+                    .collect();
+                if !rule.denyallow.iter().all(|domain| is_domain(domain)) {
+                    return Err("denyallow only supports domains".into());
+                }
+            }
+            _ => {
+                return Err(format!("modifier ${name} is not supported"));
+            }
+        }
+    }
+    // `$important` alone does not restrict a rule for all domains
+    if rule.pattern == Pattern::Any && !rule.is_restricted() {
+        return Err(
+            "rules for all domains need modifiers that restrict the queries they apply to".into(),
+        );
+    }
+    Ok(Some(rule))
+}
+
+/// What a `Filter` decided for a query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Verdict {
+    /// No blocking rule applies
+    Unmatched,
+    Blocked,
+    /// A blocking rule applies, but so does an exception rule that takes precedence
+    Excepted,
+    /// A blocking rule applies, but the domain is allowlisted
+    Allowlisted,
+}
+
+/// Decides whether queries are blocked by blocklist entries and adblock-style filter rules.
+///
+/// Allowlisted domains are never blocked. Otherwise, exceptions win over blocking rules, unless the blocking rule is
+/// `$important` and the exception is not. Rules without exceptions and modifiers, which make up almost all of common
+/// lists, are kept in a `DomainSet`, while the other rules are kept by the domain of their pattern, so matching a query
+/// takes one lookup per label of its domain. The patterns of all regular expression and glob rules are compiled into
+/// one `RegexSet`, which matches them in a single pass over the domain.
+#[derive(Debug, Default)]
+pub(crate) struct Filter {
+    blocklist: DomainSet,
+    allowlist: DomainSet,
+    rules: HashMap<String, Vec<FilterRule>>,
+    /// The rules for all domains, which only apply to some queries due to their modifiers
+    any_domain_rules: Vec<FilterRule>,
+    /// The rules with a `Pattern::Regex`, in the order of the patterns in `regex_set`
+    regex_rules: Vec<FilterRule>,
+    regex_set: RegexSet,
+}
+
+/// How many rules of a list were added to a `Filter`, and which were rejected
+#[derive(Debug, Default)]
+pub(crate) struct ListReport {
+    pub accepted: usize,
+    pub rejected: Vec<RejectedRule>,
+}
+
+impl Filter {
+    /// Adds the rules of a list in `format` from `source`, where domains without a pattern are blocked according to
+    /// `mode`
+    pub fn add_list<'a>(
+        &mut self,
+        source: &str,
+        lines: impl IntoIterator<Item = &'a str>,
+        format: ListFormat,
+        mode: BlockMode,
+    ) -> ListReport {
+        let regex_rules = self.regex_rules.len();
+        let mut report = parse_list(source, lines, format, mode, |rule| {
+            self.insert(rule);
+            Ok(())
+        });
+        self.blocklist.commit();
+        if self.regex_rules.len() == regex_rules {
+            return report;
+        }
+
+        match self.compile_regex_set() {
+            Ok(regex_set) => self.regex_set = regex_set,
+            Err(e) => {
+                // Only the regular expressions of this list are dropped, the others compiled before
+                let added = self.regex_rules.len() - regex_rules;
+                self.regex_rules.truncate(regex_rules);
+                report.accepted -= added;
+                report.rejected.push(RejectedRule {
+                    source: source.to_string(),
+                    line: None,
+                    rule: format!("{added} regular expressions"),
+                    reason: e.to_string(),
+                });
+            }
+        }
+        report
+    }
+
+    fn compile_regex_set(&self) -> Result<RegexSet, regex::Error> {
+        RegexSetBuilder::new(
+            self.regex_rules
+                .iter()
+                .filter_map(|rule| match &rule.pattern {
+                    Pattern::Regex(regex) => Some(regex),
+                    _ => None,
+                }),
+        )
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+    }
+
+    /// Adds the domains of an allowlist in `format`, which no rule can block. Rules with modifiers are rejected, since
+    /// exception rules are there to unblock a domain only for some queries.
+    pub fn add_allowlist<'a>(
+        &mut self,
+        source: &str,
+        lines: impl IntoIterator<Item = &'a str>,
+        format: ListFormat,
+        mode: BlockMode,
+    ) -> ListReport {
+        let report = parse_list(source, lines, format, mode, |rule| {
+            if rule.has_modifiers() {
+                return Err("allowlists only support domains and wildcards");
+            }
+            match &rule.pattern {
+                Pattern::Domain(domain) => self.allowlist.insert_domain(domain),
+                Pattern::DomainAndSubdomains(domain) => {
+                    self.allowlist.insert_domain(domain);
+                    self.allowlist.insert_subdomains(domain);
+                }
+                Pattern::Subdomains(domain) => self.allowlist.insert_subdomains(domain),
+                Pattern::Any | Pattern::Regex(_) => {
+                    return Err("allowlists only support domains and wildcards");
+                }
+            }
+            Ok(())
+        });
+        self.allowlist.commit();
+        report
+    }
+
+    fn insert(&mut self, rule: FilterRule) {
+        if rule.is_plain() {
+            match &rule.pattern {
+                Pattern::Domain(domain) => return self.blocklist.insert_domain(domain),
+                Pattern::DomainAndSubdomains(domain) => {
+                    self.blocklist.insert_domain(domain);
+                    return self.blocklist.insert_subdomains(domain);
+                }
+                Pattern::Subdomains(domain) => return self.blocklist.insert_subdomains(domain),
+                Pattern::Any | Pattern::Regex(_) => {}
+            }
+        }
+        if let Pattern::Regex(_) = rule.pattern {
+            return self.regex_rules.push(rule);
+        }
+        match rule.pattern.domain() {
+            Some(domain) => self.rules.entry(domain.to_string()).or_default().push(rule),
+            None => self.any_domain_rules.push(rule),
+        }
+    }
+
+    /// The number of blocked domains and other rules
+    pub fn len(&self) -> usize {
+        self.blocklist.len()
+            + self.rules.values().map(Vec::len).sum::<usize>()
+            + self.any_domain_rules.len()
+            + self.regex_rules.len()
+    }
+
+    /// Writes the blocked domains to `path` and maps them from there, see `DomainSet::persist`
+    pub fn persist_blocklist(&mut self, path: &Path) -> io::Result<()> {
+        self.blocklist.persist(path)
+    }
+
+    /// The number of allowlisted domains
+    pub fn allowlist_len(&self) -> usize {
+        self.allowlist.len()
+    }
+
+    pub fn verdict(&self, domain: &str, record_type: RecordType, client: IpAddr) -> Verdict {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let mut blocked = self.blocklist.contains(&domain);
+        let mut important_blocked = false;
+        let mut allowed = false;
+        let mut important_allowed = false;
+
+        let mut apply = |rule: &FilterRule| match (rule.exception, rule.important) {
+            (false, false) => blocked = true,
+            (false, true) => important_blocked = true,
+            (true, false) => allowed = true,
+            (true, true) => important_allowed = true,
+        };
+
+        for rule in &self.any_domain_rules {
+            if rule.applies(&domain, record_type, client) {
+                apply(rule);
+            }
+        }
+        let mut parent = Some(domain.as_str());
+        while let Some(key) = parent {
+            for rule in self.rules.get(key).into_iter().flatten() {
+                if rule.pattern.matches(key != domain) && rule.applies(&domain, record_type, client)
+                {
+                    apply(rule);
+                }
+            }
+            parent = key.split_once('.').map(|(_, rest)| rest);
+        }
+        if !self.regex_rules.is_empty() {
+            for index in self.regex_set.matches(&domain) {
+                let rule = &self.regex_rules[index];
+                if rule.applies(&domain, record_type, client) {
+                    apply(rule);
+                }
+            }
+        }
+
+        if !blocked && !important_blocked {
+            Verdict::Unmatched
+        } else if self.allowlist.contains(&domain) {
+            Verdict::Allowlisted
+        } else if important_allowed || (!important_blocked && allowed) {
+            Verdict::Excepted
+        } else {
+            Verdict::Blocked
+        }
+    }
+}
+
+/// Parses the lines of a list in `format` from `source` and hands each rule to `insert`, which may reject it
+fn parse_list<'a>(
+    source: &str,
+    lines: impl IntoIterator<Item = &'a str>,
+    format: ListFormat,
+    mode: BlockMode,
+    mut insert: impl FnMut(FilterRule) -> Result<(), &'static str>,
+) -> ListReport {
+    let mut report = ListReport::default();
+    for (number, line) in lines.into_iter().enumerate() {
+        let reject = |rule: &str, reason: String| RejectedRule {
+            source: source.to_string(),
+            line: Some(number + 1),
+            rule: rule.trim().to_string(),
+            reason,
+        };
+        let rules = match format.rules(line) {
+            Ok(rules) => rules,
+            Err(reason) => {
+                report.rejected.push(reject(line, reason.into()));
+                continue;
+            }
+        };
+        for rule in rules {
+            match parse_rule(&rule, mode) {
+                Ok(Some(parsed)) => match insert(parsed) {
+                    Ok(()) => report.accepted += 1,
+                    Err(reason) => report.rejected.push(reject(&rule, reason.into())),
+                },
+                Ok(None) => {}
+                Err(reason) => report.rejected.push(reject(&rule, reason)),
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use dns::protocol::record_type::RecordType;
+
+    use crate::{
+        filter::{BlockMode, Filter, Pattern, Verdict, is_comment, parse_rule},
+        list_format::ListFormat,
+    };
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 2));
+
+    fn filter(lines: &[&str]) -> Filter {
+        let mut filter = Filter::default();
+        let report = filter.add_list(
+            "test",
+            lines.iter().copied(),
+            ListFormat::Adblock,
+            BlockMode::Exact,
+        );
+        assert_eq!(report.rejected, []);
+        assert_eq!(report.accepted, lines.len());
+        filter
+    }
+
+    fn unsupported(line: &str) -> Option<String> {
+        parse_rule(line, BlockMode::Exact).err()
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        for comment in [
+            "",
+            "! Title: list",
+            "# hosts comment",
+            "## Section",
+            "[Adblock Plus 2.0]",
+        ] {
+            assert!(is_comment(comment), "{comment}");
+            assert_eq!(parse_rule(comment, BlockMode::Exact), Ok(None));
+        }
+
+        let pattern = |line, mode| parse_rule(line, mode).unwrap().unwrap().pattern;
+        assert_eq!(
+            pattern("||Ads.example.com^", BlockMode::Exact),
+            Pattern::DomainAndSubdomains("ads.example.com".into())
+        );
+        assert_eq!(
+            pattern("|ads.example.com^|", BlockMode::Subdomains),
+            Pattern::Domain("ads.example.com".into())
+        );
+        assert_eq!(
+            pattern("*.example.com", BlockMode::Exact),
+            Pattern::Subdomains("example.com".into())
+        );
+        assert_eq!(
+            pattern("example.com", BlockMode::Exact),
+            Pattern::Domain("example.com".into())
+        );
+        assert_eq!(
+            pattern("example.com^", BlockMode::Subdomains),
+            Pattern::DomainAndSubdomains("example.com".into())
+        );
+        assert_eq!(pattern("*$denyallow=com", BlockMode::Exact), Pattern::Any);
+        assert_eq!(pattern("$client=10.0.0.1", BlockMode::Exact), Pattern::Any);
+        assert_eq!(
+            pattern("/^ads?\\./$important", BlockMode::Exact),
+            Pattern::Regex("^ads?\\.".into())
+        );
+        assert_eq!(
+            pattern("||ex*ample.com^", BlockMode::Exact),
+            Pattern::Regex("(?:^|\\.)ex.*ample\\.com$".into())
+        );
+        assert_eq!(
+            pattern("*.track*.net", BlockMode::Exact),
+            Pattern::Regex("\\.track.*\\.net$".into())
+        );
+
+        for line in [
+            "example.com##.banner",
+            "##.banner",
+            "||example.com/ads^",
+            "/ads[0-9+\\.example\\.com/",
+            "/ads\\.example\\.com",
+            "0.0.0.0 example.com",
+            "||example.com^$badfilter",
+            "||example.com^$dnsrewrite=1.2.3.4",
+            "||example.com^$client='Frank\\'s laptop'",
+            "||example.com^$dnstype=NOPE",
+            "*$denyallow=~com",
+            "*.*",
+            "||*.*^",
+            "|*.*^",
+        ] {
+            assert!(unsupported(line).is_some(), "{line}");
+        }
+        // Rules for all domains would block every query
+        for line in ["*", "||*^", "||*", "*$important", "@@*"] {
+            assert_eq!(
+                unsupported(line),
+                Some(
+                    "rules for all domains need modifiers that restrict the queries they apply to"
+                        .into()
+                ),
+                "{line}"
+            );
+        }
+        assert_eq!(
+            unsupported("||example.com^$ctag=child"),
+            Some("modifier $ctag is not supported".into())
+        );
+    }
+
+    #[test]
+    fn test_filter_regexes() {
+        let filter = filter(&[
+            r"/^ad[s]?[0-9]*\./",
+            "track*.example.*",
+            "||cdn*.net^",
+            r"/^metrics\./$dnstype=AAAA",
+            r"@@/^ads\.example\.org$/",
+        ]);
+        assert_eq!(filter.len(), 5);
+
+        let verdict = |domain, record_type| filter.verdict(domain, record_type, CLIENT);
+        for domain in [
+            "ad.example.com",
+            "ADS42.example.com",
+            "tracker.example.co.uk",
+            "track.example.com",
+            "cdn1.net",
+            "img.cdn-eu.net",
+        ] {
+            assert_eq!(verdict(domain, RecordType::A), Verdict::Blocked, "{domain}");
+        }
+        for domain in [
+            "bad.example.com",
+            "ads.example.org",
+            "www.tracker.example.com",
+            "cdn.network",
+        ] {
+            assert_ne!(verdict(domain, RecordType::A), Verdict::Blocked, "{domain}");
+        }
+        assert_eq!(
+            verdict("metrics.example.com", RecordType::AAAA),
+            Verdict::Blocked
+        );
+        assert_eq!(
+            verdict("metrics.example.com", RecordType::A),
+            Verdict::Unmatched
+        );
+
+        let mut filter = Filter::default();
+        let report = filter.add_list(
+            "https://example.com/list.txt",
+            ["||example.com^", "! comment", r"/ads[0-9+\.example\.com/"],
+            ListFormat::Adblock,
+            BlockMode::Subdomains,
+        );
+        assert_eq!(report.accepted, 1);
+        let [rejected] = &report.rejected[..] else {
+            panic!("{:?}", report.rejected);
+        };
+        assert_eq!(
+            (rejected.source.as_str(), rejected.line),
+            ("https://example.com/list.txt", Some(3))
+        );
+        assert_eq!(
+            rejected.to_string(),
+            r"filter: https://example.com/list.txt:3: rejected rule '/ads[0-9+\.example\.com/': invalid regular expression: unclosed character class"
+        );
+    }
+
+    #[test]
+    fn test_filter_exceptions() {
+        let filter = filter(&[
+            "||example.com^",
+            "@@||www.example.com^",
+            "||tracker.net^$important",
+            "@@||tracker.net^",
+            "||cdn.org^",
+            "@@||cdn.org^$important",
+            "||cdn.org^$important",
+        ]);
+        assert_eq!(filter.len(), 7);
+
+        assert_eq!(
+            filter.verdict("example.com", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_eq!(
+            filter.verdict("ads.example.com", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_eq!(
+            filter.verdict("www.example.com", RecordType::A, CLIENT),
+            Verdict::Excepted
+        );
+        assert_ne!(
+            filter.verdict("img.www.example.com", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_eq!(
+            filter.verdict("tracker.net", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("cdn.org", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("example.org", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+    }
+
+    #[test]
+    fn test_filter_modifiers() {
+        let filter = filter(&[
+            "||example.com^$client=192.168.1.0/24|~192.168.1.3",
+            "||ipv6.net^$dnstype=AAAA",
+            "||other.net^$dnstype=~A|~CNAME",
+            "*$denyallow=com|org,client=10.0.0.0/8",
+            "@@|example.net^$client=10.0.0.1",
+        ]);
+
+        assert_eq!(
+            filter.verdict("example.com", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_eq!(
+            filter.verdict("www.example.com", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("example.com", RecordType::A, [192, 168, 1, 3].into()),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("example.com", RecordType::A, [192, 168, 2, 2].into()),
+            Verdict::Blocked
+        );
+
+        assert_eq!(
+            filter.verdict("ipv6.net", RecordType::AAAA, CLIENT),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("ipv6.net", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_eq!(
+            filter.verdict("other.net", RecordType::TXT, CLIENT),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("other.net", RecordType::CNAME, CLIENT),
+            Verdict::Blocked
+        );
+
+        let client: IpAddr = [10, 0, 0, 2].into();
+        assert_eq!(
+            filter.verdict("example.net", RecordType::A, client),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("example.com", RecordType::A, client),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("www.example.org", RecordType::A, client),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("example.net", RecordType::A, CLIENT),
+            Verdict::Blocked
+        );
+        assert_ne!(
+            filter.verdict("example.net", RecordType::A, [10, 0, 0, 1].into()),
+            Verdict::Blocked
+        );
+        assert_eq!(
+            filter.verdict("www.example.net", RecordType::A, [10, 0, 0, 1].into()),
+            Verdict::Blocked
+        );
+    }
+
+    #[test]
+    fn test_filter_reports_rules_for_all_domains() {
+        let mut filter = Filter::default();
+        let report = filter.add_list(
+            "test",
+            ["||ads.example.com^", "*", "||*^"],
+            ListFormat::Adblock,
+            BlockMode::Subdomains,
+        );
+        assert_eq!(report.accepted, 1);
+        assert_eq!(
+            report
+                .rejected
+                .iter()
+                .map(|rule| (rule.line, rule.rule.as_str()))
+                .collect::<Vec<_>>(),
+            [(Some(2), "*"), (Some(3), "||*^")]
+        );
+        assert_eq!(
+            filter.verdict("example.com", RecordType::A, CLIENT),
+            Verdict::Unmatched
+        );
+    }
+
+    #[test]
+    fn test_filter_allowlist() {
+        let mut filter = filter(&[
+            "||example.com^$important",
+            "@@||www.example.com^",
+            "||tracker.net^",
+            "ads.org",
+        ]);
+        let report = filter.add_allowlist(
+            "allowlist",
+            [
+                "api.example.com",
+                "*.tracker.net",
+                "@@||www.example.com^",
+                "||ads.org^$client=10.0.0.1",
+                "0.0.0.0 ads.org",
+            ],
+            ListFormat::Adblock,
+            BlockMode::Subdomains,
+        );
+        assert_eq!(report.accepted, 3);
+        assert_eq!(
+            report
+                .rejected
+                .iter()
+                .map(|rule| rule.rule.as_str())
+                .collect::<Vec<_>>(),
+            ["||ads.org^$client=10.0.0.1", "0.0.0.0 ads.org"]
+        );
+        assert_eq!(filter.allowlist_len(), 3);
+
+        let verdict = |domain| filter.verdict(domain, RecordType::A, CLIENT);
+        // Even over $important rules
+        assert_eq!(verdict("api.example.com"), Verdict::Allowlisted);
+        assert_eq!(verdict("v1.api.example.com"), Verdict::Allowlisted);
+        assert_eq!(verdict("example.com"), Verdict::Blocked);
+        assert_eq!(verdict("www.example.com"), Verdict::Allowlisted);
+        assert_eq!(verdict("cdn.tracker.net"), Verdict::Allowlisted);
+        assert_eq!(verdict("tracker.net"), Verdict::Blocked);
+        assert_eq!(verdict("ads.org"), Verdict::Blocked);
+        assert_eq!(verdict("example.org"), Verdict::Unmatched);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    env, io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::list_source::{ListSource, digest};
+
+/// Where the cache is kept if neither systemd's `StateDirectory=` nor a home directory is set
+const FALLBACK_DIRECTORY: &str = "/var/lib/dns-block-tokio/lists";
+
+/// The longest part of a cached list's file name that is taken from its URL
+const MAX_NAME_LEN: usize = 64;
+
+/// A directory with a copy of each list fetched from a URL, so the last fetched lists still block when their hosts
+/// are unreachable at startup. Each list is kept as `<name>.txt` along with `<name>.meta`, which holds its URL,
+/// `ETag`, `Last-Modified`, fetch time and number of lines.
+#[derive(Debug, Clone)]
+pub(crate) struct ListCache {
+    directory: PathBuf,
+}
+
+impl ListCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// The `lists` directory within the state directory that systemd passes as `$STATE_DIRECTORY`, or within
+    /// `$XDG_STATE_HOME/dns-block-tokio`, which defaults to `~/.local/state/dns-block-tokio`
+    pub fn default_directory() -> String {
+        let state_directory = env::var("STATE_DIRECTORY")
+            .ok()
+            .and_then(|directories| directories.split(':').next().map(PathBuf::from))
+            .or_else(|| {
+                env::var("XDG_STATE_HOME")
+                    .ok()
+                    .filter(|state| !state.is_empty())
+                    .map(PathBuf::from)
+                    .or_else(|| {
+                        env::var("HOME")
+                            .ok()
+                            .map(|home| PathBuf::from(home).join(".local/state"))
+                    })
+                    .map(|state| state.join("dns-block-tokio"))
+            });
+        match state_directory {
+            Some(directory) => directory.join("lists").display().to_string(),
+            None => FALLBACK_DIRECTORY.to_string(),
+        }
+    }
+
+    /// The path of a list's file with `extension`, whose name is readable but kept unique by a hash of the whole URL
+    fn path(&self, url: &str, extension: &str) -> PathBuf {
+        let name: String = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .take(MAX_NAME_LEN)
+            .collect();
+        self.directory
+            .join(format!("{name}-{:016x}.{extension}", fnv1a(url)))
+    }
+
+    /// Stores the fetched list, first the list and then its metadata, so a list that was not written completely is
+    /// never loaded
+    pub async fn store(&self, list: &ListSource, body: &str) -> io::Result<()> {
+        let meta_path = self.path(&list.location, "meta");
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let _ = tokio::fs::remove_file(&meta_path).await;
+        tokio::fs::write(self.path(&list.location, "txt"), body).await?;
+
+        let mut meta = format!("url {}\n", list.location);
+        if let Some(etag) = &list.etag {
+            meta += &format!("etag {etag}\n");
+        }
+        if let Some(last_modified) = &list.last_modified {
+            meta += &format!("last-modified {last_modified}\n");
+        }
+        if let Some(fetched) = list.fetched {
+            let fetched = fetched.duration_since(UNIX_EPOCH).unwrap_or_default();
+            meta += &format!("fetched {}\n", fetched.as_secs());
+        }
+        meta += &format!("lines {}\n", body.lines().count());
+        tokio::fs::write(meta_path, meta).await
+    }
+
+    /// Loads the digest of the stored copy of a list, along with the headers to refresh it with conditional requests.
+    /// Returns whether there was a copy, which `read` reads once the filter is built.
+    pub async fn load(&self, list: &mut ListSource) -> io::Result<bool> {
+        let meta = match tokio::fs::read_to_string(self.path(&list.location, "meta")).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let body = tokio::fs::read_to_string(self.path(&list.location, "txt")).await?;
+
+        let (mut url, mut etag, mut last_modified, mut fetched, mut lines) =
+            (None, None, None, None, None);
+        for line in meta.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "url" => url = Some(value),
+                "etag" => etag = Some(value.to_string()),
+                "last-modified" => last_modified = Some(value.to_string()),
+                "fetched" => {
+                    fetched = value
+                        .parse()
+                        .ok()
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                }
+                "lines" => lines = value.parse::<usize>().ok(),
+                _ => {}
+            }
+        }
+        if url != Some(list.location.as_str()) || lines != Some(body.lines().count()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the cached copy belongs to another URL or is incomplete",
+            ));
+        }
+
+        list.digest = Some(digest(&body));
+        list.etag = etag;
+        list.last_modified = last_modified;
+        list.fetched = fetched;
+        Ok(true)
+    }
+
+    /// Reads the stored copy of a list, which blocks, since the filter is built on a blocking thread
+    pub fn read(&self, url: &str) -> io::Result<String> {
+        std::fs::read_to_string(self.path(url, "txt"))
+    }
+}
+
+/// Ages like `3 minutes` or `2 days`, rounded down
+pub(crate) fn format_age(fetched: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(fetched)
+        .unwrap_or_default()
+        .as_secs();
+    let (count, unit) = match secs {
+        0..120 => (secs, "seconds"),
+        120..7_200 => (secs / 60, "minutes"),
+        7_200..172_800 => (secs / 3_600, "hours"),
+        _ => (secs / 86_400, "days"),
+    };
+    format!("{count} {unit}")
+}
+
+/// The 64-bit FNV-1a hash, which unlike the `Hasher`s of the standard library is the same across releases
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        list_cache::ListCache,
+        list_source::{ListSource, digest},
+    };
+
+    #[tokio::test]
+    async fn test_list_cache() {
+        let directory =
+            std::env::temp_dir().join(format!("dns-block-cache-{}", std::process::id()));
+        let cache = ListCache::new(&directory);
+        let url = "https://example.com/lists/ads.txt?format=hosts";
+
+        let mut list = ListSource::new(url);
+        assert!(!cache.load(&mut list).await.unwrap());
+
+        let body = "0.0.0.0 ads.example.com\n0.0.0.0 tracker.example.com\n";
+        list.digest = Some(digest(body));
+        list.etag = Some("\"v1\"".into());
+        list.fetched = Some(SystemTime::now() - Duration::from_secs(90));
+        cache.store(&list, body).await.unwrap();
+
+        let mut cached = ListSource::new(url);
+        assert!(cache.load(&mut cached).await.unwrap());
+        assert_eq!(cached.digest, list.digest);
+        assert_eq!(cache.read(url).unwrap(), body);
+        assert_eq!(cached.read(Some(&cache)).unwrap(), body);
+        assert_eq!(cached.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(cached.last_modified, None);
+        assert!(cached.fetched.is_some());
+
+        // Other URLs are kept apart
+        let mut other = ListSource::new("https://example.com/lists/ads.txt?format=adblock");
+        assert!(!cache.load(&mut other).await.unwrap());
+
+        // A list that was cut short is not loaded
+        fs::write(cache.path(url, "txt"), "0.0.0.0 ads.example.com\n").unwrap();
+        assert!(cache.load(&mut ListSource::new(url)).await.is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_path() {
+        let cache = ListCache::new("/var/cache");
+        let path = cache.path("https://example.com/hosts.txt?x=1", "txt");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("example.com_hosts.txt_x_1-"), "{name}");
+        assert!(name.ends_with(".txt"), "{name}");
+        assert_ne!(path, cache.path("https://example.com/hosts.txt?x=2", "txt"));
+    }
+}
@@ -0,0 +1,300 @@
+use std::net::IpAddr;
+
+use crate::filter::{has_literal_label, is_domain};
+
+/// How many rules are looked at to detect the format of a list
+const DETECTION_SAMPLE: usize = 100;
+
+/// Names that hosts files map to loopback addresses, which have to keep resolving
+const LOCAL_HOST_NAMES: [&str; 10] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+];
+
+/// The formats of blocklists, which are translated line by line into the adblock-style syntax of `Filter`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ListFormat {
+    /// One domain per line, like `ads.example.com`, `*.example.com` for all of its subdomains, or a glob like
+    /// `track*.example.*`
+    Domains,
+    /// hosts files like `0.0.0.0 ads.example.com`, with one or more domains per line
+    Hosts,
+    /// Adblock-style rules like `||ads.example.com^`
+    Adblock,
+    /// dnsmasq configuration like `address=/ads.example.com/`, which blocks the domain and its subdomains
+    Dnsmasq,
+}
+
+impl ListFormat {
+    /// Detects the format that most of the first rules of a list are in
+    pub fn detect<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let formats = [
+            ListFormat::Adblock,
+            ListFormat::Hosts,
+            ListFormat::Dnsmasq,
+            ListFormat::Domains,
+        ];
+        let mut counts = [0; 4];
+        for line in lines
+            .into_iter()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with(['#', '!']))
+            .take(DETECTION_SAMPLE)
+        {
+            let format = Self::guess(line);
+            counts[formats.iter().position(|&f| f == format).unwrap()] += 1;
+        }
+
+        // Ties go to the earlier format, which is the more specific one
+        let (most, _) = counts
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, count)| count)
+            .unwrap();
+        if counts[most] == 0 {
+            ListFormat::Domains
+        } else {
+            formats[most]
+        }
+    }
+
+    fn guess(line: &str) -> Self {
+        let mut fields = line.split_whitespace();
+        if line.starts_with("address=/")
+            || line.starts_with("server=/")
+            || line.starts_with("local=/")
+        {
+            ListFormat::Dnsmasq
+        } else if fields.next().is_some_and(|ip| ip.parse::<IpAddr>().is_ok())
+            && fields.next().is_some()
+        {
+            ListFormat::Hosts
+        } else if line.starts_with(['|', '@', '[', '/'])
+            || line.contains(['^', '$'])
+            || line.contains("##")
+        {
+            ListFormat::Adblock
+        } else {
+            ListFormat::Domains
+        }
+    }
+
+    /// Translates a line of a list into rules in the adblock-style syntax, or returns why the line is rejected.
+    /// Comments and lines that do not block anything, like `127.0.0.1 localhost`, yield no rules.
+    pub fn rules(self, line: &str) -> Result<Vec<String>, &'static str> {
+        if self == ListFormat::Adblock {
+            return Ok(vec![line.to_string()]);
+        }
+
+        let line = strip_comment(line);
+        if line.is_empty() {
+            return Ok(vec![]);
+        }
+        match self {
+            ListFormat::Domains => {
+                let domain = line.strip_prefix("*.").unwrap_or(line).replace('*', "a");
+                if line.contains(char::is_whitespace) || !is_domain(domain.trim_end_matches('.')) {
+                    return Err("not a domain");
+                }
+                if !has_literal_label(line) {
+                    return Err(
+                        "wildcards need a label without '*', or they match almost all domains",
+                    );
+                }
+                Ok(vec![line.trim_end_matches('.').to_string()])
+            }
+            ListFormat::Hosts => {
+                let mut fields = line.split_whitespace();
+                let ip = fields.next().and_then(|ip| ip.parse::<IpAddr>().ok());
+                if !ip.is_some_and(|ip| ip.is_unspecified() || ip.is_loopback()) {
+                    return Err(
+                        "only entries for 0.0.0.0, :: and loopback addresses block domains",
+                    );
+                }
+                fields
+                    .map(|domain| domain.trim_end_matches('.'))
+                    // StevenBlack's lists contain `0.0.0.0 0.0.0.0`
+                    .filter(|domain| domain.parse::<IpAddr>().is_err())
+                    .filter(|domain| {
+                        !LOCAL_HOST_NAMES.contains(&domain.to_ascii_lowercase().as_str())
+                    })
+                    .map(|domain| {
+                        is_domain(domain)
+                            .then(|| domain.to_string())
+                            .ok_or("not a domain")
+                    })
+                    .collect()
+            }
+            ListFormat::Dnsmasq => {
+                let (option, value) = line.split_once('=').ok_or("not a dnsmasq option")?;
+                if !matches!(option, "address" | "server" | "local") {
+                    return Err("only address, server and local options block domains");
+                }
+                let mut fields: Vec<_> = value.split('/').collect();
+                let target = fields.pop().unwrap();
+                if fields.len() < 2 || !fields[0].is_empty() {
+                    return Err("not a dnsmasq option for domains");
+                }
+                let blocks = match option {
+                    "address" => {
+                        target.is_empty()
+                            || target == "#"
+                            || target.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified())
+                    }
+                    _ => target.is_empty(),
+                };
+                if !blocks {
+                    return Err(
+                        "only options without an upstream or address, or with 0.0.0.0 or ::, block domains",
+                    );
+                }
+                fields[1..]
+                    .iter()
+                    .map(|domain| domain.trim_end_matches('.'))
+                    .map(|domain| {
+                        is_domain(domain)
+                            .then(|| format!("||{domain}^"))
+                            .ok_or("not a domain")
+                    })
+                    .collect()
+            }
+            ListFormat::Adblock => unreachable!(),
+        }
+    }
+}
+
+/// Strips `#` comments, which either take the whole line or follow whitespace, so `address=/example.com/#` is kept
+fn strip_comment(line: &str) -> &str {
+    let line = line.trim();
+    let end = line
+        .char_indices()
+        .find(|&(i, c)| c == '#' && (i == 0 || line[..i].ends_with(char::is_whitespace)))
+        .map_or(line.len(), |(i, _)| i);
+    line[..end].trim_end()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list_format::{ListFormat, strip_comment};
+
+    #[test]
+    fn test_detect() {
+        let hosts = "# StevenBlack\n127.0.0.1 localhost\n0.0.0.0 ads.example.com\n0.0.0.0 tracker.example.com";
+        assert_eq!(ListFormat::detect(hosts.lines()), ListFormat::Hosts);
+        let adblock = "[Adblock Plus]\n! Title: list\n||ads.example.com^\n/^tracker[0-9]*\\./";
+        assert_eq!(ListFormat::detect(adblock.lines()), ListFormat::Adblock);
+        let dnsmasq = "address=/ads.example.com/\naddress=/tracker.example.com/0.0.0.0";
+        assert_eq!(ListFormat::detect(dnsmasq.lines()), ListFormat::Dnsmasq);
+        let domains = "# hagezi\nads.example.com\n*.tracker.example.com";
+        assert_eq!(ListFormat::detect(domains.lines()), ListFormat::Domains);
+        assert_eq!(ListFormat::detect("".lines()), ListFormat::Domains);
+    }
+
+    #[test]
+    fn test_strip_comment() {
+        assert_eq!(strip_comment("# comment"), "");
+        assert_eq!(
+            strip_comment("  0.0.0.0 ads.example.com  # ads "),
+            "0.0.0.0 ads.example.com"
+        );
+        assert_eq!(
+            strip_comment("0.0.0.0\tads.example.com\t#ads"),
+            "0.0.0.0\tads.example.com"
+        );
+        assert_eq!(
+            strip_comment("address=/example.com/#"),
+            "address=/example.com/#"
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            ListFormat::Hosts.rules("0.0.0.0 Ads.example.com tracker.example.com. # both"),
+            Ok(vec!["Ads.example.com".into(), "tracker.example.com".into()])
+        );
+        assert_eq!(
+            ListFormat::Hosts.rules("::1 ip6-localhost ip6-loopback"),
+            Ok(vec![])
+        );
+        assert_eq!(ListFormat::Hosts.rules("0.0.0.0 0.0.0.0"), Ok(vec![]));
+        assert_eq!(
+            ListFormat::Hosts.rules("# 0.0.0.0 ads.example.com"),
+            Ok(vec![])
+        );
+        assert!(ListFormat::Hosts.rules("192.168.1.2 nas.home").is_err());
+        assert!(
+            ListFormat::Hosts
+                .rules("0.0.0.0 ads.example.com/path")
+                .is_err()
+        );
+
+        assert_eq!(
+            ListFormat::Domains.rules("*.ads.example.com  # wildcard"),
+            Ok(vec!["*.ads.example.com".into()])
+        );
+        assert_eq!(
+            ListFormat::Domains.rules("example.com."),
+            Ok(vec!["example.com".into()])
+        );
+        assert!(
+            ListFormat::Domains
+                .rules("0.0.0.0 ads.example.com")
+                .is_err()
+        );
+        assert_eq!(
+            ListFormat::Domains.rules("track*.example.*"),
+            Ok(vec!["track*.example.*".into()])
+        );
+        assert!(ListFormat::Domains.rules("||ads.example.com^").is_err());
+        // Wildcards alone would block every query, or every domain with a dot
+        for line in ["*", "*.*", "**", "*.*.", "*.*-*"] {
+            assert_eq!(
+                ListFormat::Domains.rules(line),
+                Err("wildcards need a label without '*', or they match almost all domains"),
+                "{line}"
+            );
+        }
+
+        assert_eq!(
+            ListFormat::Dnsmasq.rules("address=/ads.example.com/tracker.example.com/#"),
+            Ok(vec![
+                "||ads.example.com^".into(),
+                "||tracker.example.com^".into()
+            ])
+        );
+        assert_eq!(
+            ListFormat::Dnsmasq.rules("address=/ads.example.com/0.0.0.0"),
+            Ok(vec!["||ads.example.com^".into()])
+        );
+        assert_eq!(
+            ListFormat::Dnsmasq.rules("server=/ads.example.com/"),
+            Ok(vec!["||ads.example.com^".into()])
+        );
+        assert!(
+            ListFormat::Dnsmasq
+                .rules("address=/nas.home/192.168.1.2")
+                .is_err()
+        );
+        assert!(
+            ListFormat::Dnsmasq
+                .rules("server=/home/192.168.1.1")
+                .is_err()
+        );
+        assert!(ListFormat::Dnsmasq.rules("cache-size=1000").is_err());
+
+        assert_eq!(
+            ListFormat::Adblock.rules("||ads.example.com^$important"),
+            Ok(vec!["||ads.example.com^$important".into()])
+        );
+    }
+}
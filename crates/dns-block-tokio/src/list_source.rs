@@ -0,0 +1,590 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use futures::future;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use reqwest::{
+    Client, StatusCode,
+    header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use tokio::sync::mpsc;
+
+use crate::list_cache::{ListCache, format_age};
+
+/// How many lists are fetched at once
+const CONCURRENT_FETCHES: usize = 5;
+
+/// A domain list at a URL or path. Lists at URLs are refreshed with conditional requests, so lists that did not
+/// change are neither downloaded nor parsed again.
+///
+/// Only a digest of the list is kept in memory, while the list itself is read again from its file or the `ListCache`
+/// whenever the filter is rebuilt, so lists with millions of domains don't stay in memory next to the filter.
+#[derive(Debug)]
+pub(crate) struct ListSource {
+    /// The URL, or the path of a local file
+    pub location: String,
+    /// The digest of the list as of the last successful fetch, which is kept when a refresh fails
+    pub digest: Option<u64>,
+    /// The list as of the last successful fetch, only if it is at a URL and could not be stored in the cache
+    body: Option<String>,
+    /// The `ETag` header of the last successful fetch, sent as `If-None-Match`
+    pub etag: Option<String>,
+    /// The `Last-Modified` header of the last successful fetch, sent as `If-Modified-Since`
+    pub last_modified: Option<String>,
+    /// When the list was last fetched successfully, even if it did not change
+    pub fetched: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub(crate) enum FetchError {
+    Request(reqwest::Error),
+    Status(StatusCode),
+    ContentType(String),
+    Read(io::Error),
+}
+
+impl Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Request(e) => f.write_fmt(format_args!("list: request failed: {e}")),
+            FetchError::Status(status) => {
+                f.write_fmt(format_args!("list: server responded with {status}"))
+            }
+            FetchError::ContentType(content_type) => f.write_fmt(format_args!(
+                "list: content type '{content_type}' is not text/plain"
+            )),
+            FetchError::Read(e) => f.write_fmt(format_args!("list: reading failed: {e}")),
+        }
+    }
+}
+
+impl Error for FetchError {}
+
+impl ListSource {
+    pub fn new(location: &str) -> Self {
+        Self {
+            location: location.to_string(),
+            digest: None,
+            body: None,
+            etag: None,
+            last_modified: None,
+            fetched: None,
+        }
+    }
+
+    pub fn is_remote(&self) -> bool {
+        is_remote(&self.location)
+    }
+
+    /// Fetches or reads the list, unless the server reports that it did not change, and returns it if it changed
+    pub async fn fetch(&mut self, client: &Client) -> Result<Option<String>, FetchError> {
+        if !self.is_remote() {
+            let body = tokio::fs::read_to_string(&self.location)
+                .await
+                .map_err(FetchError::Read)?;
+            return Ok(self.update(body));
+        }
+
+        let mut request = client.get(&self.location);
+        if self.digest.is_some() {
+            if let Some(etag) = &self.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &self.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request.send().await.map_err(FetchError::Request)?;
+        if response.status() == StatusCode::NOT_MODIFIED && self.digest.is_some() {
+            self.fetched = Some(SystemTime::now());
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(FetchError::Status(response.status()));
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let content_type = header(CONTENT_TYPE).unwrap_or_else(|| "text/plain".into());
+        if !content_type.starts_with("text/plain") {
+            return Err(FetchError::ContentType(content_type));
+        }
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+
+        // The headers are only kept along with the body they belong to, or later requests would be answered with
+        // `304 Not Modified` for a body that was never read
+        let body = response.text().await.map_err(FetchError::Request)?;
+        self.etag = etag;
+        self.last_modified = last_modified;
+        Ok(self.update(body))
+    }
+
+    fn update(&mut self, body: String) -> Option<String> {
+        let digest = digest(&body);
+        let changed = self.digest != Some(digest);
+        self.digest = Some(digest);
+        self.fetched = Some(SystemTime::now());
+        changed.then_some(body)
+    }
+
+    /// Reads the list as of the last successful fetch again, from its file, or from `cache` if it is at a URL
+    pub fn read(&self, cache: Option<&ListCache>) -> io::Result<Cow<'_, str>> {
+        if let Some(body) = &self.body {
+            return Ok(Cow::Borrowed(body));
+        }
+        let body = match (local_path(&self.location), cache) {
+            (Some(path), _) => fs::read_to_string(path)?,
+            (None, Some(cache)) => cache.read(&self.location)?,
+            (None, None) => return Err(io::Error::from(io::ErrorKind::NotFound)),
+        };
+        Ok(Cow::Owned(body))
+    }
+}
+
+/// Tells lists apart that have different contents
+pub(crate) fn digest(body: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// The path of a location that is not a URL, like `file:///etc/lists/*.txt` or `lists/ads.txt`
+fn local_path(location: &str) -> Option<&Path> {
+    (!is_remote(location)).then(|| Path::new(location.strip_prefix("file://").unwrap_or(location)))
+}
+
+/// The lists of `--domain-blacklists` or `--domain-allowlists`, whose paths may be directories, or have `*` wildcards in
+/// their file name, like `/etc/lists/*.txt`
+#[derive(Debug)]
+pub(crate) struct ListSources {
+    locations: Vec<String>,
+    pub lists: Vec<ListSource>,
+    /// Where the lists at URLs are stored once fetched, and loaded from before they are fetched for the first time
+    cache: Option<ListCache>,
+}
+
+impl ListSources {
+    pub fn new(locations: &[String]) -> Self {
+        Self {
+            locations: locations.to_vec(),
+            lists: vec![],
+            cache: None,
+        }
+    }
+
+    pub fn with_cache(mut self, cache: ListCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Whether any list was fetched or loaded from the cache
+    pub fn has_fetched(&self) -> bool {
+        self.lists.iter().any(|list| list.digest.is_some())
+    }
+
+    /// Reads the lists that were fetched or loaded from the cache, one at a time as the iterator advances
+    pub fn read(&self) -> impl Iterator<Item = (&str, io::Result<Cow<'_, str>>)> {
+        self.lists
+            .iter()
+            .filter(|list| list.digest.is_some())
+            .map(|list| (list.location.as_str(), list.read(self.cache.as_ref())))
+    }
+
+    /// Whether any of the lists is local, so its directory is watched
+    pub fn has_local(&self) -> bool {
+        self.locations.iter().any(|location| !is_remote(location))
+    }
+
+    /// Matches the local paths against the files that are there now, and fetches all lists if `remote` is set, or only
+    /// the local ones otherwise. Returns whether any of them changed, which failed fetches are logged but not counted as.
+    ///
+    /// Lists at URLs that were not fetched yet start out as their cached copy, which is kept if fetching them fails,
+    /// and only downloaded again if they changed since.
+    pub async fn refresh(&mut self, client: &Client, remote: bool) -> bool {
+        let mut changed = self.expand();
+        let cache = self.cache.as_ref();
+        let mut lists: Vec<_> = self
+            .lists
+            .iter_mut()
+            .filter(|list| remote || !list.is_remote())
+            .collect();
+        for lists in lists.chunks_mut(CONCURRENT_FETCHES) {
+            let fetches =
+                future::join_all(lists.iter_mut().map(|list| fetch_list(list, client, cache)));
+            changed |= fetches.await.contains(&true);
+        }
+        changed
+    }
+
+    /// Replaces the lists by those at the locations now, keeping the lists that are still there, and returns whether
+    /// any list was removed. Files that several locations match are only read once.
+    fn expand(&mut self) -> bool {
+        let mut previous: HashMap<_, _> = self
+            .lists
+            .drain(..)
+            .map(|list| (list.location.clone(), list))
+            .collect();
+        let mut seen = HashSet::new();
+        for location in &self.locations {
+            let locations = match local_path(location) {
+                Some(path) => expand_path(path)
+                    .unwrap_or_else(|e| {
+                        eprintln!("Reading domain lists from {location} failed: {e}");
+                        vec![]
+                    })
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect(),
+                None => vec![location.clone()],
+            };
+            for location in locations {
+                if !seen.insert(location.clone()) {
+                    continue;
+                }
+                let list = previous
+                    .remove(&location)
+                    .unwrap_or_else(|| ListSource::new(&location));
+                self.lists.push(list);
+            }
+        }
+        !previous.is_empty()
+    }
+
+    /// The directories that contain the local lists, where files may be added, replaced or removed
+    fn directories(&self) -> Vec<PathBuf> {
+        self.locations
+            .iter()
+            .filter_map(|location| local_path(location))
+            .map(|path| {
+                if path.is_dir() {
+                    path.to_path_buf()
+                } else {
+                    match path.parent() {
+                        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                        _ => PathBuf::from("."),
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// The files at a path, which are all files of a directory except hidden ones, or the files whose name matches the `*`
+/// wildcards of the path's file name, in the order of their names
+fn expand_path(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    let (directory, pattern) = match file_name {
+        Some(pattern) if pattern.contains('*') => {
+            (path.parent().unwrap_or(Path::new(".")), pattern)
+        }
+        _ if path.is_dir() => (path, "*"),
+        _ => return Ok(vec![path.to_path_buf()]),
+    };
+    let directory = if directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        directory
+    };
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if !name.starts_with('.') && matches_glob(name, pattern) && entry.path().is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Whether a file name matches a pattern, where `*` matches any characters
+fn matches_glob(name: &str, pattern: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = name.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(start) => rest = &rest[start + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Watches the directories of the local lists, and sends on `changes` whenever a file in them changed. Events are
+/// dropped while one is still pending, since the lists are read again as a whole anyway.
+pub(crate) fn watch(
+    sources: &[&ListSources],
+    changes: mpsc::Sender<()>,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = changes.try_send(());
+        }
+    })?;
+    for directory in sources.iter().flat_map(|sources| sources.directories()) {
+        watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
+
+async fn fetch_list(list: &mut ListSource, client: &Client, cache: Option<&ListCache>) -> bool {
+    let cache = cache.filter(|_| list.is_remote());
+    let mut cached = false;
+    if let Some(cache) = cache
+        && list.digest.is_none()
+    {
+        cached = cache.load(list).await.unwrap_or_else(|e| {
+            eprintln!(
+                "Loading the cached domain list from {} failed: {e}",
+                list.location
+            );
+            false
+        });
+    }
+
+    match list.fetch(client).await {
+        Ok(Some(body)) => {
+            // Local lists are read from their files again, and lists at URLs from the cache if they could be stored
+            list.body = None;
+            if list.is_remote() {
+                match cache {
+                    Some(cache) => {
+                        if let Err(e) = cache.store(list, &body).await {
+                            eprintln!("Caching domain list from {} failed: {e}", list.location);
+                            list.body = Some(body);
+                        }
+                    }
+                    None => list.body = Some(body),
+                }
+            }
+            true
+        }
+        Ok(None) => cached,
+        Err(e) if list.digest.is_some() => {
+            let age = list.fetched.map_or("an unknown time".into(), format_age);
+            eprintln!(
+                "Fetching domain list from {} failed, keeping the version fetched {age} ago: {e}",
+                list.location
+            );
+            cached
+        }
+        Err(e) => {
+            eprintln!("Fetching domain list from {} failed: {e}", list.location);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use reqwest::Client;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::list_source::{FetchError, ListSource, ListSources, digest, matches_glob};
+
+    /// Answers each request with the response that `respond` returns for the number of requests before it and the
+    /// lowercase request, and closes the connection
+    async fn serve(
+        respond: impl Fn(usize, &str) -> String + Send + 'static,
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/list.txt", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).to_ascii_lowercase();
+                let response = respond(counter.fetch_add(1, Ordering::SeqCst), &request);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn list_response(etag: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: text/plain\r\netag: {etag}\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    /// Serves `ads.example.com` with an `ETag` at the first request and answers later ones that send it with
+    /// `304 Not Modified`, and the requests after `failing_after` with `500`
+    async fn list_server(failing_after: usize) -> (String, Arc<AtomicUsize>) {
+        serve(move |requests, request| {
+            if requests >= failing_after {
+                "HTTP/1.1 500 Internal Server Error\r\nconnection: close\r\ncontent-length: 0\r\n\r\n".to_string()
+            } else if request.contains("if-none-match: \"v1\"") {
+                "HTTP/1.1 304 Not Modified\r\nconnection: close\r\netag: \"v1\"\r\n\r\n".to_string()
+            } else {
+                list_response("\"v1\"", "ads.example.com\n")
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let (url, requests) = list_server(2).await;
+        let client = Client::new();
+        let mut source = ListSource::new(&url);
+
+        assert_eq!(
+            source.fetch(&client).await.unwrap().as_deref(),
+            Some("ads.example.com\n")
+        );
+        assert_eq!(source.digest, Some(digest("ads.example.com\n")));
+        assert_eq!(source.etag.as_deref(), Some("\"v1\""));
+
+        // Unchanged, so the server does not send it again
+        assert_eq!(source.fetch(&client).await.unwrap(), None);
+        assert_eq!(source.digest, Some(digest("ads.example.com\n")));
+
+        // A failing refresh keeps the last version
+        assert!(matches!(
+            source.fetch(&client).await,
+            Err(FetchError::Status(status)) if status.as_u16() == 500
+        ));
+        assert_eq!(source.digest, Some(digest("ads.example.com\n")));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_interrupted() {
+        let (url, _) = serve(|requests, request| match requests {
+            0 => list_response("\"v1\"", "ads.example.com\n"),
+            // The connection closes before the announced body was sent
+            1 => list_response("\"v2\"", "ads.example.com\ntracker.exa")
+                .replace("content-length: 27", "content-length: 100"),
+            _ if request.contains("if-none-match: \"v2\"") => {
+                "HTTP/1.1 304 Not Modified\r\nconnection: close\r\n\r\n".to_string()
+            }
+            _ => list_response("\"v2\"", "ads.example.com\ntracker.example.com\n"),
+        })
+        .await;
+        let client = Client::new();
+        let mut source = ListSource::new(&url);
+        assert!(source.fetch(&client).await.unwrap().is_some());
+
+        // The `ETag` of the body that could not be read is not kept, so the next refresh downloads it again
+        assert!(matches!(
+            source.fetch(&client).await,
+            Err(FetchError::Request(_))
+        ));
+        assert_eq!(source.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            source.fetch(&client).await.unwrap().as_deref(),
+            Some("ads.example.com\ntracker.example.com\n")
+        );
+        assert_eq!(source.etag.as_deref(), Some("\"v2\""));
+    }
+
+    #[test]
+    fn test_matches_glob() {
+        assert!(matches_glob("ads.txt", "*.txt"));
+        assert!(matches_glob("ads.txt", "*"));
+        assert!(matches_glob("ads.txt", "ads.txt"));
+        assert!(matches_glob("ads-2024.list.txt", "ads*.txt"));
+        assert!(matches_glob("ads.txt", "a*d*s.txt"));
+        assert!(!matches_glob("ads.txt.bak", "*.txt"));
+        assert!(!matches_glob("trackers.txt", "ads*"));
+        assert!(!matches_glob("ads.txt", "ads"));
+    }
+
+    #[tokio::test]
+    async fn test_local_lists() {
+        let directory =
+            std::env::temp_dir().join(format!("dns-block-lists-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("ads.txt"), "ads.example.com\n").unwrap();
+        fs::write(directory.join("trackers.txt"), "tracker.example.com\n").unwrap();
+        fs::write(directory.join("notes.md"), "# Lists\n").unwrap();
+        fs::write(directory.join(".ads.txt.swp"), "").unwrap();
+
+        let glob = format!("file://{}/*.txt", directory.display());
+        let mut sources = ListSources::new(&[glob, directory.display().to_string()]);
+        assert!(sources.has_local());
+        let client = Client::new();
+        assert!(sources.refresh(&client, false).await);
+        let locations = |sources: &ListSources| {
+            sources
+                .lists
+                .iter()
+                .map(|list| {
+                    list.location
+                        .strip_prefix(&directory.display().to_string())
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        let bodies = |sources: &ListSources| {
+            sources
+                .read()
+                .map(|(_, body)| body.unwrap().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            locations(&sources),
+            ["/ads.txt", "/trackers.txt", "/notes.md"]
+        );
+        assert_eq!(
+            bodies(&sources),
+            ["ads.example.com\n", "tracker.example.com\n", "# Lists\n"]
+        );
+        assert!(!sources.refresh(&client, false).await);
+
+        fs::write(directory.join("ads.txt"), "ads.example.org\n").unwrap();
+        fs::remove_file(directory.join("trackers.txt")).unwrap();
+        assert!(sources.refresh(&client, false).await);
+        assert_eq!(locations(&sources), ["/ads.txt", "/notes.md"]);
+        assert_eq!(bodies(&sources), ["ads.example.org\n", "# Lists\n"]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,15 +1,30 @@
 mod cache;
 mod cli;
+mod doh;
 mod domain_rewrite;
+mod domain_set;
+mod filter;
+mod list_cache;
+mod list_format;
+mod list_source;
+mod multiplexer;
 mod recording;
 mod resolution;
+mod upstream;
 
 use cli::ServerArgs;
-use futures::stream::{self, StreamExt};
-use http::{HeaderValue, Uri};
+use filter::{Filter, ListReport};
+use list_cache::ListCache;
+use list_format::ListFormat;
+use list_source::{ListSources, watch};
 use resolution::Resolver;
-use std::{sync::Arc, thread::available_parallelism};
-use tokio::{signal, task::JoinHandle};
+use std::{fs, path::Path, sync::Arc, thread::available_parallelism, time::Duration};
+use tokio::{
+    signal,
+    sync::mpsc,
+    task::{self, JoinHandle},
+    time::{self, Instant, MissedTickBehavior},
+};
 
 use dns::parser::DnsPacketBuffer;
 
@@ -31,23 +46,50 @@ async fn main() {
     start_server_with_acceptors(server_args, get_acceptor_pool_size()).await;
 }
 
-async fn start_server_with_acceptors(mut server_args: ServerArgs, num_acceptor_tasks: u8) {
+async fn start_server_with_acceptors(server_args: ServerArgs, num_acceptor_tasks: u8) {
     let client_socket =
         tokio::net::UdpSocket::bind((server_args.bind_address.clone(), server_args.bind_port))
             .await
             .unwrap();
-    let upstream_socket = tokio::net::UdpSocket::bind(("0.0.0.0", 0)).await.unwrap();
 
-    // Extend the explicitly passed list of blocked domains with blocked domains fetched from remote repositories
-    // TODO: what about wildcard domain blocklist entries? Should *.<domain>.<tld> block any subdomain of <domain>.<tld>
-    // or should blocking <domain>.<tld> just imply blocking any subdomain as well?
-    let external_blocked_domains: Vec<String> =
-        resolve_external_blocked_domains(&server_args.domain_blacklists).await;
-    server_args
-        .blocked_domains
-        .extend_from_slice(&external_blocked_domains);
+    // Block the explicitly passed domains along with those of the blocklists fetched from remote repositories or read
+    // from local files, and likewise for allowed domains
+    let client = reqwest::Client::new();
+    let cache = ListCache::new(&server_args.list_cache_dir);
+    let mut blocklists = ListSources::new(&server_args.domain_blacklists).with_cache(cache.clone());
+    let mut allowlists = ListSources::new(&server_args.domain_allowlists).with_cache(cache);
+    blocklists.refresh(&client, true).await;
+    allowlists.refresh(&client, true).await;
+    if !blocklists.has_fetched() {
+        if server_args.require_blocklists {
+            eprintln!(
+                "No blocklist could be fetched or loaded from the cache, not starting due to --require-blocklists"
+            );
+            std::process::exit(1);
+        } else if !blocklists.is_empty() {
+            eprintln!(
+                "No blocklist could be fetched or loaded from the cache, starting without them"
+            );
+        }
+    }
+    let filter = load_filter(&server_args, &blocklists, &allowlists);
 
-    let resolver = Arc::new(Resolver::new(server_args, client_socket, upstream_socket));
+    let resolver = Arc::new(
+        Resolver::new(server_args, filter, client_socket)
+            .expect("Could not set up the upstream DNS server"),
+    );
+    let refresh_task_handle = ((resolver.server_args.list_refresh_interval > 0
+        && !(blocklists.is_empty() && allowlists.is_empty()))
+        || blocklists.has_local()
+        || allowlists.has_local())
+    .then(|| {
+        tokio::spawn(refresh_filter(
+            Arc::clone(&resolver),
+            client,
+            blocklists,
+            allowlists,
+        ))
+    });
 
     let acceptor_task_handles: Vec<JoinHandle<_>> = (0..num_acceptor_tasks)
         .map(|_| {
@@ -58,12 +100,13 @@ async fn start_server_with_acceptors(mut server_args: ServerArgs, num_acceptor_t
                 loop {
                     let resolver = Arc::clone(&resolver);
                     let mut buffer: DnsPacketBuffer = [0u8; 512];
-                    let (_, sender) = resolver.client_socket.recv_from(&mut buffer).await.unwrap();
+                    let (len, sender) =
+                        resolver.client_socket.recv_from(&mut buffer).await.unwrap();
 
                     // ...and then dispatches processing that UDP packet to an independent Tokio task, so that accepting and processing
                     // are decoupled and we don't block accepting new incoming UDP packets from being processed
                     tokio::spawn(async move {
-                        resolver.process(&buffer, &sender).await;
+                        resolver.process(&buffer[..len], &sender).await;
                     });
                 }
             })
@@ -75,93 +118,158 @@ async fn start_server_with_acceptors(mut server_args: ServerArgs, num_acceptor_t
         Err(e) => eprintln!("Error when handling SIGINT {e}"),
     };
 
-    for handle in acceptor_task_handles {
+    for handle in acceptor_task_handles.iter().chain(&refresh_task_handle) {
         handle.abort();
     }
 }
 
-async fn resolve_external_blocked_domains(domain_blacklist_uris: &[String]) -> Vec<String> {
-    let valid_uris = domain_blacklist_uris
-        .iter()
-        .flat_map(|string| string.parse::<Uri>());
-
-    let successful_response_bodies = stream::iter(valid_uris)
-        .map(|uri| reqwest::get(uri.to_string()))
-        .filter_map(|res| async move {
-            let res = res.await;
-
-            match res {
-                Ok(response) => {
-                    if !response.status().is_success()
-                        || !response
-                            .headers()
-                            .get("content-type")
-                            .unwrap_or(&HeaderValue::from_static("text/plain"))
-                            .to_str()
-                            .unwrap()
-                            .starts_with("text/plain")
-                    {
-                        eprintln!(
-                            "Fetching blocked domains from {} failed",
-                            response.url().as_str()
-                        );
-                        return None;
-                    }
-
-                    let url = response.url().as_str().to_string();
-                    let body = response.text().await;
-
-                    match body {
-                        Ok(text) => Some(async { (url, text) }),
-                        Err(err) => {
-                            eprintln!(
-                                "Fetching blocked domains from {} failed: {:?}",
-                                err.url().unwrap(),
-                                err
-                            );
-                            None
-                        }
-                    }
-                }
-                Err(err) => {
-                    eprintln!(
-                        "Fetching blocked domains from {} failed: {:?}",
-                        err.url().unwrap(),
-                        err
-                    );
-                    None
-                }
+/// Re-fetches the lists every `--list-refresh-interval`, and reads the local lists again whenever files in their
+/// directories change. Once any of them changed, the filter is rebuilt and swapped in, while queries are still resolved
+/// with the previous one.
+async fn refresh_filter(
+    resolver: Arc<Resolver>,
+    client: reqwest::Client,
+    mut blocklists: ListSources,
+    mut allowlists: ListSources,
+) {
+    // Configuration management and editors tend to write a file in several steps, or several files at once
+    const WATCH_DELAY: Duration = Duration::from_millis(500);
+
+    let refresh_interval = resolver.server_args.list_refresh_interval;
+    let period = Duration::from_secs(refresh_interval.max(1));
+    let mut interval = time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let (sender, mut changes) = mpsc::channel(1);
+    let _watcher = watch(&[&blocklists, &allowlists], sender)
+        .inspect_err(|e| eprintln!("Watching local domain lists failed: {e}"))
+        .ok();
+
+    loop {
+        let remote = tokio::select! {
+            _ = interval.tick(), if refresh_interval > 0 => true,
+            Some(()) = changes.recv() => {
+                time::sleep(WATCH_DELAY).await;
+                let _ = changes.try_recv();
+                false
             }
-        })
-        .buffer_unordered(5)
-        .collect::<Vec<_>>()
-        .await;
-
-    let domains = successful_response_bodies
-        .into_iter()
-        .flat_map(|(url, body)| {
-            let domains = body
-                .split('\n')
-                // Some blocklists have comments in them and should not be empty
-                .filter(|line| !line.starts_with('#') && line.len() > 3 && line.contains('.'))
-                .map(|s| s.to_string())
-                .collect::<Vec<_>>();
-
-            println!(
-                "[Debug] Loaded {} blocked domains from {}",
-                domains.len(),
-                url
-            );
+            else => return,
+        };
+        let blocklists_changed = blocklists.refresh(&client, remote).await;
+        let allowlists_changed = allowlists.refresh(&client, remote).await;
+        if !blocklists_changed && !allowlists_changed {
+            continue;
+        }
 
-            domains
+        // Parsing large lists takes a while, which must not hold up the tasks resolving queries
+        let server_args = resolver.server_args.clone();
+        let filter;
+        (filter, blocklists, allowlists) = task::spawn_blocking(move || {
+            let filter = load_filter(&server_args, &blocklists, &allowlists);
+            (filter, blocklists, allowlists)
         })
-        .collect::<Vec<_>>();
+        .await
+        .expect("Could not rebuild the filter");
+        resolver.filter.store(Arc::new(filter));
+    }
+}
 
-    domains.iter().map(|s| s.to_string()).collect()
+/// Builds the filter from `--blocked-domains` and `--allowed-domains`, which may use the adblock-style syntax, and the
+/// fetched lists, whose format is detected for each list. The lists are read one at a time, so only one of them is in
+/// memory next to the filter.
+fn load_filter(
+    server_args: &ServerArgs,
+    blocklists: &ListSources,
+    allowlists: &ListSources,
+) -> Filter {
+    let mut filter = Filter::default();
+
+    if !server_args.blocked_domains.is_empty() {
+        let report = filter.add_list(
+            "--blocked-domains",
+            server_args.blocked_domains.iter().map(String::as_str),
+            ListFormat::Adblock,
+            server_args.block_mode,
+        );
+        report_list("--blocked-domains", ListFormat::Adblock, &report);
+    }
+    for (location, body) in blocklists.read() {
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Reading domain list {location} failed: {e}");
+                continue;
+            }
+        };
+        let format = ListFormat::detect(body.lines());
+        let report = filter.add_list(location, body.lines(), format, server_args.block_mode);
+        report_list(location, format, &report);
+    }
+
+    if !server_args.allowed_domains.is_empty() {
+        let report = filter.add_allowlist(
+            "--allowed-domains",
+            server_args.allowed_domains.iter().map(String::as_str),
+            ListFormat::Adblock,
+            server_args.block_mode,
+        );
+        report_list("--allowed-domains", ListFormat::Adblock, &report);
+    }
+    for (location, body) in allowlists.read() {
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Reading domain list {location} failed: {e}");
+                continue;
+            }
+        };
+        let format = ListFormat::detect(body.lines());
+        let report = filter.add_allowlist(location, body.lines(), format, server_args.block_mode);
+        report_list(location, format, &report);
+    }
+
+    // Lists with millions of domains take less memory mapped from a file, whose pages the kernel can evict
+    if !blocklists.is_empty() {
+        let path = Path::new(&server_args.list_cache_dir).join("blocklist.fst");
+        if let Err(e) = fs::create_dir_all(&server_args.list_cache_dir)
+            .and_then(|()| filter.persist_blocklist(&path))
+        {
+            eprintln!("Mapping the blocklist from {} failed: {e}", path.display());
+        }
+    }
+
+    println!(
+        "Blocking with {} rules, allowing {} domains [mode={:?}]",
+        filter.len(),
+        filter.allowlist_len(),
+        server_args.block_mode
+    );
+    filter
+}
+
+/// Prints how many rules of a list were loaded, and the first of the rejected ones, so a list that does not block as
+/// expected can be told apart from a broken one
+fn report_list(source: &str, format: ListFormat, report: &ListReport) {
+    const MAX_REPORTED: usize = 10;
+
+    println!(
+        "Loaded {} rules from {source} [format={format:?}, rejected={}]",
+        report.accepted,
+        report.rejected.len()
+    );
+    for rule in report.rejected.iter().take(MAX_REPORTED) {
+        eprintln!("{rule}");
+    }
+    if report.rejected.len() > MAX_REPORTED {
+        eprintln!(
+            "Skipping {} more rules from {source}",
+            report.rejected.len() - MAX_REPORTED
+        );
+    }
 }
 
 /// Returns the number of acceptor Tokio tasks to spawn, based on the number
-/// of CPU cores that this application runs on.
+/// of CPU cores that this application runs on, but at least one.
 fn get_acceptor_pool_size() -> u8 {
-    available_parallelism().unwrap().get() as u8 / 2
+    (available_parallelism().unwrap().get() as u8 / 2).max(1)
 }
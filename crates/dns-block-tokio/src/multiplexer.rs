@@ -0,0 +1,273 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use dns::{
+    client::{MAX_UDP_RESPONSE, random_id},
+    parser::DnsParser,
+    protocol::{packet::DnsPacket, record_type::RecordType},
+};
+use tokio::{net::UdpSocket, sync::oneshot, task::JoinHandle};
+
+/// Identifies a query in flight to the upstream, since the request ID alone may be reused for another question
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TransactionKey {
+    request_id: u16,
+    domain: String,
+    record_type: RecordType,
+    class: u16,
+}
+
+impl TransactionKey {
+    fn new(request_id: u16, packet: &DnsPacket) -> Self {
+        Self {
+            request_id,
+            domain: packet.question.domain_name.to_ascii_lowercase(),
+            record_type: packet.question.r#type,
+            class: packet.question.class,
+        }
+    }
+}
+
+type PendingTransactions = Arc<Mutex<HashMap<TransactionKey, oneshot::Sender<Vec<u8>>>>>;
+
+/// Forwards queries of all clients to a plain DNS upstream via a single UDP socket.
+///
+/// Every query is sent with a random request ID that is unique among the queries in flight, so clients that happen
+/// to use the same ID do not get each other's answers, and the client's ID is restored in the answer. A single task
+/// receives all answers and hands each to the query it belongs to, matched by ID and question. Answers that do not
+/// come from the upstream or match no query in flight are dropped.
+#[derive(Debug)]
+pub(crate) struct UdpMultiplexer {
+    socket: Arc<UdpSocket>,
+    upstream: SocketAddr,
+    /// How long to wait for the upstream's answer
+    timeout: Duration,
+    pending: PendingTransactions,
+    receiver: JoinHandle<()>,
+}
+
+impl UdpMultiplexer {
+    /// Starts receiving answers from `upstream` on `socket`, which requires a Tokio runtime
+    pub fn new(socket: UdpSocket, upstream: SocketAddr) -> Self {
+        let socket = Arc::new(socket);
+        let pending = PendingTransactions::default();
+        let receiver = tokio::spawn(receive_answers(
+            Arc::clone(&socket),
+            upstream,
+            Arc::clone(&pending),
+        ));
+
+        Self {
+            socket,
+            upstream,
+            timeout: Duration::from_secs(5),
+            pending,
+            receiver,
+        }
+    }
+
+    /// Binds a socket on a random port of the unspecified address of `upstream`'s family
+    pub fn bind(upstream: SocketAddr) -> Result<Self, io::Error> {
+        let local: SocketAddr = match upstream {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = std::net::UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::new(UdpSocket::from_std(socket)?, upstream))
+    }
+
+    /// Sends the raw DNS message `request` and returns the upstream's answer with the request's ID
+    pub async fn exchange(&self, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let packet = DnsParser::new(request)
+            .parse()
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "upstream udp: invalid query"))?;
+
+        let (sender, receiver) = oneshot::channel();
+        let transaction = {
+            let mut pending = self.pending.lock().unwrap();
+            loop {
+                let request_id = random_id().map_err(io::Error::other)?;
+                if let Entry::Vacant(entry) =
+                    pending.entry(TransactionKey::new(request_id, &packet))
+                {
+                    let key = entry.key().clone();
+                    entry.insert(sender);
+                    break Transaction {
+                        key,
+                        pending: &self.pending,
+                    };
+                }
+            }
+        };
+
+        let mut upstream_request = request.to_vec();
+        upstream_request[..2].copy_from_slice(&transaction.key.request_id.to_be_bytes());
+        self.socket
+            .send_to(&upstream_request, self.upstream)
+            .await?;
+
+        let mut answer = match tokio::time::timeout(self.timeout, receiver).await {
+            Ok(Ok(answer)) => answer,
+            Ok(Err(_)) => {
+                return Err(io::Error::new(
+                    ErrorKind::BrokenPipe,
+                    "upstream udp: stopped receiving answers",
+                ));
+            }
+            Err(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "upstream udp: no answer in time",
+                ));
+            }
+        };
+        answer[..2].copy_from_slice(&request[..2]);
+        Ok(answer)
+    }
+}
+
+impl Drop for UdpMultiplexer {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+/// Removes a query from the pending transactions once it was answered, timed out or its task was cancelled
+struct Transaction<'a> {
+    key: TransactionKey,
+    pending: &'a PendingTransactions,
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.key);
+    }
+}
+
+async fn receive_answers(
+    socket: Arc<UdpSocket>,
+    upstream: SocketAddr,
+    pending: PendingTransactions,
+) {
+    loop {
+        let mut answer = [0; MAX_UDP_RESPONSE];
+        let (len, sender) = match socket.recv_from(&mut answer).await {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Failed to receive upstream answer: {e:?}");
+                continue;
+            }
+        };
+        if sender != upstream {
+            continue;
+        }
+
+        let Ok(packet) = DnsParser::new(&answer[..len]).parse() else {
+            continue;
+        };
+        let key = TransactionKey::new(packet.header.request_id, &packet);
+        let waiting = pending.lock().unwrap().remove(&key);
+        if let Some(waiting) = waiting {
+            // The query may have timed out in the meantime
+            let _ = waiting.send(answer[..len].to_vec());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, time::Duration};
+
+    use dns::{
+        parser::DnsParser,
+        protocol::{query::Query, record_type::RecordType},
+        serialize::serialize_query,
+    };
+    use tokio::net::UdpSocket;
+
+    use crate::multiplexer::UdpMultiplexer;
+
+    async fn multiplexer(upstream: SocketAddr) -> UdpMultiplexer {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut multiplexer = UdpMultiplexer::new(socket, upstream);
+        multiplexer.timeout = Duration::from_millis(300);
+        multiplexer
+    }
+
+    /// Turns a query into an answer by setting the QR flag
+    fn answer(query: &[u8]) -> Vec<u8> {
+        let mut answer = query.to_vec();
+        answer[2] |= 0b1000_0000;
+        answer
+    }
+
+    #[tokio::test]
+    async fn test_exchange_matches_answers_with_the_same_client_id() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = upstream.local_addr().unwrap();
+        let multiplexer = multiplexer(address).await;
+
+        // Answers both queries once both arrived, in reverse order
+        tokio::spawn(async move {
+            let mut queries = vec![];
+            for _ in 0..2 {
+                let mut query = [0u8; 512];
+                let (len, client) = upstream.recv_from(&mut query).await.unwrap();
+                queries.push((query[..len].to_vec(), client));
+            }
+            assert_ne!(queries[0].0[..2], queries[1].0[..2]);
+            for (query, client) in queries.iter().rev() {
+                upstream.send_to(&answer(query), client).await.unwrap();
+            }
+        });
+
+        let query_a = serialize_query(&Query::new("example.com", RecordType::A), 0x1337);
+        let query_aaaa = serialize_query(&Query::new("example.com", RecordType::AAAA), 0x1337);
+        let (answer_a, answer_aaaa) = tokio::join!(
+            multiplexer.exchange(&query_a),
+            multiplexer.exchange(&query_aaaa)
+        );
+
+        let packet = DnsParser::new(&answer_a.unwrap()).parse().unwrap();
+        assert_eq!(packet.header.request_id, 0x1337);
+        assert_eq!(packet.question.r#type, RecordType::A);
+        let packet = DnsParser::new(&answer_aaaa.unwrap()).parse().unwrap();
+        assert_eq!(packet.header.request_id, 0x1337);
+        assert_eq!(packet.question.r#type, RecordType::AAAA);
+        assert!(multiplexer.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_exchange_ignores_foreign_answers_and_times_out() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = upstream.local_addr().unwrap();
+        let multiplexer = multiplexer(address).await;
+
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            let (len, client) = upstream.recv_from(&mut query).await.unwrap();
+
+            // From another source address
+            let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            spoofer
+                .send_to(&answer(&query[..len]), client)
+                .await
+                .unwrap();
+            // With the client's instead of the upstream request ID
+            let mut answer = answer(&query[..len]);
+            answer[..2].copy_from_slice(&0x1337u16.to_be_bytes());
+            upstream.send_to(&answer, client).await.unwrap();
+        });
+
+        let query = serialize_query(&Query::new("example.com", RecordType::A), 0x1337);
+        let error = multiplexer.exchange(&query).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(multiplexer.pending.lock().unwrap().is_empty());
+    }
+}
@@ -1,50 +1,86 @@
-use std::{
-    collections::{BTreeSet, HashMap},
-    net::SocketAddr,
-    sync::Arc,
-};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use arc_swap::ArcSwap;
 
 use dns::{
-    parser::{DnsPacketBuffer, DnsParser},
-    protocol::{packet::DnsPacket, record_type::RecordType},
-    resolver::{relay_query_async, stub_response_with_delay},
-    serialize::generate_nx_response,
+    client::random_id,
+    iterative::IterativeResolver,
+    parser::DnsParser,
+    protocol::{
+        answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+        class::CLASS_IN,
+        header::{Flags, Header},
+        packet::DnsPacket,
+        query::Query,
+        record_type::RecordType,
+        response_code::ResponseCode,
+    },
+    resolver::stub_response_with_delay,
+    serialize::{
+        generate_nx_response, generate_truncated_response, serialize_query, serialize_response,
+    },
+    tcp::TcpConnection,
 };
 use tokio::{net::UdpSocket, sync::RwLock, time::Instant};
 
 use crate::{
     cache::{CacheKey, RequestCache},
     cli::ServerArgs,
+    domain_rewrite::{RewriteAnswers, Rewrites, reverse_rewrites},
+    filter::{Filter, Verdict},
+    upstream::UpstreamConnection,
 };
 
 pub struct Resolver {
     pub(crate) server_args: ServerArgs,
-    pub(crate) request_associations: Arc<RwLock<RequestAssociationMap>>,
     pub(crate) request_cache: Arc<RwLock<RequestCache>>,
-    pub(crate) blocked_domains: Arc<BTreeSet<String>>,
+    /// The records of the rewritten domains and wildcards
+    pub(crate) rewrites: Rewrites,
+    /// The rewritten domains by the name of their IP's PTR records, see `reverse_rewrites`
+    pub(crate) reverse_rewrites: HashMap<String, Vec<String>>,
+    /// Decides which queries are blocked, see `Filter`, and is swapped for a new one when the lists are refreshed
+    pub(crate) filter: ArcSwap<Filter>,
     pub(crate) client_socket: UdpSocket,
-    pub(crate) upstream_socket: UdpSocket,
+    /// Used to repeat queries whose plain UDP upstream answers were truncated
+    pub(crate) upstream_tcp: TcpConnection,
+    pub(crate) upstream_connection: UpstreamConnection,
 }
 
 impl Resolver {
     pub fn new(
         server_args: ServerArgs,
+        filter: Filter,
         client_socket: UdpSocket,
-        upstream_socket: UdpSocket,
-    ) -> Self {
-        Self {
-            request_associations: Default::default(),
+    ) -> Result<Self, std::io::Error> {
+        Ok(Self {
             request_cache: Arc::new(RwLock::new(RequestCache::new())),
-            blocked_domains: Arc::new(BTreeSet::from_iter(server_args.blocked_domains.clone())),
+            filter: ArcSwap::from_pointee(filter),
+            rewrites: Rewrites::new(&server_args.domain_rewrites)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            reverse_rewrites: reverse_rewrites(&server_args.domain_rewrites),
+            upstream_tcp: TcpConnection::new(server_args.dns_relay.address()),
+            upstream_connection: if server_args.recursive {
+                UpstreamConnection::Iterative(IterativeResolver::new())
+            } else {
+                UpstreamConnection::new(
+                    &server_args.dns_relay,
+                    server_args.doh_method,
+                    server_args.bootstrap_dns,
+                )?
+            },
             server_args,
             client_socket,
-            upstream_socket,
-        }
+        })
     }
 
-    pub async fn process(&self, client_packet: &DnsPacketBuffer, sender: &SocketAddr) {
-        let mut parser = DnsParser::new(client_packet);
-        let request_packet = parser.parse().unwrap();
+    pub async fn process(&self, client_packet: &[u8], sender: &SocketAddr) {
+        let request_packet = match DnsParser::new(client_packet).parse() {
+            Ok(request_packet) => request_packet,
+            Err(e) => {
+                self.reject_malformed(client_packet, e, sender).await;
+                return;
+            }
+        };
 
         if self.server_args.benchmark {
             handle_benchmark(
@@ -54,10 +90,13 @@ impl Resolver {
                 std::time::Duration::from_millis(self.server_args.resolution_delay_ms),
             )
             .await;
-        } else if self
-            .blocked_domains
-            .contains(&request_packet.question.domain_name)
-        {
+        } else if let Some(answers) = self.rewrites.answers(
+            &request_packet.question.domain_name,
+            request_packet.question.r#type,
+            self.server_args.rewrite_ttl,
+        ) {
+            self.answer_rewrite(&request_packet, answers, sender).await;
+        } else if self.is_blocked(&request_packet, sender) {
             handle_filter(
                 &self.server_args,
                 &request_packet,
@@ -65,6 +104,19 @@ impl Resolver {
                 sender,
             )
             .await;
+        } else if request_packet.question.r#type == RecordType::PTR
+            && let Some(domains) = self
+                .reverse_rewrites
+                .get(&request_packet.question.domain_name.to_ascii_lowercase())
+        {
+            handle_reverse_rewrite(
+                &self.server_args,
+                &request_packet,
+                domains,
+                &self.client_socket,
+                sender,
+            )
+            .await;
         } else {
             let start = Instant::now();
 
@@ -76,10 +128,7 @@ impl Resolver {
                     .await
                     .get(cache_key.clone(), request_packet.header.request_id)
             {
-                self.client_socket
-                    .send_to(&dns_reply, sender)
-                    .await
-                    .unwrap();
+                self.send_reply(&dns_reply, &request_packet, sender).await;
 
                 // todo: record cache hit
 
@@ -95,112 +144,243 @@ impl Resolver {
                 return;
             }
 
-            // Create a unqiue key that identifies the query, store it in a shared hashmap and
-            // pass it to `handle_resolution` so it can later lookup who to send it to.
-            let sender_key = RequestKey::from_packet(&request_packet);
+            self.forward(client_packet, &request_packet, sender, start, cache_key)
+                .await;
+        }
+    }
+}
 
-            self.request_associations.write().await.insert(
-                sender_key.clone(),
-                (*sender, request_packet, start, cache_key),
+impl Resolver {
+    /// Whether the filter blocks the query, which is logged if an allowlist or exception overrides the blocking rules
+    fn is_blocked(&self, request_packet: &DnsPacket, sender: &SocketAddr) -> bool {
+        let verdict = self.filter.load().verdict(
+            &request_packet.question.domain_name,
+            request_packet.question.r#type,
+            sender.ip(),
+        );
+        if !self.server_args.quiet && matches!(verdict, Verdict::Allowlisted | Verdict::Excepted) {
+            println!(
+                "Allowing blocked request for {:?} [{verdict:?}]",
+                request_packet.question.domain_name
             );
+        }
+        verdict == Verdict::Blocked
+    }
 
-            // We send the incoming client DNS packet to the configured relay DNS server via `relay_socket` and get back
-            // a DNS response as a raw `DnsPacketBuffer` or an error.
-            match relay_query_async(
-                client_packet,
-                &self.server_args.dns_relay,
-                &self.upstream_socket,
-            )
-            .await
-            {
-                Ok(reply_buffer) => {
-                    // todo: limit the number of times we need to parse DNS packets
-                    let reply_packet = DnsParser::new(&reply_buffer).parse().unwrap();
-                    let unique_request_key = RequestKey::from_packet(&reply_packet);
-                    let request_data = self
-                        .request_associations
-                        .write()
-                        .await
-                        .remove(&unique_request_key);
-
-                    match request_data {
-                        Some((client_address, request_packet, started_at, cache_key)) => {
-                            debug_assert_eq!(
-                                reply_packet.question.domain_name,
-                                request_packet.question.domain_name
-                            );
-                            debug_assert_eq!(
-                                reply_packet.header.request_id,
-                                request_packet.header.request_id
-                            );
-
-                            // Send the upstream DNS reply to the original client that sent the DNS query.
-                            // We need to use the same client that we used to accept the client's query, so that
-                            // the client does not invalidate our response because of a port mismatch, since
-                            // any other socket would not be on the DNS listening port.
-                            self.client_socket
-                                .send_to(&reply_buffer, client_address)
-                                .await
-                                .unwrap();
-
-                            // todo: record handled response metric
-
-                            if self.server_args.caching_enabled {
-                                self.request_cache
-                                    .write()
-                                    .await
-                                    .set(cache_key, reply_buffer);
-                            }
-
-                            if !self.server_args.quiet {
-                                println!(
-                                    "Handled {:?} query for {} [{}ms]",
-                                    reply_packet.question.r#type,
-                                    reply_packet.question.domain_name,
-                                    started_at.elapsed().as_millis()
-                                );
-                            }
-                        }
-                        None => {
-                            eprintln!("No matching sender address for {:?}", unique_request_key);
-                        }
-                    }
-                }
-                Err(e) => {
-                    dbg!(e);
-                }
+    /// Answers a query for a rewritten domain locally, completed by the upstream's records for the target of a CNAME
+    /// rewrite that no other rewrite covers
+    async fn answer_rewrite(
+        &self,
+        request_packet: &DnsPacket,
+        answers: RewriteAnswers,
+        sender: &SocketAddr,
+    ) {
+        if !self.server_args.quiet {
+            println!(
+                "Rewriting {:?} request for {:?}",
+                request_packet.question.r#type, request_packet.question.domain_name
+            );
+        }
+
+        let mut records = answers.records;
+        if let Some(cname) = answers.unresolved_cname {
+            records.extend(
+                self.resolve_cname_target(&cname, request_packet.question.r#type)
+                    .await,
+            );
+        }
+        let response = generate_local_response(request_packet, answers.response_code, records);
+        self.client_socket.send_to(&response, sender).await.unwrap();
+    }
+
+    /// Queries the upstream for the records of the target of a CNAME rewrite, or none if that fails
+    async fn resolve_cname_target(
+        &self,
+        cname: &str,
+        record_type: RecordType,
+    ) -> Vec<ResourceRecord> {
+        let resolved = match random_id() {
+            Ok(request_id) => {
+                let query = serialize_query(&Query::new(cname, record_type), request_id);
+                self.upstream_connection.exchange(&query).await
+            }
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        match resolved.map(|reply| DnsParser::new(&reply).parse().map(|packet| packet.answers)) {
+            Ok(Ok(answers)) => answers,
+            Ok(Err(_)) => {
+                eprintln!("Failed to parse upstream answer for rewrite target {cname}");
+                vec![]
+            }
+            Err(e) => {
+                eprintln!("Failed to resolve rewrite target {cname}: {e:?}");
+                vec![]
             }
         }
     }
-}
 
-pub type RequestAssociationMap = HashMap<RequestKey, (SocketAddr, DnsPacket, Instant, CacheKey)>;
+    /// Forwards the query via the upstream connection, which matches the answer to the query on its own
+    async fn forward(
+        &self,
+        client_packet: &[u8],
+        request_packet: &DnsPacket,
+        sender: &SocketAddr,
+        started_at: Instant,
+        cache_key: CacheKey,
+    ) {
+        let reply_buffer = match self.upstream_connection.exchange(client_packet).await {
+            Ok(reply_buffer) => reply_buffer,
+            Err(e) => {
+                eprintln!(
+                    "Failed to forward {:?} query for {}: {e:?}",
+                    request_packet.question.r#type, request_packet.question.domain_name
+                );
+                return;
+            }
+        };
+        let Ok(reply_packet) = DnsParser::new(&reply_buffer).parse() else {
+            eprintln!(
+                "Failed to parse upstream answer for {}",
+                request_packet.question.domain_name
+            );
+            return;
+        };
 
-#[derive(PartialEq, Eq, Clone, Debug, Hash)]
-pub struct RequestKey {
-    record_type: RecordType,
-    request_id: u16,
-    domain: String,
-}
+        // Stream transports never truncate, so only plain UDP answers have to be repeated via TCP
+        let (reply_buffer, truncated) = match &self.upstream_connection {
+            UpstreamConnection::Plain(_) if reply_packet.header.flags.truncation => {
+                match self.resolve_truncated(client_packet, request_packet).await {
+                    Some(complete_reply) => (complete_reply, false),
+                    None => (reply_buffer, true),
+                }
+            }
+            _ => (reply_buffer, reply_packet.header.flags.truncation),
+        };
+
+        self.send_reply(&reply_buffer, request_packet, sender).await;
 
-impl RequestKey {
-    pub fn new(record_type: RecordType, request_id: u16, domain: String) -> Self {
-        RequestKey {
-            record_type,
-            request_id,
-            domain,
+        // Truncated answers are incomplete, so a later identical query should try again
+        if self.server_args.caching_enabled && !truncated {
+            self.request_cache
+                .write()
+                .await
+                .set(cache_key, reply_buffer);
+        }
+
+        if !self.server_args.quiet {
+            println!(
+                "Handled {:?} query for {} [{}ms]",
+                reply_packet.question.r#type,
+                reply_packet.question.domain_name,
+                started_at.elapsed().as_millis()
+            );
         }
     }
 
-    pub(crate) fn from_packet(packet: &DnsPacket) -> Self {
-        RequestKey::new(
-            packet.question.r#type,
-            packet.header.request_id,
-            packet.question.domain_name.clone(),
-        )
+    /// Answers a query that can't be parsed with FORMERR, see https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1,
+    /// unless not even its header is complete or it is a response, which is ignored so as not to answer answers
+    async fn reject_malformed(&self, client_packet: &[u8], error: dns::Error, sender: &SocketAddr) {
+        if !self.server_args.quiet {
+            println!("Rejecting malformed query from {sender}: {error}");
+        }
+        let Ok(header) = DnsParser::new(client_packet).parse_header() else {
+            return;
+        };
+        if !header.flags.query {
+            return;
+        }
+        let response = Header {
+            request_id: header.request_id,
+            flags: Flags {
+                query: false,
+                opcode: header.flags.opcode,
+                recursion_desired: header.flags.recursion_desired,
+                recursion_available: true,
+                response_code: ResponseCode::FORMERR.into(),
+                ..Flags::default()
+            },
+            ..Header::default()
+        };
+        let response: [u8; 12] = response.into();
+        let _ = self.client_socket.send_to(&response, sender).await;
+    }
+
+    /// Sends `reply` to the client, or only its header and question with the truncation flag set, if it exceeds the
+    /// client's UDP limit, which happens for answers received via TCP or other stream transports
+    async fn send_reply(
+        &self,
+        reply: &[u8],
+        request_packet: &DnsPacket,
+        client_address: &SocketAddr,
+    ) {
+        if reply.len() > request_packet.max_udp_payload_size()
+            && let Ok(reply_packet) = DnsParser::new(reply).parse()
+        {
+            let truncated = generate_truncated_response(&reply_packet);
+            self.client_socket
+                .send_to(&truncated, client_address)
+                .await
+                .unwrap();
+        } else {
+            self.client_socket
+                .send_to(reply, client_address)
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Repeats a query whose upstream UDP answer was truncated via TCP, see https://datatracker.ietf.org/doc/html/rfc7766#section-5
+    ///
+    /// Returns `None` to keep the truncated answer if that fails or the complete answer does not fit into a UDP
+    /// response to the client, since the client can only retry via TCP on its own then.
+    async fn resolve_truncated(
+        &self,
+        client_packet: &[u8],
+        request_packet: &DnsPacket,
+    ) -> Option<Vec<u8>> {
+        match self.upstream_tcp.exchange(client_packet).await {
+            // The answer is cached, which requires it to parse, and must answer the client's question
+            Ok(reply) if !answers_question(&reply, request_packet) => {
+                eprintln!(
+                    "Ignoring upstream answer for {} via TCP, which is malformed or answers another question",
+                    request_packet.question.domain_name
+                );
+                None
+            }
+            Ok(reply) if reply.len() <= request_packet.max_udp_payload_size() => Some(reply),
+            Ok(reply) => {
+                if !self.server_args.quiet {
+                    println!(
+                        "Answer for {} via TCP exceeds the client's UDP limit [{} bytes]",
+                        request_packet.question.domain_name,
+                        reply.len()
+                    );
+                }
+                None
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to resolve truncated answer for {} via TCP: {e:?}",
+                    request_packet.question.domain_name
+                );
+                None
+            }
+        }
     }
 }
 
+/// Whether `reply` parses as an answer to the question of `request_packet`
+fn answers_question(reply: &[u8], request_packet: &DnsPacket) -> bool {
+    DnsParser::new(reply).parse().is_ok_and(|reply_packet| {
+        !reply_packet.header.flags.query
+            && reply_packet.question.r#type == request_packet.question.r#type
+            && reply_packet
+                .question
+                .domain_name
+                .eq_ignore_ascii_case(&request_packet.question.domain_name)
+    })
+}
+
 pub async fn handle_filter(
     server_args: &ServerArgs,
     request_packet: &DnsPacket,
@@ -217,6 +397,80 @@ pub async fn handle_filter(
     socket.send_to(&nx_response, sender).await.unwrap();
 }
 
+/// Answers a PTR query for the IP of rewritten domains with those domains
+pub async fn handle_reverse_rewrite(
+    server_args: &ServerArgs,
+    request_packet: &DnsPacket,
+    domains: &[String],
+    socket: &tokio::net::UdpSocket,
+    sender: &std::net::SocketAddr,
+) {
+    if !server_args.quiet {
+        println!(
+            "Answering rewritten reverse request for {:?}",
+            request_packet.question.domain_name
+        );
+    }
+
+    let answers = domains
+        .iter()
+        .map(|domain| {
+            local_record(
+                request_packet,
+                server_args.rewrite_ttl,
+                ResourceRecordData::PTR {
+                    domain_name: domain.clone(),
+                },
+            )
+        })
+        .collect();
+    let response = generate_local_response(request_packet, ResponseCode::NOERROR.into(), answers);
+    socket.send_to(&response, sender).await.unwrap();
+}
+
+fn local_record(request_packet: &DnsPacket, ttl: u32, value: ResourceRecordData) -> ResourceRecord {
+    ResourceRecord::new(
+        ResourceRecordMeta {
+            name: request_packet.question.domain_name.clone(),
+            record_type: value.record_type().unwrap(),
+            class: CLASS_IN,
+            ttl,
+            len: 0,
+        },
+        value,
+    )
+}
+
+/// Generates a response with `response_code` and `answers` to the question of `request_packet`, which is
+/// authoritative unless it reports an error
+fn generate_local_response(
+    request_packet: &DnsPacket,
+    response_code: u8,
+    answers: Vec<ResourceRecord>,
+) -> Vec<u8> {
+    let header = Header {
+        request_id: request_packet.header.request_id,
+        flags: Flags {
+            query: false,
+            authoritative_answer: response_code == u8::from(ResponseCode::NOERROR),
+            recursion_desired: request_packet.header.flags.recursion_desired,
+            recursion_available: true,
+            response_code,
+            ..Flags::default()
+        },
+        ..Header::default()
+    };
+
+    let response = DnsPacket {
+        header,
+        question: request_packet.question.clone(),
+        answers,
+        authorities: vec![],
+        additional: vec![],
+    };
+    serialize_response(&response).unwrap()
+}
+
 pub async fn handle_benchmark(
     request_id: u16,
     socket: &tokio::net::UdpSocket,
@@ -228,3 +482,117 @@ pub async fn handle_benchmark(
         .unwrap();
     socket.send_to(&reply, sender).await.unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use std::{net::Ipv6Addr, str::FromStr};
+
+    use dns::{
+        parser::DnsParser,
+        protocol::{
+            answer::ResourceRecordData, query::Query, record_type::RecordType,
+            response_code::ResponseCode,
+        },
+        serialize::serialize_query,
+    };
+
+    use crate::{
+        domain_rewrite::{DomainRewrite, Rewrites},
+        resolution::{answers_question, generate_local_response},
+    };
+
+    #[test]
+    fn test_answers_question() {
+        let query = serialize_query(&Query::new("Big.example.com", RecordType::TXT), 0x1337);
+        let request_packet = DnsParser::new(&query).parse().unwrap();
+        let answer = |domain, record_type| {
+            let query = serialize_query(&Query::new(domain, record_type), 0x1337);
+            let packet = DnsParser::new(&query).parse().unwrap();
+            generate_local_response(&packet, ResponseCode::NOERROR.into(), vec![])
+        };
+
+        assert!(answers_question(
+            &answer("big.example.com", RecordType::TXT),
+            &request_packet
+        ));
+        assert!(!answers_question(
+            &answer("other.example.com", RecordType::TXT),
+            &request_packet
+        ));
+        assert!(!answers_question(
+            &answer("big.example.com", RecordType::A),
+            &request_packet
+        ));
+        // The query itself is no answer, and neither is a header without the question it announces
+        assert!(!answers_question(&query, &request_packet));
+        assert!(!answers_question(
+            &answer("big.example.com", RecordType::TXT)[..12],
+            &request_packet
+        ));
+    }
+
+    #[test]
+    fn test_rewrite_response() {
+        let rewrites = Rewrites::new(
+            &[
+                "nas.home:10.0.0.2,fd00::2",
+                "www.home:CNAME nas.home",
+                "nas.home:TXT hello",
+            ]
+            .map(|rewrite| DomainRewrite::from_str(rewrite).unwrap()),
+        )
+        .unwrap();
+
+        let query = serialize_query(&Query::new("WWW.home", RecordType::AAAA), 0x1337);
+        let request_packet = DnsParser::new(&query).parse().unwrap();
+        let answers = rewrites.answers("WWW.home", RecordType::AAAA, 60).unwrap();
+        let response =
+            generate_local_response(&request_packet, answers.response_code, answers.records);
+
+        let packet = DnsParser::new(&response).parse().unwrap();
+        assert_eq!(packet.header.request_id, 0x1337);
+        assert!(!packet.header.flags.query);
+        assert!(packet.header.flags.authoritative_answer);
+        assert!(packet.header.flags.recursion_desired);
+        assert_eq!(
+            packet.header.flags.response_code,
+            u8::from(ResponseCode::NOERROR)
+        );
+        assert_eq!(packet.question.domain_name, "WWW.home");
+        let records: Vec<_> = packet
+            .answers
+            .iter()
+            .map(|record| (record.meta.name.as_str(), record.meta.ttl, &record.value))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                (
+                    "WWW.home",
+                    60,
+                    &ResourceRecordData::CNAME {
+                        cname: "nas.home".into()
+                    }
+                ),
+                (
+                    "nas.home",
+                    60,
+                    &ResourceRecordData::AAAA {
+                        ipv6: Ipv6Addr::from_str("fd00::2").unwrap()
+                    }
+                ),
+            ]
+        );
+
+        // Records without a variant of their own are serialized from their wire format
+        let query = serialize_query(&Query::new("nas.home", RecordType::TXT), 0x1337);
+        let request_packet = DnsParser::new(&query).parse().unwrap();
+        let answers = rewrites.answers("nas.home", RecordType::TXT, 60).unwrap();
+        let response =
+            generate_local_response(&request_packet, answers.response_code, answers.records);
+        assert!(response.ends_with(&[0, 6, 5, b'h', b'e', b'l', b'l', b'o']));
+        let packet = DnsParser::new(&response).parse().unwrap();
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(packet.answers[0].meta.record_type, RecordType::TXT);
+    }
+}
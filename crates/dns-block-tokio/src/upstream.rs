@@ -0,0 +1,406 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    str::FromStr,
+};
+
+use dns::{
+    iterative::IterativeResolver,
+    quic::QuicConnection,
+    system::{RESOLV_CONF_PATH, ResolvConf},
+    tls::TlsConnection,
+};
+
+use crate::{
+    doh::{DohMethod, HttpsConnection},
+    multiplexer::UdpMultiplexer,
+};
+
+/// The upstream DNS server that queries are forwarded to, see `ServerArgs::dns_relay`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Upstream {
+    /// Plain DNS via UDP, which falls back to TCP for truncated answers, e.g. `1.1.1.1:53`
+    Plain { address: String },
+    /// DNS-over-TLS, e.g. `tls://1.1.1.1:853#cloudflare-dns.com`, where the optional fragment is the name the
+    /// server's certificate has to be valid for and defaults to the host
+    Tls {
+        address: String,
+        server_name: String,
+    },
+    /// DNS-over-QUIC, e.g. `quic://dns.adguard-dns.com`, with the same optional fragment as DNS-over-TLS
+    Quic {
+        address: String,
+        server_name: String,
+    },
+    /// DNS-over-HTTPS, e.g. `https://cloudflare-dns.com/dns-query`
+    Https { url: String },
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum UpstreamError {
+    UnsupportedScheme(String),
+    AddressMissing,
+    ServerNameInvalid(String),
+    UrlInvalid(String),
+}
+
+impl Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::UnsupportedScheme(scheme) => f.write_fmt(format_args!(
+                "upstream: unsupported scheme '{scheme}', use a plain address, tls://, quic:// or https://"
+            )),
+            UpstreamError::AddressMissing => f.write_str("upstream: address is missing"),
+            UpstreamError::ServerNameInvalid(name) => f.write_fmt(format_args!(
+                "upstream: '{name}' is not a valid TLS server name"
+            )),
+            UpstreamError::UrlInvalid(url) => f.write_fmt(format_args!(
+                "upstream: '{url}' is not a valid DNS-over-HTTPS URL"
+            )),
+        }
+    }
+}
+
+impl Error for UpstreamError {}
+
+impl FromStr for Upstream {
+    type Err = UpstreamError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, rest)) = s.split_once("://") else {
+            if s.is_empty() {
+                return Err(UpstreamError::AddressMissing);
+            }
+            return Ok(Upstream::Plain {
+                address: with_default_port(s, 53),
+            });
+        };
+
+        match scheme {
+            "tls" | "quic" => {
+                let (address, server_name) = match rest.split_once('#') {
+                    Some((address, server_name)) => (address, server_name),
+                    None => (rest, host(rest)),
+                };
+                if address.is_empty() {
+                    return Err(UpstreamError::AddressMissing);
+                }
+                // Only IP addresses and DNS names are accepted in certificates
+                if server_name.parse::<IpAddr>().is_err()
+                    && (server_name.is_empty()
+                        || !server_name
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+                {
+                    return Err(UpstreamError::ServerNameInvalid(server_name.to_string()));
+                }
+
+                let (address, server_name) =
+                    (with_default_port(address, 853), server_name.to_string());
+                Ok(match scheme {
+                    "tls" => Upstream::Tls {
+                        address,
+                        server_name,
+                    },
+                    _ => Upstream::Quic {
+                        address,
+                        server_name,
+                    },
+                })
+            }
+            "https" => match reqwest::Url::parse(s) {
+                Ok(url) if url.host_str().is_some_and(|host| !host.is_empty()) => {
+                    Ok(Upstream::Https { url: s.to_string() })
+                }
+                _ if rest.is_empty() => Err(UpstreamError::AddressMissing),
+                _ => Err(UpstreamError::UrlInvalid(s.to_string())),
+            },
+            scheme => Err(UpstreamError::UnsupportedScheme(scheme.to_string())),
+        }
+    }
+}
+
+impl Display for Upstream {
+    /// Formats the upstream the way it is parsed from
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Upstream::Plain { address } => f.write_str(address),
+            Upstream::Tls {
+                address,
+                server_name,
+            } => write!(f, "tls://{address}#{server_name}"),
+            Upstream::Quic {
+                address,
+                server_name,
+            } => write!(f, "quic://{address}#{server_name}"),
+            Upstream::Https { url } => f.write_str(url),
+        }
+    }
+}
+
+impl Upstream {
+    /// The first name server of the host's /etc/resolv.conf that is not the blocker itself, or Cloudflare's if there
+    /// is none, see `system_nameserver`
+    pub fn system(bind: Option<SocketAddr>) -> Self {
+        let address = ResolvConf::from_path(RESOLV_CONF_PATH)
+            .ok()
+            .and_then(|conf| system_nameserver(&conf.nameservers, bind))
+            .map_or_else(|| "1.1.1.1:53".to_string(), |address| address.to_string());
+        Upstream::Plain { address }
+    }
+
+    /// The address to connect to, or the URL for DNS-over-HTTPS
+    pub fn address(&self) -> &str {
+        match self {
+            Upstream::Plain { address }
+            | Upstream::Tls { address, .. }
+            | Upstream::Quic { address, .. } => address,
+            Upstream::Https { url } => url,
+        }
+    }
+}
+
+/// Picks the first of `nameservers` that is neither a loopback address nor `bind`. The blocker is often the host's own
+/// resolver, e.g. `nameserver 127.0.0.1`, and would forward every query to itself.
+fn system_nameserver(nameservers: &[SocketAddr], bind: Option<SocketAddr>) -> Option<SocketAddr> {
+    nameservers.iter().copied().find(|&nameserver| {
+        !nameserver.ip().is_loopback()
+            && !nameserver.ip().is_unspecified()
+            && Some(nameserver) != bind
+    })
+}
+
+/// Appends `port` to `address`, unless it already has one
+fn with_default_port(address: &str, port: u16) -> String {
+    if address.parse::<SocketAddr>().is_ok() {
+        return address.to_string();
+    }
+    match address.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) if address.contains(':') => address.to_string(),
+        Err(_) => format!("{address}:{port}"),
+    }
+}
+
+/// Returns the host of an address with an optional port
+fn host(address: &str) -> &str {
+    match address.parse::<SocketAddr>() {
+        // Strip the brackets of IPv6 addresses
+        Ok(SocketAddr::V6(_)) => address
+            .rsplit_once(':')
+            .map_or(address, |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']'),
+        Ok(SocketAddr::V4(_)) => address.split_once(':').map_or(address, |(host, _)| host),
+        Err(_) if address.parse::<IpAddr>().is_ok() => address,
+        Err(_) => address.split_once(':').map_or(address, |(host, _)| host),
+    }
+}
+
+/// A connection to an upstream, which matches answers to queries on its own
+#[derive(Debug)]
+pub(crate) enum UpstreamConnection {
+    Plain(UdpMultiplexer),
+    Tls(TlsConnection),
+    Quic(QuicConnection),
+    Https(HttpsConnection),
+    /// Resolves queries on its own instead of forwarding them, see `ServerArgs::recursive`
+    Iterative(IterativeResolver),
+}
+
+impl UpstreamConnection {
+    /// Sets up the connection to `upstream`, which is established lazily on the first exchange
+    ///
+    /// DNS-over-HTTPS servers are queried with `doh_method` and their host name is resolved via the plain DNS
+    /// server `bootstrap_dns`.
+    pub fn new(
+        upstream: &Upstream,
+        doh_method: DohMethod,
+        bootstrap_dns: SocketAddr,
+    ) -> Result<Self, io::Error> {
+        match upstream {
+            Upstream::Plain { address } => {
+                let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("upstream: '{address}' has no address"),
+                    )
+                })?;
+                Ok(UpstreamConnection::Plain(UdpMultiplexer::bind(address)?))
+            }
+            Upstream::Tls {
+                address,
+                server_name,
+            } => Ok(UpstreamConnection::Tls(TlsConnection::new(
+                address.as_str(),
+                server_name,
+            )?)),
+            Upstream::Quic {
+                address,
+                server_name,
+            } => Ok(UpstreamConnection::Quic(QuicConnection::new(
+                address.as_str(),
+                server_name,
+            )?)),
+            Upstream::Https { url } => Ok(UpstreamConnection::Https(HttpsConnection::new(
+                url,
+                doh_method,
+                bootstrap_dns,
+            )?)),
+        }
+    }
+
+    /// Sends the raw DNS message `request` and returns the upstream's answer
+    pub async fn exchange(&self, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        match self {
+            UpstreamConnection::Plain(connection) => connection.exchange(request).await,
+            UpstreamConnection::Tls(connection) => connection.exchange(request).await,
+            UpstreamConnection::Quic(connection) => connection.exchange(request).await,
+            UpstreamConnection::Https(connection) => connection.exchange(request).await,
+            UpstreamConnection::Iterative(resolver) => resolver.exchange(request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use crate::upstream::{Upstream, UpstreamError, system_nameserver};
+
+    #[test]
+    fn test_upstream_display() {
+        for upstream in [
+            "1.1.1.1:53",
+            "[2606:4700:4700::1111]:53",
+            "tls://1.1.1.1:853#cloudflare-dns.com",
+            "quic://dns.adguard-dns.com:853#dns.adguard-dns.com",
+            "https://cloudflare-dns.com/dns-query",
+        ] {
+            assert_eq!(Upstream::from_str(upstream).unwrap().to_string(), upstream);
+        }
+    }
+
+    #[test]
+    fn test_system_nameserver() {
+        let addresses = |addresses: &[&str]| {
+            addresses
+                .iter()
+                .map(|address| address.parse().unwrap())
+                .collect::<Vec<SocketAddr>>()
+        };
+        let nameservers = addresses(&[
+            "127.0.0.1:53",
+            "[::1]:53",
+            "0.0.0.0:53",
+            "192.168.1.2:53",
+            "9.9.9.9:53",
+        ]);
+        assert_eq!(
+            system_nameserver(&nameservers, None),
+            Some("192.168.1.2:53".parse().unwrap())
+        );
+        assert_eq!(
+            system_nameserver(&nameservers, Some("192.168.1.2:53".parse().unwrap())),
+            Some("9.9.9.9:53".parse().unwrap())
+        );
+        // The same address on another port is another server
+        assert_eq!(
+            system_nameserver(&nameservers, Some("192.168.1.2:53000".parse().unwrap())),
+            Some("192.168.1.2:53".parse().unwrap())
+        );
+        assert_eq!(
+            system_nameserver(&addresses(&["127.0.0.53:53"]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_upstream_parse() {
+        assert_eq!(
+            Upstream::from_str("1.1.1.1:53"),
+            Ok(Upstream::Plain {
+                address: "1.1.1.1:53".into()
+            })
+        );
+        assert_eq!(
+            Upstream::from_str("2606:4700:4700::1111"),
+            Ok(Upstream::Plain {
+                address: "[2606:4700:4700::1111]:53".into()
+            })
+        );
+        assert_eq!(
+            Upstream::from_str("tls://1.1.1.1:853#cloudflare-dns.com"),
+            Ok(Upstream::Tls {
+                address: "1.1.1.1:853".into(),
+                server_name: "cloudflare-dns.com".into()
+            })
+        );
+        assert_eq!(
+            Upstream::from_str("tls://dns.quad9.net"),
+            Ok(Upstream::Tls {
+                address: "dns.quad9.net:853".into(),
+                server_name: "dns.quad9.net".into()
+            })
+        );
+        assert_eq!(
+            Upstream::from_str("tls://[2606:4700:4700::1111]:853"),
+            Ok(Upstream::Tls {
+                address: "[2606:4700:4700::1111]:853".into(),
+                server_name: "2606:4700:4700::1111".into()
+            })
+        );
+
+        assert_eq!(
+            Upstream::from_str("quic://dns.adguard-dns.com"),
+            Ok(Upstream::Quic {
+                address: "dns.adguard-dns.com:853".into(),
+                server_name: "dns.adguard-dns.com".into()
+            })
+        );
+        assert_eq!(
+            Upstream::from_str("quic://94.140.14.14:8853#dns.adguard-dns.com"),
+            Ok(Upstream::Quic {
+                address: "94.140.14.14:8853".into(),
+                server_name: "dns.adguard-dns.com".into()
+            })
+        );
+        assert_eq!(
+            Upstream::from_str("https://cloudflare-dns.com/dns-query"),
+            Ok(Upstream::Https {
+                url: "https://cloudflare-dns.com/dns-query".into()
+            })
+        );
+        assert_eq!(
+            Upstream::from_str("https://[2606:4700:4700::1111]:8443/dns-query"),
+            Ok(Upstream::Https {
+                url: "https://[2606:4700:4700::1111]:8443/dns-query".into()
+            })
+        );
+
+        assert_eq!(
+            Upstream::from_str("tls://#cloudflare-dns.com"),
+            Err(UpstreamError::AddressMissing)
+        );
+        assert_eq!(
+            Upstream::from_str("tls://1.1.1.1#cloudflare dns"),
+            Err(UpstreamError::ServerNameInvalid("cloudflare dns".into()))
+        );
+        assert_eq!(
+            Upstream::from_str("ftp://1.1.1.1"),
+            Err(UpstreamError::UnsupportedScheme("ftp".into()))
+        );
+        assert_eq!(
+            Upstream::from_str("https://"),
+            Err(UpstreamError::AddressMissing)
+        );
+        assert_eq!(
+            Upstream::from_str("https://dns example/dns-query"),
+            Err(UpstreamError::UrlInvalid(
+                "https://dns example/dns-query".into()
+            ))
+        );
+    }
+}
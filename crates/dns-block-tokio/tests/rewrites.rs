@@ -0,0 +1,377 @@
+//! Runs the `dns-block-tokio` binary against a stub upstream and checks which queries are answered locally.
+
+use std::{
+    net::SocketAddr,
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use dns::{
+    client::Client,
+    parser::DnsParser,
+    protocol::{
+        answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+        class::CLASS_IN,
+        packet::DnsPacket,
+        query::Query,
+        record_type::RecordType,
+        response_code::ResponseCode,
+    },
+    serialize::{serialize_query, serialize_response},
+};
+use tokio::net::UdpSocket;
+
+/// Kills the server once the test is done, even if it failed
+struct Server {
+    process: Child,
+    address: SocketAddr,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// The address the stub upstream answers all A queries with
+const UPSTREAM_IPV4: [u8; 4] = [192, 0, 2, 1];
+
+/// An upstream that answers A queries with `UPSTREAM_IPV4`, all others without records, and counts them
+async fn stub_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&queries);
+
+    tokio::spawn(async move {
+        let mut query = [0u8; 512];
+        loop {
+            let (len, client) = socket.recv_from(&mut query).await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut packet = DnsParser::new(&query[..len]).parse().unwrap();
+            packet.header.flags.query = false;
+            if packet.question.r#type == RecordType::A {
+                packet.answers.push(ResourceRecord::new(
+                    ResourceRecordMeta {
+                        name: packet.question.domain_name.clone(),
+                        record_type: RecordType::A,
+                        class: CLASS_IN,
+                        ttl: 30,
+                        len: 0,
+                    },
+                    ResourceRecordData::A {
+                        ipv4: UPSTREAM_IPV4.into(),
+                    },
+                ));
+            }
+            let answer = serialize_response(&packet).unwrap();
+            socket.send_to(&answer, client).await.unwrap();
+        }
+    });
+    (address, queries)
+}
+
+async fn start_server(upstream: SocketAddr, args: &[&str]) -> (Server, Client) {
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = SocketAddr::from(([127, 0, 0, 1], port));
+    let server = Server {
+        process: Command::new(env!("CARGO_BIN_EXE_dns-block-tokio"))
+            .args([
+                "--bind-address",
+                "127.0.0.1",
+                "--bind-port",
+                &port.to_string(),
+            ])
+            .args(["--dns-relay", &upstream.to_string(), "--quiet"])
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+        address,
+    };
+
+    // Wait until the server answers
+    let client = Client::new(address)
+        .with_timeout(Duration::from_millis(100))
+        .with_retries(0);
+    for _ in 0..50 {
+        if client
+            .query(&Query::new("ready.test", RecordType::A))
+            .await
+            .is_ok()
+        {
+            return (server, client);
+        }
+    }
+    panic!("dns-block-tokio did not start");
+}
+
+fn records(packet: &DnsPacket) -> Vec<(u32, &ResourceRecordData)> {
+    packet
+        .answers
+        .iter()
+        .map(|record| (record.meta.ttl, &record.value))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_rewritten_domains_are_answered_locally() {
+    let (upstream, upstream_queries) = stub_upstream().await;
+    let (_server, client) = start_server(
+        upstream,
+        &[
+            "--domain-rewrites",
+            "nas.home:10.0.0.2",
+            "--domain-rewrites",
+            "nas.home:10.0.0.3",
+            "--rewrite-ttl",
+            "60",
+        ],
+    )
+    .await;
+    let forwarded = upstream_queries.load(Ordering::SeqCst);
+
+    let packet = client
+        .query(&Query::new("NAS.home", RecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(
+        records(&packet),
+        [
+            (
+                60,
+                &ResourceRecordData::A {
+                    ipv4: [10, 0, 0, 2].into()
+                }
+            ),
+            (
+                60,
+                &ResourceRecordData::A {
+                    ipv4: [10, 0, 0, 3].into()
+                }
+            ),
+        ]
+    );
+
+    for record_type in [RecordType::AAAA, RecordType::HTTPS] {
+        let packet = client
+            .query(&Query::new("nas.home", record_type))
+            .await
+            .unwrap();
+        assert_eq!(
+            packet.header.flags.response_code,
+            u8::from(ResponseCode::NOERROR)
+        );
+        assert!(packet.answers.is_empty());
+    }
+
+    let names = client.resolve_ptr([10, 0, 0, 3].into()).await.unwrap();
+    assert_eq!(names, ["nas.home"]);
+
+    assert_eq!(upstream_queries.load(Ordering::SeqCst), forwarded);
+
+    // Everything else is still forwarded
+    client
+        .query(&Query::new("www.home", RecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(upstream_queries.load(Ordering::SeqCst), forwarded + 1);
+}
+
+#[tokio::test]
+async fn test_rewrites_with_wildcards_and_cnames() {
+    let (upstream, upstream_queries) = stub_upstream().await;
+    let (_server, client) = start_server(
+        upstream,
+        &[
+            "--domain-rewrites",
+            "*.dev.lan:10.0.0.1",
+            "--domain-rewrites",
+            "api.dev.lan:10.0.0.2,fd00::2",
+            "--domain-rewrites",
+            "www.home:CNAME nas.home",
+            "--domain-rewrites",
+            "nas.home:10.0.0.3",
+            "--domain-rewrites",
+            "docs.home:CNAME docs.example.com",
+            "--rewrite-ttl",
+            "60",
+        ],
+    )
+    .await;
+    let forwarded = upstream_queries.load(Ordering::SeqCst);
+
+    for (domain, ipv4) in [
+        ("web.dev.lan", [10, 0, 0, 1]),
+        ("v1.web.dev.lan", [10, 0, 0, 1]),
+        ("api.dev.lan", [10, 0, 0, 2]),
+    ] {
+        let packet = client
+            .query(&Query::new(domain, RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(
+            records(&packet),
+            [(60, &ResourceRecordData::A { ipv4: ipv4.into() })],
+            "{domain}"
+        );
+    }
+
+    let packet = client
+        .query(&Query::new("api.dev.lan", RecordType::AAAA))
+        .await
+        .unwrap();
+    assert_eq!(
+        records(&packet),
+        [(
+            60,
+            &ResourceRecordData::AAAA {
+                ipv6: "fd00::2".parse().unwrap()
+            }
+        )]
+    );
+
+    // The target of a CNAME rewrite is rewritten as well
+    let packet = client
+        .query(&Query::new("www.home", RecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(
+        records(&packet),
+        [
+            (
+                60,
+                &ResourceRecordData::CNAME {
+                    cname: "nas.home".into()
+                }
+            ),
+            (
+                60,
+                &ResourceRecordData::A {
+                    ipv4: [10, 0, 0, 3].into()
+                }
+            ),
+        ]
+    );
+    assert_eq!(upstream_queries.load(Ordering::SeqCst), forwarded);
+
+    // Other targets are resolved upstream
+    let packet = client
+        .query(&Query::new("docs.home", RecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(
+        records(&packet),
+        [
+            (
+                60,
+                &ResourceRecordData::CNAME {
+                    cname: "docs.example.com".into()
+                }
+            ),
+            (
+                30,
+                &ResourceRecordData::A {
+                    ipv4: UPSTREAM_IPV4.into()
+                }
+            ),
+        ]
+    );
+    assert_eq!(packet.answers[1].meta.name, "docs.example.com");
+    assert_eq!(upstream_queries.load(Ordering::SeqCst), forwarded + 1);
+
+    // A wildcard does not match the domain itself
+    let packet = client
+        .query(&Query::new("dev.lan", RecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(packet.answers[0].meta.ttl, 30);
+    assert_eq!(upstream_queries.load(Ordering::SeqCst), forwarded + 2);
+}
+
+#[tokio::test]
+async fn test_malformed_queries_are_rejected() {
+    let (upstream, upstream_queries) = stub_upstream().await;
+    let (server, client) = start_server(upstream, &[]).await;
+    let forwarded = upstream_queries.load(Ordering::SeqCst);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server.address).await.unwrap();
+
+    // A query that announces a question it doesn't carry is answered with FORMERR
+    let mut query = serialize_query(&Query::new("nas.home", RecordType::A), 0x1337);
+    query.truncate(20);
+    socket.send(&query).await.unwrap();
+    let mut answer = [0u8; 512];
+    let len = tokio::time::timeout(Duration::from_secs(1), socket.recv(&mut answer))
+        .await
+        .unwrap()
+        .unwrap();
+    let header = DnsParser::new(&answer[..len]).parse_header().unwrap();
+    assert_eq!(header.request_id, 0x1337);
+    assert!(!header.flags.query);
+    assert_eq!(header.flags.response_code, u8::from(ResponseCode::FORMERR));
+
+    // Datagrams without a complete header are ignored
+    socket.send(&[0x13, 0x37, 0x01]).await.unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), socket.recv(&mut answer))
+            .await
+            .is_err()
+    );
+
+    // The server keeps answering
+    client
+        .query(&Query::new("www.home", RecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(upstream_queries.load(Ordering::SeqCst), forwarded + 1);
+}
+
+#[tokio::test]
+async fn test_cname_rewrite_loops_fail() {
+    let (upstream, upstream_queries) = stub_upstream().await;
+    let (_server, client) = start_server(
+        upstream,
+        &[
+            "--domain-rewrites",
+            "a.home:CNAME b.home",
+            "--domain-rewrites",
+            "b.home:CNAME a.home",
+        ],
+    )
+    .await;
+    let forwarded = upstream_queries.load(Ordering::SeqCst);
+
+    let packet = client
+        .query(&Query::new("a.home", RecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(
+        packet.header.flags.response_code,
+        u8::from(ResponseCode::SERVFAIL)
+    );
+    assert!(packet.answers.is_empty());
+    assert_eq!(upstream_queries.load(Ordering::SeqCst), forwarded);
+}
+
+#[test]
+fn test_cname_rewrite_conflicts_are_rejected() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-block-tokio"))
+        .args(["--domain-rewrites", "www.home:CNAME nas.home"])
+        .args(["--domain-rewrites", "www.home:10.0.0.2"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'www.home' has a CNAME record"), "{stderr}");
+}
@@ -1,29 +1,181 @@
-use dns::protocol::answer::ResourceRecordData;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use dns::{
+    client::Client,
+    protocol::{
+        answer::ResourceRecordData,
+        class::parse_class,
+        query::{Edns, Query},
+        record_type::RecordType,
+    },
+    system::SystemResolver,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct ClientArgs {
+    /// Domain name to resolve, or the IP address to look up with `-x`
+    domain: String,
+
+    /// DNS server to query, the port defaults to 53. Without one, the name servers and search domains of
+    /// /etc/resolv.conf are used
+    #[arg(value_parser = parse_server_arg)]
+    dns_server: Option<SocketAddr>,
+
+    /// Record type to query, e.g. `A`, `AAAA`, `MX` or `TYPE65`
+    #[arg(short = 't', long = "type", default_value = "A", value_parser = clap::value_parser!(RecordType))]
+    record_type: RecordType,
+
+    /// Look up the domain names of the IP address given as domain via its PTR records
+    #[arg(short = 'x', long, default_value_t = false)]
+    reverse: bool,
+
+    /// Class to query, e.g. `IN`, `CH` or `CLASS3`
+    #[arg(short, long, default_value = "IN", value_parser = parse_class_arg)]
+    class: u16,
+
+    /// Do not ask the server to resolve the query recursively
+    #[arg(long, default_value_t = false)]
+    no_recurse: bool,
+
+    /// Ask the server to skip DNSSEC validation
+    #[arg(long, default_value_t = false)]
+    checking_disabled: bool,
+
+    /// Ask the server to include DNSSEC records, which implies `--edns`
+    #[arg(long, default_value_t = false)]
+    dnssec_ok: bool,
+
+    /// Attach an EDNS OPT record to the query
+    #[arg(long, default_value_t = false)]
+    edns: bool,
+
+    /// UDP payload size to advertise via EDNS, which implies `--edns`
+    #[arg(long)]
+    edns_payload_size: Option<u16>,
+}
+
+fn parse_class_arg(input: &str) -> Result<u16, String> {
+    parse_class(input).ok_or_else(|| format!("unknown class '{input}'"))
+}
+
+/// Parses an IP address or host name, with an optional port
+fn parse_server_arg(input: &str) -> Result<SocketAddr, String> {
+    if let Ok(ip) = input.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    let addresses = match input.rsplit_once(':') {
+        Some((_, port)) if port.bytes().all(|byte| byte.is_ascii_digit()) => {
+            input.to_socket_addrs()
+        }
+        _ => (input, 53).to_socket_addrs(),
+    };
+    addresses
+        .map_err(|e| format!("cannot resolve '{input}': {e}"))?
+        .next()
+        .ok_or_else(|| format!("no address for '{input}'"))
+}
 
 fn main() {
-    let mut args = std::env::args();
-    args.next();
-    let domain = args.next().expect("Please specify a domain name");
-    let dns_server = args.next().unwrap_or_else(|| "1.1.1.1".into());
+    let args = ClientArgs::parse();
 
-    println!("Resolving {domain} via DNS {dns_server}\n\n");
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
 
-    let (answers, _) = dns::resolver::resolve_domain(&domain, &dns_server, None, None)
+    if args.reverse {
+        let ip = match args.domain.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => ClientArgs::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("'{}' is not a valid IP address", args.domain),
+                )
+                .exit(),
+        };
+        let names = match args.dns_server {
+            Some(dns_server) => {
+                println!("Resolving PTR {ip} via DNS {dns_server}\n\n");
+                runtime.block_on(Client::new(dns_server).resolve_ptr(ip))
+            }
+            None => {
+                let resolver = SystemResolver::load();
+                println!(
+                    "Resolving PTR {ip} via system DNS {:?}\n\n",
+                    resolver.conf().nameservers
+                );
+                runtime.block_on(resolver.resolve_ptr(ip))
+            }
+        }
         .expect("Error resolving DNS records");
+        for name in names {
+            println!("PTR\t{ip} - {name}");
+        }
+        return;
+    }
+
+    let domain = &args.domain;
+    let record_type = args.record_type;
+    let mut query = Query::new(domain, record_type)
+        .class(args.class)
+        .recursion_desired(!args.no_recurse)
+        .checking_disabled(args.checking_disabled);
+    if args.edns || args.dnssec_ok || args.edns_payload_size.is_some() {
+        let default = Edns::default();
+        query = query.edns(Edns {
+            udp_payload_size: args.edns_payload_size.unwrap_or(default.udp_payload_size),
+            dnssec_ok: args.dnssec_ok,
+        });
+    }
+
+    let answers = match args.dns_server {
+        Some(dns_server) => {
+            println!("Resolving {record_type:?} {domain} via DNS {dns_server}\n\n");
+
+            let packet = runtime
+                .block_on(Client::new(dns_server).query(&query))
+                .expect("Error resolving DNS records");
+            packet.answers
+        }
+        None => {
+            let resolver = SystemResolver::load();
+            println!(
+                "Resolving {record_type:?} {domain} via system DNS {:?}\n\n",
+                resolver.conf().nameservers
+            );
+
+            let packet = runtime
+                .block_on(resolver.query(&query))
+                .expect("Error resolving DNS records");
+            if packet.question.domain_name != *domain {
+                println!("Found {}\n", packet.question.domain_name);
+            }
+            packet.answers
+        }
+    };
 
     for answer in answers {
         let meta = &answer.meta;
+        let record_type = meta.record_type;
         match answer.value {
-            ResourceRecordData::A { ipv4 } => println!("A\t{meta:?} - {ipv4}"),
-            ResourceRecordData::CNAME { cname } => println!("CNAME\t{meta:?} - {cname}"),
-            ResourceRecordData::AAAA { ipv6 } => println!("CNAME\t{meta:?} - {ipv6}"),
-            ResourceRecordData::NS { ns } => println!("CNAME\t{meta:?} - {ns}"),
-            ResourceRecordData::MB { domain_name } => println!("CNAME\t{meta:?} - {domain_name}"),
+            ResourceRecordData::A { ipv4 } => println!("{record_type:?}\t{meta:?} - {ipv4}"),
+            ResourceRecordData::CNAME { cname } => {
+                println!("{record_type:?}\t{meta:?} - {cname}")
+            }
+            ResourceRecordData::AAAA { ipv6 } => println!("{record_type:?}\t{meta:?} - {ipv6}"),
+            ResourceRecordData::NS { ns } => println!("{record_type:?}\t{meta:?} - {ns}"),
+            ResourceRecordData::MB { domain_name } => {
+                println!("{record_type:?}\t{meta:?} - {domain_name}")
+            }
             ResourceRecordData::MX {
                 preference,
                 exchange,
-            } => println!("CNAME\t{meta:?} - {exchange} ({preference})"),
-            ResourceRecordData::PTR { domain_name } => println!("CNAME\t{meta:?} - {domain_name}"),
+            } => println!("{record_type:?}\t{meta:?} - {exchange} ({preference})"),
+            ResourceRecordData::PTR { domain_name } => {
+                println!("{record_type:?}\t{meta:?} - {domain_name}")
+            }
             ResourceRecordData::SOA {
                 mname,
                 rname,
@@ -32,7 +184,14 @@ fn main() {
                 retry: _,
                 expire: _,
                 minimum: _,
-            } => println!("CNAME\t{meta:?} - {mname} - {rname}"),
+            } => println!("{record_type:?}\t{meta:?} - {mname} - {rname}"),
+            ResourceRecordData::TSIG { algorithm, .. } => {
+                println!("{record_type:?}\t{meta:?} - {algorithm}")
+            }
+            ResourceRecordData::Empty => println!("{record_type:?}\t{meta:?}"),
+            ResourceRecordData::Raw { data, .. } => {
+                println!("{record_type:?}\t{meta:?} - {data:02x?}")
+            }
             ResourceRecordData::Unknown => {
                 println!("Unknown record type {:?}", meta.record_type)
             }
@@ -0,0 +1,498 @@
+use std::{
+    fmt::Display,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{net::UdpSocket, time::Instant};
+
+use crate::{
+    parser::DnsParser,
+    protocol::{
+        answer::ResourceRecordData, packet::DnsPacket, query::Query, record_type::RecordType,
+    },
+    reverse::reverse_name,
+    serialize::serialize_query,
+    tcp::TcpConnection,
+};
+
+/// The largest UDP answer we accept, which leaves room for EDNS answers up to the common 4096 byte payload size
+pub const MAX_UDP_RESPONSE: usize = 4096;
+
+/// An asynchronous DNS client that sends queries to a single upstream DNS server via UDP.
+///
+/// Every query uses a cryptographically random request ID and is sent from a freshly bound socket on a
+/// random source port, which makes it harder to spoof answers, see https://datatracker.ietf.org/doc/html/rfc5452.
+/// Datagrams that do not originate from the server or do not match the query's request ID and question are
+/// discarded. Unanswered queries are retried with an exponential backoff.
+/// Truncated answers are repeated via TCP on a connection that is shared between clones of the client.
+#[derive(Debug, Clone)]
+pub struct Client {
+    server: SocketAddr,
+    timeout: Duration,
+    retries: u8,
+    backoff: Duration,
+    tcp: Arc<TcpConnection>,
+}
+
+impl Client {
+    pub fn new(server: SocketAddr) -> Self {
+        Self {
+            server,
+            timeout: Duration::from_secs(2),
+            retries: 2,
+            backoff: Duration::from_millis(100),
+            tcp: Arc::new(TcpConnection::new(server.to_string())),
+        }
+    }
+
+    /// Sets how long to wait for an answer per attempt, which also applies to connecting via TCP
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.tcp = Arc::new(
+            TcpConnection::new(self.server.to_string())
+                .with_connect_timeout(timeout)
+                .with_timeout(timeout),
+        );
+        self
+    }
+
+    /// Sets how often to re-send an unanswered query
+    pub fn with_retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the delay before the first retry, which doubles with every further retry
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Resolves `query` and returns the server's answer
+    pub async fn query(&self, query: &Query) -> Result<DnsPacket, ClientError> {
+        self.exchange(query).await.map(|(packet, _)| packet)
+    }
+
+    /// Looks up the domain names that `ip` points back to via its PTR records, which is empty if there are none
+    pub async fn resolve_ptr(&self, ip: IpAddr) -> Result<Vec<String>, ClientError> {
+        let packet = self
+            .query(&Query::new(&reverse_name(ip), RecordType::PTR))
+            .await?;
+        Ok(ptr_names(packet))
+    }
+
+    /// Resolves `query` and returns the server's answer along with the raw message it was parsed from
+    pub async fn exchange(&self, query: &Query) -> Result<(DnsPacket, Vec<u8>), ClientError> {
+        let request_id = random_id()?;
+        let request = serialize_query(query, request_id);
+
+        let mut backoff = self.backoff;
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            let socket = bind_random_port(&self.server).await?;
+            socket.send_to(&request, self.server).await?;
+
+            if let Some((packet, response)) =
+                self.receive_answer(&socket, request_id, query).await?
+            {
+                if packet.header.flags.truncation {
+                    return self.query_over_tcp(&request, request_id, query).await;
+                }
+                return Ok((packet, response));
+            }
+        }
+
+        Err(ClientError::Timeout)
+    }
+
+    /// Repeats the already serialized `request` via TCP, see https://datatracker.ietf.org/doc/html/rfc7766#section-5
+    async fn query_over_tcp(
+        &self,
+        request: &[u8],
+        request_id: u16,
+        query: &Query,
+    ) -> Result<(DnsPacket, Vec<u8>), ClientError> {
+        let response = self
+            .tcp
+            .exchange(request)
+            .await
+            .map_err(|e| match e.kind() {
+                ErrorKind::TimedOut => ClientError::Timeout,
+                _ => ClientError::Io(e),
+            })?;
+
+        match DnsParser::new(&response).parse() {
+            Ok(packet) if is_answer(&packet, request_id, query) => Ok((packet, response)),
+            _ => Err(ClientError::Io(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "dns client: tcp answer does not match the query",
+            ))),
+        }
+    }
+
+    /// Waits for the matching answer until the timeout elapses and returns `None` if there was none
+    async fn receive_answer(
+        &self,
+        socket: &UdpSocket,
+        request_id: u16,
+        query: &Query,
+    ) -> Result<Option<(DnsPacket, Vec<u8>)>, ClientError> {
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            let mut response = [0; MAX_UDP_RESPONSE];
+            let (len, sender) =
+                match tokio::time::timeout_at(deadline, socket.recv_from(&mut response)).await {
+                    Ok(received) => received?,
+                    Err(_) => return Ok(None),
+                };
+
+            if sender != self.server {
+                continue;
+            }
+
+            // Anything we cannot parse cannot be the answer to our query either
+            let Ok(packet) = DnsParser::new(&response[..len]).parse() else {
+                continue;
+            };
+
+            if is_answer(&packet, request_id, query) {
+                return Ok(Some((packet, response[..len].to_vec())));
+            }
+        }
+    }
+}
+
+/// Whether `packet` answers the question of `query` that was sent with `request_id`
+fn is_answer(packet: &DnsPacket, request_id: u16, query: &Query) -> bool {
+    packet.header.request_id == request_id
+        && !packet.header.flags.query
+        && packet.question.r#type == query.record_type
+        && packet.question.class == query.class
+        && packet
+            .question
+            .domain_name
+            .eq_ignore_ascii_case(query.domain.trim_end_matches('.'))
+}
+
+/// The domain names of the PTR records among the answers of `packet`
+pub(crate) fn ptr_names(packet: DnsPacket) -> Vec<String> {
+    packet
+        .answers
+        .into_iter()
+        .filter_map(|record| match record.value {
+            ResourceRecordData::PTR { domain_name } => Some(domain_name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns a cryptographically random DNS request ID
+pub fn random_id() -> Result<u16, ClientError> {
+    Ok(getrandom::u32().map_err(ClientError::Random)? as u16)
+}
+
+/// Binds a UDP socket to a cryptographically random, unprivileged source port
+async fn bind_random_port(server: &SocketAddr) -> Result<UdpSocket, ClientError> {
+    let ip = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+
+    // Retry a couple of times in case we picked a port that is already in use
+    for _ in 0..8 {
+        let port = 1024 + (getrandom::u32().map_err(ClientError::Random)? % (65536 - 1024)) as u16;
+        match UdpSocket::bind(SocketAddr::new(ip, port)).await {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    // Fall back to an OS-assigned ephemeral port
+    Ok(UdpSocket::bind(SocketAddr::new(ip, 0)).await?)
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// No matching answer arrived within the timeout of any attempt
+    Timeout,
+    /// Sending or receiving a datagram failed
+    Io(std::io::Error),
+    /// The operating system could not provide randomness for request IDs or source ports
+    Random(getrandom::Error),
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Timeout => f.write_str("dns client: query timed out"),
+            ClientError::Io(e) => write!(f, "dns client: {e}"),
+            ClientError::Random(e) => write!(f, "dns client: no randomness available: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use tokio::net::{TcpListener, UdpSocket};
+
+    use crate::{
+        client::{Client, ClientError},
+        parser::{DnsPacketBuffer, DnsParser},
+        protocol::{
+            answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+            query::{Edns, Query},
+            record_type::RecordType,
+        },
+        serialize::{encode_resource_record, serialize_response},
+        tcp::{read_message, write_message},
+    };
+
+    /// Turns a query into an answer with a single A record
+    fn answer(query: &[u8], request_id: u16, domain: &str) -> Vec<u8> {
+        let buffer: DnsPacketBuffer = {
+            let mut buffer = [0; 512];
+            buffer[..query.len()].copy_from_slice(query);
+            buffer
+        };
+        let mut header = DnsParser::new(&buffer).parse_header().unwrap();
+        header.request_id = request_id;
+        header.flags.query = false;
+        header.answer_count = 1;
+        header.additional_count = 0;
+
+        let question_end = 12 + query[12..].iter().position(|&b| b == 0).unwrap() + 1 + 4;
+        let h: [u8; 12] = header.into();
+        let mut response = h.to_vec();
+        response.extend_from_slice(&query[12..question_end]);
+        encode_resource_record(
+            &mut response,
+            &ResourceRecord::new(
+                ResourceRecordMeta {
+                    name: domain.into(),
+                    record_type: RecordType::A,
+                    class: 1,
+                    ttl: 60,
+                    len: 4,
+                },
+                ResourceRecordData::A {
+                    ipv4: [192, 0, 2, 1].into(),
+                },
+            ),
+        )
+        .unwrap();
+        response
+    }
+
+    async fn stub_server() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        (socket, address)
+    }
+
+    #[tokio::test]
+    async fn test_query_ignores_spoofed_answers() {
+        let (server, address) = stub_server().await;
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            let (len, client) = server.recv_from(&mut query).await.unwrap();
+            let request_id = u16::from_be_bytes([query[0], query[1]]);
+
+            // From another source address
+            let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let spoofed = answer(&query[..len], request_id, "example.com");
+            spoofer.send_to(&spoofed, client).await.unwrap();
+            // With another request ID
+            let spoofed = answer(&query[..len], request_id.wrapping_add(1), "example.com");
+            server.send_to(&spoofed, client).await.unwrap();
+            // For another question
+            let mut spoofed = answer(&query[..len], request_id, "example.com");
+            spoofed[13] = b'f';
+            server.send_to(&spoofed, client).await.unwrap();
+
+            let genuine = answer(&query[..len], request_id, "example.com");
+            server.send_to(&genuine, client).await.unwrap();
+        });
+
+        let packet = Client::new(address)
+            .with_retries(0)
+            .query(&Query::new("example.com", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(packet.question.domain_name, "example.com");
+        assert_eq!(packet.answers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_retries_and_times_out() {
+        let (server, address) = stub_server().await;
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&received);
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            loop {
+                let (len, client) = server.recv_from(&mut query).await.unwrap();
+                // Only answer the third attempt
+                if counter.fetch_add(1, Ordering::SeqCst) == 2 {
+                    let request_id = u16::from_be_bytes([query[0], query[1]]);
+                    let genuine = answer(&query[..len], request_id, "example.com");
+                    server.send_to(&genuine, client).await.unwrap();
+                }
+            }
+        });
+
+        let client = Client::new(address)
+            .with_timeout(Duration::from_millis(50))
+            .with_backoff(Duration::from_millis(1));
+
+        let query = Query::new("example.com", RecordType::A);
+        let result = client.clone().with_retries(1).query(&query).await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+
+        let packet = client.with_retries(1).query(&query).await.unwrap();
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_query_record_type_class_and_flags() {
+        let (server, address) = stub_server().await;
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            let (len, client) = server.recv_from(&mut query).await.unwrap();
+
+            let packet = DnsParser::new(&query).parse().unwrap();
+            assert_eq!(packet.question.r#type, RecordType::AAAA);
+            assert_eq!(packet.question.class, 3);
+            assert!(!packet.header.flags.recursion_desired);
+            assert!(packet.header.flags.checking_disabled());
+            let opt = &packet.additional[0].meta;
+            assert_eq!(
+                (opt.record_type, opt.class, opt.ttl),
+                (RecordType::OPT, 1400, 0x8000)
+            );
+
+            // An answer for the INternet class does not match the question
+            let request_id = packet.header.request_id;
+            let mut spoofed = answer(&query[..len], request_id, "example.com");
+            spoofed[len - 11 - 1] = 1;
+            server.send_to(&spoofed, client).await.unwrap();
+
+            let genuine = answer(&query[..len], request_id, "example.com");
+            server.send_to(&genuine, client).await.unwrap();
+        });
+
+        let query = Query::new("example.com", RecordType::AAAA)
+            .class(3)
+            .recursion_desired(false)
+            .checking_disabled(true)
+            .edns(Edns {
+                udp_payload_size: 1400,
+                dnssec_ok: true,
+            });
+        let packet = Client::new(address)
+            .with_retries(0)
+            .query(&query)
+            .await
+            .unwrap();
+        assert_eq!(packet.question.r#type, RecordType::AAAA);
+        assert_eq!(packet.question.class, 3);
+    }
+
+    #[tokio::test]
+    async fn test_query_truncated_answer_via_tcp() {
+        let (server, address) = stub_server().await;
+        let listener = TcpListener::bind(address).await.unwrap();
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            let (len, client) = server.recv_from(&mut query).await.unwrap();
+            let mut truncated = query[..len].to_vec();
+            truncated[2] |= 0b1000_0010;
+            server.send_to(&truncated, client).await.unwrap();
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let query = read_message(&mut stream).await.unwrap();
+            let request_id = u16::from_be_bytes([query[0], query[1]]);
+            let genuine = answer(&query, request_id, "example.com");
+            write_message(&mut stream, &genuine).await.unwrap();
+        });
+
+        let packet = Client::new(address)
+            .with_retries(0)
+            .query(&Query::new("example.com", RecordType::A))
+            .await
+            .unwrap();
+        assert!(!packet.header.flags.truncation);
+        assert_eq!(packet.answers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ptr() {
+        let (server, address) = stub_server().await;
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            let (_, client) = server.recv_from(&mut query).await.unwrap();
+
+            let mut packet = DnsParser::new(&query).parse().unwrap();
+            assert_eq!(packet.question.r#type, RecordType::PTR);
+            assert_eq!(packet.question.domain_name, "1.2.0.192.in-addr.arpa");
+
+            packet.header.flags.query = false;
+            packet.answers = vec![ResourceRecord::new(
+                ResourceRecordMeta {
+                    name: packet.question.domain_name.clone(),
+                    record_type: RecordType::PTR,
+                    class: 1,
+                    ttl: 60,
+                    len: 0,
+                },
+                ResourceRecordData::PTR {
+                    domain_name: "host.example.com".into(),
+                },
+            )];
+            let response = serialize_response(&packet).unwrap();
+            server.send_to(&response, client).await.unwrap();
+        });
+
+        let names = Client::new(address)
+            .with_retries(0)
+            .resolve_ptr([192, 0, 2, 1].into())
+            .await
+            .unwrap();
+        assert_eq!(names, ["host.example.com"]);
+    }
+}
@@ -0,0 +1,61 @@
+use core::fmt::Display;
+
+use crate::protocol::record_type::RecordType;
+
+/// Errors that can occur while parsing or serializing DNS packets.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    /// The packet does not contain a question section, which we need to make sense of it.
+    MissingQuestion,
+    /// The packet uses an opcode that the called parser does not handle, e.g. a DNS UPDATE message that
+    /// was passed to `DnsParser::parse` instead of `DnsParser::parse_update`.
+    UnexpectedOpcode(u8),
+    /// A DNS UPDATE message violates the section format of RFC 2136.
+    MalformedUpdate,
+    /// Resource record data without a known record type was used where its type has to be derived from it.
+    UntypedRecordData,
+    /// Serializing the data of resource records of this type is not supported (yet).
+    UnsupportedRecordData(RecordType),
+    /// A record type mnemonic could not be parsed.
+    UnknownRecordType,
+    /// The message ends before a field that its header or one of its records announced.
+    Truncated,
+    /// The fields of a record's data don't add up to the length of its data.
+    InvalidRecordLength(RecordType),
+    /// A compressed domain name points forward or in a loop instead of to a prior name in the message.
+    InvalidCompressionPointer,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::MissingQuestion => f.write_str("dns packet: question section is missing"),
+            Error::UnexpectedOpcode(opcode) => {
+                write!(f, "dns packet: unexpected opcode {opcode}")
+            }
+            Error::MalformedUpdate => f.write_str("dns update: malformed section"),
+            Error::UntypedRecordData => {
+                f.write_str("dns packet: record type cannot be derived from record data")
+            }
+            Error::UnsupportedRecordData(record_type) => {
+                write!(
+                    f,
+                    "dns packet: cannot serialize {record_type:?} record data"
+                )
+            }
+            Error::UnknownRecordType => f.write_str("dns packet: unknown record type"),
+            Error::Truncated => f.write_str("dns packet: message is truncated"),
+            Error::InvalidRecordLength(record_type) => {
+                write!(
+                    f,
+                    "dns packet: {record_type:?} record data does not match its length"
+                )
+            }
+            Error::InvalidCompressionPointer => {
+                f.write_str("dns packet: invalid domain name compression pointer")
+            }
+        }
+    }
+}
+
+impl core::error::Error for Error {}
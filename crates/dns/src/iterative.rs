@@ -0,0 +1,862 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{
+    client::Client,
+    parser::DnsParser,
+    protocol::{
+        answer::{ResourceRecord, ResourceRecordData},
+        header::Header,
+        packet::DnsPacket,
+        query::Query,
+        record_type::RecordType,
+        response_code::ResponseCode,
+    },
+    serialize::serialize_response,
+};
+
+/// The IPv4 addresses of `a.root-servers.net` to `m.root-servers.net`, see https://www.iana.org/domains/root/servers
+pub const ROOT_HINTS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+/// How many referrals are followed for a single name before giving up
+const MAX_REFERRALS: usize = 16;
+
+/// How many CNAMEs are followed for a single query before giving up
+const MAX_CNAMES: usize = 8;
+
+/// How many name server names may have to be resolved in turn in order to resolve a single name
+const MAX_DEPTH: usize = 4;
+
+/// Resolves queries on its own by walking the delegation chain from the root servers down to the authoritative
+/// servers of a name, see https://datatracker.ietf.org/doc/html/rfc1034#section-5.3.3
+///
+/// Referrals are followed via their glue records, which are only trusted for names within the zone of the server that
+/// sent them. Name servers without glue are resolved on their own. Delegations are cached for the TTL of their NS
+/// records, so later queries start at the closest known zone instead of the root. CNAMEs are chased across zones and
+/// their records are prepended to the final answer.
+#[derive(Debug)]
+pub struct IterativeResolver {
+    roots: Vec<SocketAddr>,
+    port: u16,
+    timeout: Duration,
+    delegations: Mutex<HashMap<String, Delegation>>,
+}
+
+#[derive(Debug, Clone)]
+struct Delegation {
+    servers: Vec<SocketAddr>,
+    expires_at: Instant,
+}
+
+/// The answer of the authoritative server for the last name of a CNAME chain
+struct Resolution {
+    packet: DnsPacket,
+    /// The raw message `packet` was parsed from
+    response: Vec<u8>,
+    /// The answers of the previous names of the chain
+    chain: Vec<ResourceRecord>,
+}
+
+/// A zone that the name servers of its parent zone referred to
+struct Referral {
+    zone: String,
+    name_servers: Vec<String>,
+    ttl: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IterationError {
+    /// None of the name servers of the zone gave a usable answer
+    Unreachable(String),
+    /// Following referrals did not lead to an answer
+    ReferralLimit,
+    /// The CNAME chain is too long or loops
+    CnameLimit,
+    /// Resolving the name servers required resolving too many further name servers
+    DepthLimit,
+}
+
+impl Display for IterationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IterationError::Unreachable(zone) => {
+                write!(f, "dns iterative: no name server of '{zone}' answered")
+            }
+            IterationError::ReferralLimit => f.write_str("dns iterative: too many referrals"),
+            IterationError::CnameLimit => f.write_str("dns iterative: too many CNAMEs"),
+            IterationError::DepthLimit => {
+                f.write_str("dns iterative: too many name servers without glue")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IterationError {}
+
+impl Default for IterativeResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IterativeResolver {
+    /// Starts resolving at the built-in `ROOT_HINTS`
+    pub fn new() -> Self {
+        Self::with_roots(
+            ROOT_HINTS
+                .iter()
+                .map(|&ip| SocketAddr::new(ip.into(), 53))
+                .collect(),
+        )
+    }
+
+    /// Starts resolving at the given root servers
+    pub fn with_roots(roots: Vec<SocketAddr>) -> Self {
+        Self {
+            roots,
+            port: 53,
+            timeout: Duration::from_millis(800),
+            delegations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the port of the name servers that referrals point to, which is 53 outside of tests
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Sets how long to wait for the answer of each name server, before the next one is asked
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Resolves `query` and returns the answer of the authoritative server, with the records of any CNAMEs
+    /// that led there prepended to its answers
+    pub async fn resolve(&self, query: &Query) -> Result<DnsPacket, IterationError> {
+        let resolution = self.resolve_with_depth(query, 0).await?;
+        let mut packet = resolution.packet;
+        packet.question.domain_name = query.domain.trim_end_matches('.').to_string();
+        packet.answers.splice(0..0, resolution.chain);
+        Ok(packet)
+    }
+
+    /// Resolves the raw DNS query `request` and returns the raw answer, which is a SERVFAIL if resolution failed
+    pub async fn exchange(&self, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let request_packet = DnsParser::new(request).parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "dns iterative: invalid query")
+        })?;
+        let query = Query::new(
+            &request_packet.question.domain_name,
+            request_packet.question.r#type,
+        )
+        .class(request_packet.question.class);
+
+        let mut header = request_packet.header.clone();
+        header.flags.query = false;
+        header.flags.recursion_available = true;
+
+        let resolution = match self.resolve_with_depth(&query, 0).await {
+            Ok(resolution) => resolution,
+            Err(_) => {
+                header.flags.response_code = ResponseCode::SERVFAIL.into();
+                return serialize_failure(&request_packet, header);
+            }
+        };
+        header.flags.response_code = resolution.packet.header.flags.response_code;
+
+        // The authoritative answer can be relayed unchanged, which keeps records the serializer does not understand
+        if resolution.chain.is_empty() {
+            // Only the request ID and flags are patched, the section counts stay those of the answer
+            let mut response = resolution.response;
+            let h: [u8; 12] = header.into();
+            response[..4].copy_from_slice(&h[..4]);
+            return Ok(response);
+        }
+
+        let mut packet = resolution.packet;
+        packet.header = header.clone();
+        packet.question = request_packet.question.clone();
+        packet.answers.splice(0..0, resolution.chain);
+        // Additional records belong to the last name only and are not needed to answer the query
+        packet.additional.clear();
+        match serialize_response(&packet) {
+            Ok(response) => Ok(response),
+            Err(_) => {
+                header.flags.response_code = ResponseCode::SERVFAIL.into();
+                serialize_failure(&request_packet, header)
+            }
+        }
+    }
+
+    async fn resolve_with_depth(
+        &self,
+        query: &Query,
+        depth: usize,
+    ) -> Result<Resolution, IterationError> {
+        if depth > MAX_DEPTH {
+            return Err(IterationError::DepthLimit);
+        }
+
+        let mut chain: Vec<ResourceRecord> = vec![];
+        let mut names = vec![query.domain.trim_end_matches('.').to_ascii_lowercase()];
+        for _ in 0..=MAX_CNAMES {
+            let name = names.last().unwrap();
+            let authoritative_query = Query::new(name, query.record_type)
+                .class(query.class)
+                .recursion_desired(false);
+            let (packet, response) = self.query_zone(&authoritative_query, depth).await?;
+
+            match cname_target(&packet, name, query.record_type) {
+                Some(target)
+                    if packet.header.flags.response_code == u8::from(ResponseCode::NOERROR) =>
+                {
+                    if names.contains(&target) {
+                        return Err(IterationError::CnameLimit);
+                    }
+                    chain.extend(packet.answers);
+                    names.push(target);
+                }
+                _ => {
+                    return Ok(Resolution {
+                        packet,
+                        response,
+                        chain,
+                    });
+                }
+            }
+        }
+
+        Err(IterationError::CnameLimit)
+    }
+
+    /// Follows the referrals for the name of `query` until a server answers it
+    async fn query_zone(
+        &self,
+        query: &Query,
+        depth: usize,
+    ) -> Result<(DnsPacket, Vec<u8>), IterationError> {
+        let name = &query.domain;
+        let (mut zone, mut servers) = self.closest_delegation(name);
+
+        for _ in 0..MAX_REFERRALS {
+            let (packet, response) = self
+                .query_servers(&servers, query)
+                .await
+                .ok_or_else(|| IterationError::Unreachable(zone.clone()))?;
+
+            let referral = match referral(&packet, name, &zone) {
+                Ok(Some(referral)) => referral,
+                Ok(None) => return Ok((packet, response)),
+                // A server that refers to its own zone or one above does not know the zone
+                Err(()) => return Err(IterationError::Unreachable(zone)),
+            };
+
+            let mut addresses = self.glue(&packet, &referral, &zone);
+            if addresses.is_empty() {
+                addresses = self.resolve_name_servers(&referral, depth).await?;
+            }
+            if addresses.is_empty() {
+                return Err(IterationError::Unreachable(referral.zone));
+            }
+
+            self.delegations.lock().unwrap().insert(
+                referral.zone.clone(),
+                Delegation {
+                    servers: addresses.clone(),
+                    expires_at: Instant::now() + Duration::from_secs(referral.ttl.into()),
+                },
+            );
+            zone = referral.zone;
+            servers = addresses;
+        }
+
+        Err(IterationError::ReferralLimit)
+    }
+
+    /// Returns the most specific zone of `name` with a cached delegation and its servers, or the root zone
+    fn closest_delegation(&self, name: &str) -> (String, Vec<SocketAddr>) {
+        let mut delegations = self.delegations.lock().unwrap();
+        let now = Instant::now();
+        delegations.retain(|_, delegation| delegation.expires_at > now);
+
+        let mut candidate = name;
+        loop {
+            if let Some(delegation) = delegations.get(candidate) {
+                return (candidate.to_string(), delegation.servers.clone());
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) => candidate = parent,
+                None => return (String::new(), self.roots.clone()),
+            }
+        }
+    }
+
+    /// Asks `servers` one after another until one gives a usable answer
+    async fn query_servers(
+        &self,
+        servers: &[SocketAddr],
+        query: &Query,
+    ) -> Option<(DnsPacket, Vec<u8>)> {
+        for &server in servers {
+            let client = Client::new(server)
+                .with_timeout(self.timeout)
+                .with_retries(1);
+            match client.exchange(query).await {
+                Ok((packet, response))
+                    if packet.header.flags.response_code == u8::from(ResponseCode::NOERROR)
+                        || packet.header.flags.response_code
+                            == u8::from(ResponseCode::NXDOMAIN) =>
+                {
+                    return Some((packet, response));
+                }
+                // Lame or broken servers are skipped
+                _ => continue,
+            }
+        }
+        None
+    }
+
+    /// Returns the addresses of the referred name servers, which `packet` sent along, as long as they are within
+    /// `zone`, since servers are not authoritative for anything else
+    fn glue(&self, packet: &DnsPacket, referral: &Referral, zone: &str) -> Vec<SocketAddr> {
+        let mut addresses: Vec<SocketAddr> = packet
+            .additional
+            .iter()
+            .filter(|record| {
+                referral
+                    .name_servers
+                    .iter()
+                    .any(|ns| ns.eq_ignore_ascii_case(&record.meta.name))
+                    && is_subdomain(&record.meta.name, zone)
+            })
+            .filter_map(|record| self.address(record))
+            .collect();
+        // IPv4 first, since it is more likely to be reachable
+        addresses.sort_by_key(|address| address.is_ipv6());
+        addresses
+    }
+
+    /// Resolves the referred name servers one after another, until one of them has an address
+    async fn resolve_name_servers(
+        &self,
+        referral: &Referral,
+        depth: usize,
+    ) -> Result<Vec<SocketAddr>, IterationError> {
+        let mut error = None;
+        for name_server in &referral.name_servers {
+            // Name servers within the zone they serve cannot be resolved without glue
+            if is_subdomain(name_server, &referral.zone) {
+                continue;
+            }
+
+            let query = Query::new(name_server, RecordType::A);
+            match Box::pin(self.resolve_with_depth(&query, depth + 1)).await {
+                Ok(resolution) => {
+                    let addresses: Vec<SocketAddr> = resolution
+                        .packet
+                        .answers
+                        .iter()
+                        .filter_map(|record| self.address(record))
+                        .collect();
+                    if !addresses.is_empty() {
+                        return Ok(addresses);
+                    }
+                }
+                Err(IterationError::DepthLimit) => error = Some(IterationError::DepthLimit),
+                Err(_) => continue,
+            }
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn address(&self, record: &ResourceRecord) -> Option<SocketAddr> {
+        match record.value {
+            ResourceRecordData::A { ipv4 } => Some(SocketAddr::new(ipv4.into(), self.port)),
+            ResourceRecordData::AAAA { ipv6 } => Some(SocketAddr::new(ipv6.into(), self.port)),
+            _ => None,
+        }
+    }
+}
+
+/// Classifies `packet` as a referral to a zone between `zone` and `name`, returns `None` if it is an answer, and
+/// fails if it refers to a zone that is not below `zone`
+fn referral(packet: &DnsPacket, name: &str, zone: &str) -> Result<Option<Referral>, ()> {
+    if packet.header.flags.response_code != u8::from(ResponseCode::NOERROR)
+        || !packet.answers.is_empty()
+        || packet
+            .authorities
+            .iter()
+            .any(|record| record.meta.record_type == RecordType::SOA)
+    {
+        return Ok(None);
+    }
+
+    let Some(first) = packet
+        .authorities
+        .iter()
+        .find(|record| record.meta.record_type == RecordType::NS)
+    else {
+        return Ok(None);
+    };
+    let child = first.meta.name.to_ascii_lowercase();
+    if !is_subdomain(name, &child) || child.len() <= zone.len() || !is_subdomain(&child, zone) {
+        return Err(());
+    }
+
+    let delegation = packet
+        .authorities
+        .iter()
+        .filter(|record| record.meta.name.eq_ignore_ascii_case(&child));
+    let name_servers = delegation
+        .clone()
+        .filter_map(|record| match &record.value {
+            ResourceRecordData::NS { ns } => Some(ns.to_ascii_lowercase()),
+            _ => None,
+        })
+        .collect();
+    let ttl = delegation.map(|record| record.meta.ttl).min().unwrap_or(0);
+
+    Ok(Some(Referral {
+        zone: child,
+        name_servers,
+        ttl,
+    }))
+}
+
+/// Follows the CNAMEs for `name` within the answers of `packet` and returns the name that is left to resolve,
+/// or `None` if `packet` already answers the query or has no CNAME for it
+fn cname_target(packet: &DnsPacket, name: &str, record_type: RecordType) -> Option<String> {
+    if record_type == RecordType::CNAME {
+        return None;
+    }
+
+    let mut current = name.to_string();
+    let mut visited = vec![];
+    for _ in 0..packet.answers.len() {
+        let owned_by_current =
+            |record: &&ResourceRecord| record.meta.name.eq_ignore_ascii_case(&current);
+        if packet
+            .answers
+            .iter()
+            .filter(owned_by_current)
+            .any(|record| record.meta.record_type == record_type)
+        {
+            return None;
+        }
+
+        match packet
+            .answers
+            .iter()
+            .filter(owned_by_current)
+            .find_map(|record| match &record.value {
+                ResourceRecordData::CNAME { cname } => Some(cname.to_ascii_lowercase()),
+                _ => None,
+            }) {
+            // A loop within the answer is reported as a target that the caller already followed
+            Some(target) if visited.contains(&target) || target == name => return Some(target),
+            Some(target) => {
+                visited.push(target.clone());
+                current = target;
+            }
+            None => break,
+        }
+    }
+
+    (current != name).then_some(current)
+}
+
+/// Whether `name` equals `zone` or is below it, where the empty string is the root zone
+fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    zone.is_empty()
+        || name.eq_ignore_ascii_case(zone)
+        || (name.len() > zone.len()
+            && name.as_bytes()[name.len() - zone.len() - 1] == b'.'
+            && name[name.len() - zone.len()..].eq_ignore_ascii_case(zone))
+}
+
+fn serialize_failure(request_packet: &DnsPacket, header: Header) -> Result<Vec<u8>, io::Error> {
+    let packet = DnsPacket {
+        header,
+        question: request_packet.question.clone(),
+        answers: vec![],
+        authorities: vec![],
+        additional: vec![],
+    };
+    serialize_response(&packet)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "dns iterative: invalid query"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use tokio::net::UdpSocket;
+
+    use crate::{
+        iterative::{IterationError, IterativeResolver, is_subdomain},
+        parser::DnsParser,
+        protocol::{
+            answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+            header::{Flags, Header},
+            packet::DnsPacket,
+            query::Query,
+            record_type::RecordType,
+            response_code::ResponseCode,
+        },
+        serialize::{serialize_query, serialize_response},
+    };
+
+    fn record(name: &str, value: ResourceRecordData) -> ResourceRecord {
+        ResourceRecord::new(
+            ResourceRecordMeta {
+                name: name.into(),
+                record_type: value.record_type().unwrap(),
+                class: 1,
+                ttl: 300,
+                len: 0,
+            },
+            value,
+        )
+    }
+
+    fn a(name: &str, ip: [u8; 4]) -> ResourceRecord {
+        record(name, ResourceRecordData::A { ipv4: ip.into() })
+    }
+
+    fn cname(name: &str, target: &str) -> ResourceRecord {
+        record(
+            name,
+            ResourceRecordData::CNAME {
+                cname: target.into(),
+            },
+        )
+    }
+
+    /// A delegation of a child zone to name servers, with the glue to send along
+    struct Delegation {
+        zone: &'static str,
+        name_servers: Vec<&'static str>,
+        glue: Vec<ResourceRecord>,
+    }
+
+    /// An authoritative server that answers with its records, refers to its delegations and counts its queries
+    async fn authoritative_server(
+        ip: [u8; 4],
+        port: u16,
+        records: Vec<ResourceRecord>,
+        delegations: Vec<Delegation>,
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::from(ip).into(), port))
+            .await
+            .unwrap();
+        let address = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&queries);
+
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            loop {
+                let (_, client) = socket.recv_from(&mut query).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let request = DnsParser::new(&query).parse().unwrap();
+                assert!(!request.header.flags.recursion_desired);
+                let name = request.question.domain_name.to_ascii_lowercase();
+
+                let mut response = DnsPacket {
+                    header: Header {
+                        request_id: request.header.request_id,
+                        flags: Flags {
+                            query: false,
+                            authoritative_answer: true,
+                            ..Flags::default()
+                        },
+                        ..Header::default()
+                    },
+                    question: request.question.clone(),
+                    answers: vec![],
+                    authorities: vec![],
+                    additional: vec![],
+                };
+
+                if let Some(delegation) = delegations
+                    .iter()
+                    .find(|delegation| is_subdomain(&name, delegation.zone))
+                {
+                    response.header.flags.authoritative_answer = false;
+                    response.authorities = delegation
+                        .name_servers
+                        .iter()
+                        .map(|&ns| {
+                            record(delegation.zone, ResourceRecordData::NS { ns: ns.into() })
+                        })
+                        .collect();
+                    response.additional = delegation.glue.clone();
+                } else {
+                    let owned: Vec<&ResourceRecord> = records
+                        .iter()
+                        .filter(|record| record.meta.name == name)
+                        .collect();
+                    response.answers = owned
+                        .iter()
+                        .filter(|record| record.meta.record_type == request.question.r#type)
+                        .map(|&record| record.clone())
+                        .collect();
+                    if response.answers.is_empty() {
+                        response.answers = owned
+                            .iter()
+                            .filter(|record| record.meta.record_type == RecordType::CNAME)
+                            .map(|&record| record.clone())
+                            .collect();
+                    }
+                    if owned.is_empty() {
+                        response.header.flags.response_code = ResponseCode::NXDOMAIN.into();
+                    }
+                }
+
+                let response = serialize_response(&response).unwrap();
+                socket.send_to(&response, client).await.unwrap();
+            }
+        });
+        (address, queries)
+    }
+
+    /// A hierarchy of a root, `test.` and `example.` server on distinct loopback addresses, where the name
+    /// server of `example.` lives in `test.` and thus has no glue
+    struct Hierarchy {
+        resolver: IterativeResolver,
+        root_queries: Arc<AtomicUsize>,
+        test_queries: Arc<AtomicUsize>,
+    }
+
+    async fn hierarchy() -> Hierarchy {
+        // The port is shared by all servers, since referrals only carry addresses
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let (root, root_queries) = authoritative_server(
+            [127, 0, 0, 1],
+            port,
+            vec![],
+            vec![
+                Delegation {
+                    zone: "test",
+                    name_servers: vec!["ns1.test"],
+                    // Glue outside of the root zone's bailiwick does not exist, glue for another zone is ignored
+                    glue: vec![
+                        a("ns1.test", [127, 0, 0, 2]),
+                        a("www.example", [192, 0, 2, 66]),
+                    ],
+                },
+                Delegation {
+                    zone: "example",
+                    name_servers: vec!["ns.hosting.test"],
+                    glue: vec![],
+                },
+            ],
+        )
+        .await;
+        let (_, test_queries) = authoritative_server(
+            [127, 0, 0, 2],
+            port,
+            vec![
+                a("ns1.test", [127, 0, 0, 2]),
+                a("ns.hosting.test", [127, 0, 0, 3]),
+                cname("www.shop.test", "cdn.shop.test"),
+                cname("cdn.shop.test", "www.example"),
+                cname("loop.test", "loop.test"),
+            ],
+            vec![],
+        )
+        .await;
+        authoritative_server(
+            [127, 0, 0, 3],
+            port,
+            vec![
+                a("www.example", [192, 0, 2, 80]),
+                a("www.example", [192, 0, 2, 81]),
+            ],
+            vec![],
+        )
+        .await;
+
+        Hierarchy {
+            resolver: IterativeResolver::with_roots(vec![root]).with_port(port),
+            root_queries,
+            test_queries,
+        }
+    }
+
+    fn addresses(packet: &DnsPacket) -> Vec<Ipv4Addr> {
+        packet
+            .answers
+            .iter()
+            .filter_map(|record| match record.value {
+                ResourceRecordData::A { ipv4 } => Some(ipv4),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_follows_referral_with_glue() {
+        let hierarchy = hierarchy().await;
+        let packet = hierarchy
+            .resolver
+            .resolve(&Query::new("ns1.test", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(addresses(&packet), [Ipv4Addr::new(127, 0, 0, 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_name_server_without_glue() {
+        let hierarchy = hierarchy().await;
+        let packet = hierarchy
+            .resolver
+            .resolve(&Query::new("www.example.", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(packet.question.domain_name, "www.example");
+        assert_eq!(
+            addresses(&packet),
+            [Ipv4Addr::new(192, 0, 2, 80), Ipv4Addr::new(192, 0, 2, 81)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chases_cnames_across_zones() {
+        let hierarchy = hierarchy().await;
+        let packet = hierarchy
+            .resolver
+            .resolve(&Query::new("www.shop.test", RecordType::A))
+            .await
+            .unwrap();
+
+        let chain: Vec<&ResourceRecordData> = packet.answers.iter().map(|r| &r.value).collect();
+        assert_eq!(
+            chain[..2],
+            [
+                &ResourceRecordData::CNAME {
+                    cname: "cdn.shop.test".into()
+                },
+                &ResourceRecordData::CNAME {
+                    cname: "www.example".into()
+                }
+            ]
+        );
+        assert_eq!(
+            addresses(&packet),
+            [Ipv4Addr::new(192, 0, 2, 80), Ipv4Addr::new(192, 0, 2, 81)]
+        );
+
+        assert_eq!(
+            hierarchy
+                .resolver
+                .resolve(&Query::new("loop.test", RecordType::A))
+                .await
+                .unwrap_err(),
+            IterationError::CnameLimit
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_caches_delegations() {
+        let hierarchy = hierarchy().await;
+        hierarchy
+            .resolver
+            .resolve(&Query::new("ns1.test", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(hierarchy.root_queries.load(Ordering::SeqCst), 1);
+        assert_eq!(hierarchy.test_queries.load(Ordering::SeqCst), 1);
+
+        let packet = hierarchy
+            .resolver
+            .resolve(&Query::new("missing.test", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(
+            packet.header.flags.response_code,
+            u8::from(ResponseCode::NXDOMAIN)
+        );
+        assert_eq!(hierarchy.root_queries.load(Ordering::SeqCst), 1);
+        assert_eq!(hierarchy.test_queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_answers_raw_queries() {
+        let hierarchy = hierarchy().await;
+        let request = serialize_query(&Query::new("www.shop.test", RecordType::A), 0x1337);
+        let response = hierarchy.resolver.exchange(&request).await.unwrap();
+        let packet = DnsParser::new(&response).parse().unwrap();
+        assert_eq!(packet.header.request_id, 0x1337);
+        assert!(packet.header.flags.recursion_available);
+        assert_eq!(packet.question.domain_name, "www.shop.test");
+        assert_eq!(packet.answers.len(), 4);
+
+        let request = serialize_query(&Query::new("ns1.test", RecordType::A), 0x4242);
+        let response = hierarchy.resolver.exchange(&request).await.unwrap();
+        let packet = DnsParser::new(&response).parse().unwrap();
+        assert_eq!(packet.header.request_id, 0x4242);
+        assert!(!packet.header.flags.authoritative_answer);
+        assert_eq!(addresses(&packet), [Ipv4Addr::new(127, 0, 0, 2)]);
+
+        let unreachable = IterativeResolver::with_roots(vec!["127.0.0.1:9".parse().unwrap()])
+            .with_timeout(std::time::Duration::from_millis(50));
+        let response = unreachable.exchange(&request).await.unwrap();
+        let packet = DnsParser::new(&response).parse().unwrap();
+        assert_eq!(
+            packet.header.flags.response_code,
+            u8::from(ResponseCode::SERVFAIL)
+        );
+    }
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("www.example.com", ""));
+        assert!(is_subdomain("www.example.com", "example.com"));
+        assert!(is_subdomain("WWW.Example.com.", "example.COM"));
+        assert!(is_subdomain("example.com", "example.com"));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+        assert!(!is_subdomain("com", "example.com"));
+    }
+}
@@ -1,4 +1,35 @@
+//! Constructing and consuming DNS packets.
+//!
+//! The `protocol`, `parser`, `serialize` and `reverse` modules only depend on `core` and `alloc`, so they can be used
+//! on embedded targets and in WASM by disabling the default `std` feature. Networking lives in the `client`,
+//! `resolver`, `iterative`, `system` and `tcp` modules, which require the `client` feature, and the `tls` and `quic` modules,
+//! which require the `tls` and `quic` features.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod error;
+#[cfg(feature = "client")]
+pub mod iterative;
 pub mod parser;
 pub mod protocol;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "client")]
 pub mod resolver;
+pub mod reverse;
 pub mod serialize;
+#[cfg(feature = "client")]
+pub mod system;
+#[cfg(feature = "client")]
+pub mod tcp;
+#[cfg(all(test, feature = "tls"))]
+mod test_support;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod tsig;
+
+pub use error::Error;
+pub use reverse::reverse_name;
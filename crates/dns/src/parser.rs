@@ -1,18 +1,27 @@
-use std::time::Duration;
-
-use crate::protocol::{
-    answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
-    header::{Flags, Header},
-    packet::DnsPacket,
-    question::Question,
-    record_type::RecordType,
+use alloc::{string::String, vec, vec::Vec};
+use core::time::Duration;
+
+use crate::{
+    Error,
+    protocol::{
+        answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+        header::{Flags, Header},
+        opcode::Opcode,
+        packet::DnsPacket,
+        question::Question,
+        record_type::RecordType,
+        update::{Prerequisite, Update, UpdatePacket, Zone},
+    },
 };
 
+/// A buffer that fits any plain DNS message sent via UDP, see https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.1
 pub type DnsPacketBuffer = [u8; 512];
 
+/// Parses a DNS message, which usually lives in a `DnsPacketBuffer`, but may also be larger if it was received
+/// via TCP or with EDNS.
 #[derive(Debug)]
 pub struct DnsParser<'a> {
-    pub buf: &'a DnsPacketBuffer,
+    pub buf: &'a [u8],
     position: usize,
     /// Internal metadata about the buffer indices of the answer TTL fields live
     answer_ttl_indices: Vec<usize>,
@@ -39,7 +48,7 @@ impl<const N: usize> Collate for [u8; N] {
 }
 
 impl<'a> DnsParser<'a> {
-    pub fn new(buf: &'a DnsPacketBuffer) -> Self {
+    pub fn new(buf: &'a [u8]) -> Self {
         Self {
             buf,
             position: 0,
@@ -47,90 +56,100 @@ impl<'a> DnsParser<'a> {
         }
     }
 
-    fn peek(&self, n: usize) -> &[u8] {
-        &self.buf[self.position..self.position + n]
+    /// The next `n` bytes, or `Error::Truncated` if the message ends before them
+    fn peek(&self, n: usize) -> Result<&[u8], Error> {
+        self.buf
+            .get(self.position..self.position + n)
+            .ok_or(Error::Truncated)
     }
 
-    fn advance(&mut self, n: usize) -> &[u8] {
-        let out = &self.buf[self.position..self.position + n];
+    fn advance(&mut self, n: usize) -> Result<&[u8], Error> {
+        let out = self
+            .buf
+            .get(self.position..self.position + n)
+            .ok_or(Error::Truncated)?;
         self.position += n;
-        out
+        Ok(out)
     }
 
-    fn advance_n<const N: usize>(&mut self) -> [u8; N] {
-        let out = self.buf[self.position..self.position + N]
-            .try_into()
-            .unwrap();
-        self.position += N;
-        out
+    fn advance_n<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let out = self.advance(N)?.try_into().unwrap();
+        Ok(out)
     }
 
-    fn parse_domain_name(&mut self) -> String {
+    fn parse_domain_name(&mut self) -> Result<String, Error> {
         // parse query (again)
         // https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1
         // https://github.com/EmilHernvall/dnsguide/blob/master/chapter1.md
         let mut name = String::new();
-        self.parse_domain_name_rec(&mut name);
-        name
+        self.parse_domain_name_rec(&mut name, self.position)?;
+        Ok(name)
     }
 
-    fn parse_domain_name_rec(&mut self, buf: &mut String) {
+    /// Compression pointers must point before `limit`, the start of the labels that lead to them, so each pointer
+    /// points further back than the one before and a loop of pointers can't recurse forever.
+    fn parse_domain_name_rec(&mut self, buf: &mut String, limit: usize) -> Result<(), Error> {
         // parse query (again)
         // https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1
         // https://github.com/EmilHernvall/dnsguide/blob/master/chapter1.md
-        if self.peek(1).collate().eq(&0xC0) {
-            let offset = self.advance_n::<2>().collate() ^ 0xC000;
+        if is_compression_pointer(self.peek(1)?[0]) {
+            let offset = self.advance_n::<2>()?.collate() & 0x3FFF;
+            if offset >= limit {
+                return Err(Error::InvalidCompressionPointer);
+            }
             let old_position = self.position;
             self.position = offset;
-            self.parse_domain_name_rec(buf);
+            let parsed = self.parse_domain_name_rec(buf, offset);
             self.position = old_position;
+            parsed
         } else {
-            self.parse_domain_name_inline(buf);
+            self.parse_domain_name_inline(buf, limit)
         }
     }
 
-    fn parse_domain_name_inline(&mut self, buf: &mut String) {
-        let mut next = self.peek(1).collate();
-        if next.eq(&192) {
-            return;
-        }
+    fn parse_domain_name_inline(&mut self, buf: &mut String, limit: usize) -> Result<(), Error> {
+        let mut next = self.peek(1)?[0];
         // TODO: look to do this in one operation
-        while next > 0 && next.ne(&192) {
-            self.advance_n::<1>().collate();
-            for c in self.advance(next) {
+        while next > 0 {
+            self.advance_n::<1>()?;
+            for c in self.advance(next.into())? {
                 buf.push(*c as char);
             }
-            next = self.peek(1).collate();
+            next = self.peek(1)?[0];
             if next > 0 {
                 buf.push('.');
             }
-            if next.eq(&192) {
-                self.parse_domain_name_rec(buf);
-                return;
+            if is_compression_pointer(next) {
+                return self.parse_domain_name_rec(buf, limit);
             }
         }
         // skip 0 byte at the end
         self.position += 1;
+        Ok(())
     }
 
     // Question section format https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.2
-    pub fn parse_question(&mut self) -> Question {
-        Question {
-            domain_name: self.parse_domain_name(),
-            r#type: RecordType::from(self.advance_n::<2>().collate() as u16),
-            class: self.advance_n::<2>().collate() as u16,
-        }
+    pub fn parse_question(&mut self) -> Result<Question, Error> {
+        Ok(Question {
+            domain_name: self.parse_domain_name()?,
+            r#type: RecordType::from(self.advance_n::<2>()?.collate() as u16),
+            class: self.advance_n::<2>()?.collate() as u16,
+        })
     }
 
     // Resource section format https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.3
-    pub fn parse_resource_record(
-        &mut self,
-    ) -> Result<ResourceRecord, Box<dyn std::error::Error + Send + Sync>> {
-        let name = self.parse_domain_name();
-        let record_type: RecordType = (self.advance_n::<2>().collate() as u16).into();
-        let class = self.advance_n::<2>().collate() as u16;
-        let ttl = self.record_ttl();
-        let len = self.advance_n::<2>().collate() as u16;
+    pub fn parse_resource_record(&mut self) -> Result<ResourceRecord, Error> {
+        let name = self.parse_domain_name()?;
+        let record_type: RecordType = (self.advance_n::<2>()?.collate() as u16).into();
+        let class = self.advance_n::<2>()?.collate() as u16;
+        // The TTL field of an OPT pseudo record carries the extended RCODE and EDNS flags instead of a TTL,
+        // see https://datatracker.ietf.org/doc/html/rfc6891#section-6.1.3, so it must never be reduced.
+        let ttl = if record_type == RecordType::OPT {
+            self.advance_n::<4>()?.collate() as u32
+        } else {
+            self.record_ttl()?
+        };
+        let len = self.advance_n::<2>()?.collate() as u16;
 
         let meta = ResourceRecordMeta {
             name,
@@ -144,9 +163,11 @@ impl<'a> DnsParser<'a> {
         // of how to parse certain record types
         // More record types to parse at some point: https://en.wikipedia.org/wiki/List_of_DNS_record_types
         let resource_record_data = match record_type {
+            // Prerequisites and deletions in DNS UPDATE messages carry no RR data at all, regardless of their type
+            _ if meta.len == 0 => ResourceRecordData::Empty,
             // CNAME https://datatracker.ietf.org/doc/html/rfc1035#section-3.3.1
             RecordType::CNAME => {
-                let cname = self.parse_domain_name();
+                let cname = self.parse_domain_name()?;
                 ResourceRecordData::CNAME { cname }
             }
             // // HINFO https://datatracker.ietf.org/doc/html/rfc1035#section-3.3.2
@@ -165,8 +186,8 @@ impl<'a> DnsParser<'a> {
             // RecordType::MR => todo!(),
             // // MX https://datatracker.ietf.org/doc/html/rfc1035#section-3.3.9
             RecordType::MX => {
-                let preference = self.advance_n::<2>().collate() as u16;
-                let exchange = self.parse_domain_name();
+                let preference = self.advance_n::<2>()?.collate() as u16;
+                let exchange = self.parse_domain_name()?;
                 ResourceRecordData::MX {
                     preference,
                     exchange,
@@ -176,23 +197,23 @@ impl<'a> DnsParser<'a> {
             // RecordType::NULL => todo!(),
             // NS https://datatracker.ietf.org/doc/html/rfc1035#section-3.3.11
             RecordType::NS => {
-                let ns = self.parse_domain_name();
+                let ns = self.parse_domain_name()?;
                 ResourceRecordData::NS { ns }
             }
             // PTR https://datatracker.ietf.org/doc/html/rfc1035#section-3.3.12
             RecordType::PTR => {
-                let domain_name = self.parse_domain_name();
+                let domain_name = self.parse_domain_name()?;
                 ResourceRecordData::PTR { domain_name }
             }
             // SOA https://datatracker.ietf.org/doc/html/rfc1035#section-3.3.13
             RecordType::SOA => {
-                let mname = self.parse_domain_name();
-                let rname = self.parse_domain_name();
-                let serial = self.advance_n::<4>().collate() as u32;
-                let refresh = self.advance_n::<4>().collate() as u32;
-                let retry = self.advance_n::<4>().collate() as u32;
-                let expire = self.advance_n::<4>().collate() as u32;
-                let minimum = self.advance_n::<4>().collate() as u32;
+                let mname = self.parse_domain_name()?;
+                let rname = self.parse_domain_name()?;
+                let serial = self.advance_n::<4>()?.collate() as u32;
+                let refresh = self.advance_n::<4>()?.collate() as u32;
+                let retry = self.advance_n::<4>()?.collate() as u32;
+                let expire = self.advance_n::<4>()?.collate() as u32;
+                let minimum = self.advance_n::<4>()?.collate() as u32;
 
                 ResourceRecordData::SOA {
                     mname,
@@ -204,28 +225,61 @@ impl<'a> DnsParser<'a> {
                     minimum,
                 }
             }
+            // TSIG https://datatracker.ietf.org/doc/html/rfc8945#section-4.2
+            RecordType::TSIG => {
+                // The MAC and other data are sized by the record itself, so they must fit into its RDATA length
+                let end = self.position + usize::from(meta.len);
+                let invalid_length = Error::InvalidRecordLength(RecordType::TSIG);
+                let algorithm = self.parse_domain_name()?;
+                let time_signed = self.advance_n::<6>()?.collate() as u64;
+                let fudge = self.advance_n::<2>()?.collate() as u16;
+                let mac_size = self.advance_n::<2>()?.collate();
+                if self.position + mac_size > end {
+                    return Err(invalid_length);
+                }
+                let mac = self.advance(mac_size)?.to_vec();
+                let original_id = self.advance_n::<2>()?.collate() as u16;
+                let error = self.advance_n::<2>()?.collate() as u16;
+                let other_len = self.advance_n::<2>()?.collate();
+                if self.position + other_len != end {
+                    return Err(invalid_length);
+                }
+                let other = self.advance(other_len)?.to_vec();
+
+                ResourceRecordData::TSIG {
+                    algorithm,
+                    time_signed,
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other,
+                }
+            }
             // TXT https://datatracker.ietf.org/doc/html/rfc1035#section-3.3.14
             // RecordType::TXT => todo!(),
             // A https://datatracker.ietf.org/doc/html/rfc1035#section-3.4.1
             RecordType::A => {
-                let ipv4 = self.advance_n::<4>();
+                let ipv4 = self.advance_n::<4>()?;
                 ResourceRecordData::A { ipv4: ipv4.into() }
             }
             // AAAA https://datatracker.ietf.org/doc/html/rfc3596#section-2.2
             RecordType::AAAA => {
-                let ipv6 = self.advance_n::<16>();
+                let ipv6 = self.advance_n::<16>()?;
                 ResourceRecordData::AAAA { ipv6: ipv6.into() }
             }
+            #[cfg_attr(not(feature = "std"), allow(unused_variables))]
             unimplemented => {
                 // For record types that we have not implemented yet, we still want to advance the parser position,
                 // so the remaining packet can be parsed. Instead of failing, we can mark this parsed resource record
                 // data as `Unknown`, so upstream doesn't know what is inside the resource record data field, but it
                 // still has all necessary information from the shared resource record header, which we parsed in `meta`.
+                #[cfg(feature = "std")]
                 println!(
                     "[Debug]: Encountered unimplemented record type {:?}",
                     unimplemented
                 );
-                self.advance(meta.len.into());
+                self.advance(meta.len.into())?;
                 ResourceRecordData::Unknown
             }
         };
@@ -233,33 +287,43 @@ impl<'a> DnsParser<'a> {
         Ok(ResourceRecord::new(meta, resource_record_data))
     }
 
-    fn record_ttl(&mut self) -> u32 {
+    /// The buffer index of the next byte to parse
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    fn record_ttl(&mut self) -> Result<u32, Error> {
         self.answer_ttl_indices.push(self.position);
-        self.advance_n::<4>().collate() as u32
+        Ok(self.advance_n::<4>()?.collate() as u32)
     }
 
     // Header section format https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1
-    pub fn parse_header(&mut self) -> Header {
-        Header {
-            request_id: self.advance_n::<2>().collate() as u16,
-            flags: Flags::from(self.advance_n::<2>().collate() as u16),
-            question_count: self.advance_n::<2>().collate() as u16,
-            answer_count: self.advance_n::<2>().collate() as u16,
-            authority_count: self.advance_n::<2>().collate() as u16,
-            additional_count: self.advance_n::<2>().collate() as u16,
-        }
+    pub fn parse_header(&mut self) -> Result<Header, Error> {
+        Ok(Header {
+            request_id: self.advance_n::<2>()?.collate() as u16,
+            flags: Flags::from(self.advance_n::<2>()?.collate() as u16),
+            question_count: self.advance_n::<2>()?.collate() as u16,
+            answer_count: self.advance_n::<2>()?.collate() as u16,
+            authority_count: self.advance_n::<2>()?.collate() as u16,
+            additional_count: self.advance_n::<2>()?.collate() as u16,
+        })
     }
 
     /// Parses DNS packets according to the following format: https://datatracker.ietf.org/doc/html/rfc1035#section-4.1
-    pub fn parse(&mut self) -> Result<DnsPacket, Box<dyn std::error::Error + Send + Sync>> {
-        let header = self.parse_header();
+    pub fn parse(&mut self) -> Result<DnsPacket, Error> {
+        let header = self.parse_header()?;
+
+        // DNS UPDATE messages re-purpose the section counts, so we must not interpret them as questions and answers
+        if Opcode::from(header.flags.opcode) == Opcode::UPDATE {
+            return Err(Error::UnexpectedOpcode(header.flags.opcode));
+        }
 
         // We only pick the first question, since multiple questions seem to be unsupported by most
         // nameservers anyways, see https://stackoverflow.com/questions/4082081/requesting-a-and-aaaa-records-in-single-dns-query/4083071#4083071.
         let mut first_question = None;
         for _ in 0..header.question_count {
             if first_question.is_none() {
-                first_question = Some(self.parse_question());
+                first_question = Some(self.parse_question()?);
             }
         }
 
@@ -280,13 +344,55 @@ impl<'a> DnsParser<'a> {
 
         Ok(DnsPacket {
             header,
-            question: first_question.unwrap(),
+            question: first_question.ok_or(Error::MissingQuestion)?,
             answers,
             authorities,
             additional,
         })
     }
 
+    /// Parses DNS UPDATE messages according to the following format: https://datatracker.ietf.org/doc/html/rfc2136#section-2
+    pub fn parse_update(&mut self) -> Result<UpdatePacket, Error> {
+        let header = self.parse_header()?;
+
+        if Opcode::from(header.flags.opcode) != Opcode::UPDATE {
+            return Err(Error::UnexpectedOpcode(header.flags.opcode));
+        }
+
+        // The zone section must contain exactly one zone of type SOA, see https://datatracker.ietf.org/doc/html/rfc2136#section-3.1.1
+        if header.question_count != 1 {
+            return Err(Error::MalformedUpdate);
+        }
+        let zone = self.parse_question()?;
+        if zone.r#type != RecordType::SOA {
+            return Err(Error::MalformedUpdate);
+        }
+        let zone = Zone {
+            name: zone.domain_name,
+            class: zone.class,
+        };
+
+        let prerequisites = (0..header.answer_count)
+            .map(|_| Prerequisite::from_record(self.parse_resource_record()?, zone.class))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let updates = (0..header.authority_count)
+            .map(|_| Update::from_record(self.parse_resource_record()?, zone.class))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let additional = (0..header.additional_count)
+            .map(|_| self.parse_resource_record())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(UpdatePacket {
+            header,
+            zone,
+            prerequisites,
+            updates,
+            additional,
+        })
+    }
+
     /// This is a hack to take an existing `DnsPacketBuffer` server response and alter it so it can be re-used
     /// as a response to a later, identical DNS question.
     /// In order to do so, we need to make sure the TTL values for all response resource records are decreased
@@ -295,11 +401,11 @@ impl<'a> DnsParser<'a> {
         mut self,
         ttl_reduction: Duration,
         new_request_id: u16,
-    ) -> Result<[u8; 512], Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<Vec<u8>, Error> {
         self.position = 0;
 
         let seconds = ttl_reduction.as_secs() as u32;
-        let mut buf_copy = *self.buf;
+        let mut buf_copy = self.buf.to_vec();
 
         self.parse()?;
 
@@ -322,6 +428,32 @@ impl<'a> DnsParser<'a> {
 
         Ok(buf_copy)
     }
+
+    /// Returns a copy of the buffer with every TTL lowered to at most `max_ttl`, e.g. to honour the freshness
+    /// lifetime of an answer received via HTTP, see https://datatracker.ietf.org/doc/html/rfc8484#section-5.1
+    pub fn cap_ttls(mut self, max_ttl: u32) -> Result<Vec<u8>, Error> {
+        self.position = 0;
+
+        let mut buf_copy = self.buf.to_vec();
+
+        self.parse()?;
+
+        for &start_index in &self.answer_ttl_indices {
+            let old_ttl = buf_copy[start_index..start_index + 4].collate() as u32;
+            let new_ttl = old_ttl.min(max_ttl);
+            buf_copy[start_index..start_index + 4]
+                .copy_from_slice(new_ttl.to_be_bytes().as_slice());
+        }
+
+        Ok(buf_copy)
+    }
+}
+
+/// Label length bytes with the two most significant bits set introduce a two byte compression pointer,
+/// whose remaining 14 bits are the offset of the referenced name in the packet,
+/// see https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.4.
+fn is_compression_pointer(byte: u8) -> bool {
+    byte & 0xC0 == 0xC0
 }
 
 // TODO: Compared to the actual domain name encoding schema, this is very inefficient and might break
@@ -329,12 +461,15 @@ impl<'a> DnsParser<'a> {
 // should just never encode this ourselves and skip serialization altogether, since we only need
 // two write `DnsPacket` when alterting TTL values, which we can without any issues by just jumping
 // to the relevant byte elements via the parser.
-pub(crate) fn encode_domain_name(domain_name: &str) -> Vec<u8> {
+pub fn encode_domain_name(domain_name: &str) -> Vec<u8> {
     let mut encoded = Vec::with_capacity(domain_name.len());
-    domain_name.split('.').for_each(|part| {
-        encoded.push(part.len() as u8);
-        encoded.extend(part.as_bytes());
-    });
+    domain_name
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .for_each(|part| {
+            encoded.push(part.len() as u8);
+            encoded.extend(part.as_bytes());
+        });
     encoded.push(0);
     encoded
 }
@@ -342,8 +477,14 @@ pub(crate) fn encode_domain_name(domain_name: &str) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use crate::{
+        Error,
         parser::{Collate, DnsParser, encode_domain_name},
-        protocol::header::{Flags, Header},
+        protocol::{
+            header::{Flags, Header},
+            query::Query,
+            record_type::RecordType,
+        },
+        serialize::serialize_query,
     };
 
     #[test]
@@ -353,7 +494,7 @@ mod tests {
 
         let mut parser = DnsParser::new(&input);
         assert_eq!(
-            parser.advance_n::<3>().collate(),
+            parser.advance_n::<3>().unwrap().collate(),
             (0x3 << 16) | (0x2 << 8) | 0x1
         );
         assert_eq!(parser.buf.len(), 512);
@@ -365,10 +506,54 @@ mod tests {
         input[0..3].copy_from_slice(&[0x3, 0x2, 0x1]);
 
         let parser = DnsParser::new(&input);
-        assert_eq!(parser.peek(3), [0x3, 0x2, 0x1]);
+        assert_eq!(parser.peek(3).unwrap(), [0x3, 0x2, 0x1]);
         assert_eq!(parser.buf.len(), 512);
     }
 
+    #[test]
+    fn test_parse_truncated() {
+        let query = serialize_query(&Query::new("www.example.com", RecordType::A), 0x1337);
+        assert!(DnsParser::new(&query).parse().is_ok());
+
+        // Every datagram that was cut off, down to a partial header, is rejected instead of read past its end
+        for len in 0..query.len() {
+            assert_eq!(
+                DnsParser::new(&query[..len]).parse().unwrap_err(),
+                Error::Truncated,
+                "{len} bytes"
+            );
+        }
+
+        // A record whose data is longer than the message
+        let mut response = query.clone();
+        response[7] = 1;
+        response.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0]);
+        assert_eq!(
+            DnsParser::new(&response).parse().unwrap_err(),
+            Error::Truncated
+        );
+    }
+
+    #[test]
+    fn test_parse_compression_pointer_loop() {
+        let mut query = serialize_query(&Query::new("www.example.com", RecordType::A), 0x1337);
+        // The question's name points to itself
+        query[12..14].copy_from_slice(&[0xC0, 12]);
+        assert_eq!(
+            DnsParser::new(&query).parse().unwrap_err(),
+            Error::InvalidCompressionPointer
+        );
+
+        // An answer's name points to a label that leads back to the same pointer
+        let mut response = serialize_query(&Query::new("www.example.com", RecordType::A), 0x1337);
+        response[7] = 1;
+        response.extend_from_slice(&[3, b'w', b'w', b'w', 0xC0, 33]);
+        assert_eq!(
+            DnsParser::new(&response).parse().unwrap_err(),
+            Error::InvalidCompressionPointer
+        );
+    }
+
     #[test]
     fn test_conversion_flags() {
         let raw = 0x8100_u16; // response & recursive resolution desired flags set
@@ -386,6 +571,28 @@ mod tests {
         assert_eq!(raw, encoded);
     }
 
+    #[test]
+    fn test_conversion_flags_all_fields() {
+        let raw = 0xAFB3_u16; // response, opcode 5, AA, TC, RD, RA, AD, CD, RCODE 3
+        let flags = Flags::from(raw);
+        assert_eq!(
+            flags,
+            Flags {
+                query: false,
+                opcode: 5,
+                authoritative_answer: true,
+                truncation: true,
+                recursion_desired: true,
+                recursion_available: true,
+                z: 3,
+                response_code: 3,
+            }
+        );
+
+        let encoded: u16 = flags.into();
+        assert_eq!(raw, encoded);
+    }
+
     #[test]
     fn test_conversion_header() {
         let header = Header {
@@ -401,7 +608,7 @@ mod tests {
         packet[0..12].copy_from_slice(&serialized_header);
 
         let mut parser = DnsParser::new(&packet);
-        let deserialized_header = parser.parse_header();
+        let deserialized_header = parser.parse_header().unwrap();
         assert_eq!(header, deserialized_header);
     }
 
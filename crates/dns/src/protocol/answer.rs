@@ -1,8 +1,9 @@
-use std::net::{Ipv4Addr, Ipv6Addr};
+use alloc::{string::String, vec::Vec};
+use core::net::{Ipv4Addr, Ipv6Addr};
 
 use super::record_type::RecordType;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ResourceRecordMeta {
     pub name: String,
     pub record_type: RecordType,
@@ -11,7 +12,7 @@ pub struct ResourceRecordMeta {
     pub len: u16,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ResourceRecord {
     pub meta: ResourceRecordMeta,
     pub value: ResourceRecordData,
@@ -23,7 +24,7 @@ impl ResourceRecord {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ResourceRecordData {
     A {
         ipv4: Ipv4Addr,
@@ -56,7 +57,47 @@ pub enum ResourceRecordData {
         expire: u32,
         minimum: u32,
     },
+    /// Transaction signature, see https://datatracker.ietf.org/doc/html/rfc8945#section-4.2
+    TSIG {
+        algorithm: String,
+        /// Seconds since the UNIX epoch, which are transmitted as an unsigned 48 bit integer
+        time_signed: u64,
+        fudge: u16,
+        mac: Vec<u8>,
+        original_id: u16,
+        error: u16,
+        other: Vec<u8>,
+    },
+    /// Marks a resource record without any RR data, e.g. an OPT record without options or prerequisites
+    /// and deletions in DNS UPDATE messages, see https://datatracker.ietf.org/doc/html/rfc2136#section-2.4.
+    Empty,
+    /// RR data in wire format for record types without a variant of their own, e.g. TXT records, which allows
+    /// creating such records in the generic format of https://datatracker.ietf.org/doc/html/rfc3597#section-5.
+    /// The parser never produces this variant.
+    Raw {
+        record_type: RecordType,
+        data: Vec<u8>,
+    },
     /// Marks the RR data for a record type for which we haven't implemented the parsing step yet.
     /// We can afford this since we often don't care about RR data and only about the RR metadata.
     Unknown,
 }
+
+impl ResourceRecordData {
+    /// Returns the record type that this RR data belongs to, if it is known.
+    pub fn record_type(&self) -> Option<RecordType> {
+        match self {
+            ResourceRecordData::A { .. } => Some(RecordType::A),
+            ResourceRecordData::AAAA { .. } => Some(RecordType::AAAA),
+            ResourceRecordData::CNAME { .. } => Some(RecordType::CNAME),
+            ResourceRecordData::NS { .. } => Some(RecordType::NS),
+            ResourceRecordData::MB { .. } => Some(RecordType::MB),
+            ResourceRecordData::MX { .. } => Some(RecordType::MX),
+            ResourceRecordData::PTR { .. } => Some(RecordType::PTR),
+            ResourceRecordData::SOA { .. } => Some(RecordType::SOA),
+            ResourceRecordData::TSIG { .. } => Some(RecordType::TSIG),
+            ResourceRecordData::Raw { record_type, .. } => Some(*record_type),
+            ResourceRecordData::Empty | ResourceRecordData::Unknown => None,
+        }
+    }
+}
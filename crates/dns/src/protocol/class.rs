@@ -0,0 +1,67 @@
+//! Resource record and question classes, see https://datatracker.ietf.org/doc/html/rfc1035#section-3.2.4
+
+/// The INternet class
+pub const CLASS_IN: u16 = 1;
+/// The CHAOS class, which is mostly used to query server metadata such as `version.bind`
+pub const CLASS_CH: u16 = 3;
+/// The Hesiod class
+pub const CLASS_HS: u16 = 4;
+/// The NONE class is used by DNS UPDATE prerequisites and updates to express the absence of an RRset or RR
+pub const CLASS_NONE: u16 = 254;
+/// The ANY class matches any class in questions, DNS UPDATE prerequisites and updates
+pub const CLASS_ANY: u16 = 255;
+
+/// Parses a class mnemonic such as `IN` or `CH`, or a generic `CLASS<n>` as defined in
+/// https://datatracker.ietf.org/doc/html/rfc3597#section-5
+pub fn parse_class(input: &str) -> Option<u16> {
+    match input.to_ascii_uppercase().as_str() {
+        "IN" => Some(CLASS_IN),
+        "CH" | "CHAOS" => Some(CLASS_CH),
+        "HS" => Some(CLASS_HS),
+        "NONE" => Some(CLASS_NONE),
+        "ANY" => Some(CLASS_ANY),
+        other => other
+            .strip_prefix("CLASS")
+            .filter(|n| n.bytes().all(|byte| byte.is_ascii_digit()))?
+            .parse()
+            .ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::class::{
+        CLASS_ANY, CLASS_CH, CLASS_HS, CLASS_IN, CLASS_NONE, parse_class,
+    };
+
+    #[test]
+    fn test_parse_class() {
+        for (input, class) in [
+            ("IN", CLASS_IN),
+            ("in", CLASS_IN),
+            ("CH", CLASS_CH),
+            ("Chaos", CLASS_CH),
+            ("HS", CLASS_HS),
+            ("NONE", CLASS_NONE),
+            ("any", CLASS_ANY),
+            ("CLASS1", CLASS_IN),
+            ("class3", CLASS_CH),
+            ("CLASS0", 0),
+            ("CLASS65535", 65535),
+        ] {
+            assert_eq!(parse_class(input), Some(class), "{input}");
+        }
+
+        for input in [
+            "",
+            "INET",
+            "CLASS",
+            "CLASS65536",
+            "CLASS-1",
+            "CLASS+1",
+            "TYPE1",
+        ] {
+            assert_eq!(parse_class(input), None, "{input}");
+        }
+    }
+}
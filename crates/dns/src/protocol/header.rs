@@ -34,17 +34,32 @@ pub struct Flags {
     pub response_code: u8,
 }
 
+impl Flags {
+    /// The `z` bit that marks answers as authenticated via DNSSEC, see https://datatracker.ietf.org/doc/html/rfc4035#section-3.2.3
+    pub const AUTHENTIC_DATA: u8 = 0b010;
+    /// The `z` bit that disables DNSSEC validation upstream, see https://datatracker.ietf.org/doc/html/rfc4035#section-3.2.2
+    pub const CHECKING_DISABLED: u8 = 0b001;
+
+    pub fn authentic_data(&self) -> bool {
+        self.z & Self::AUTHENTIC_DATA > 0
+    }
+
+    pub fn checking_disabled(&self) -> bool {
+        self.z & Self::CHECKING_DISABLED > 0
+    }
+}
+
 impl From<u16> for Flags {
     fn from(input: u16) -> Self {
         Self {
             query: (input >> 15 & 1) == 0,
-            opcode: (input >> 11 & 4) as u8,
+            opcode: (input >> 11 & 0xF) as u8,
             authoritative_answer: (input >> 10 & 1) > 0,
             truncation: (input >> 9 & 1) > 0,
             recursion_desired: (input >> 8 & 1) > 0,
             recursion_available: (input >> 7 & 1) > 0,
-            z: (input >> 4 & 3) as u8,
-            response_code: (input & 4) as u8,
+            z: (input >> 4 & 7) as u8,
+            response_code: (input & 0xF) as u8,
         }
     }
 }
@@ -58,7 +73,7 @@ impl From<Flags> for u16 {
         value |= u16::from(flags.truncation) << 9;
         value |= u16::from(flags.recursion_desired) << 8;
         value |= u16::from(flags.recursion_available) << 7;
-        value |= (flags.z as u16) << 3;
+        value |= (flags.z as u16) << 4;
         value |= flags.response_code as u16;
         value
     }
@@ -1,6 +1,10 @@
 pub mod answer;
+pub mod class;
 pub mod header;
+pub mod opcode;
 pub mod packet;
+pub mod query;
 pub mod question;
 pub mod record_type;
 pub mod response_code;
+pub mod update;
@@ -0,0 +1,38 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+/// The kind of query in a DNS message, see https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-5
+pub enum Opcode {
+    QUERY,  // 0 a standard query, see RFC 1035
+    IQUERY, // 1 an inverse query (Obsolete), see RFC 3425
+    STATUS, // 2 a server status request, see RFC 1035
+    NOTIFY, // 4 a zone change notification, see RFC 1996
+    UPDATE, // 5 a dynamic update, see RFC 2136
+    // Fallback
+    Unknown(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(input: u8) -> Self {
+        match input {
+            0 => Self::QUERY,
+            1 => Self::IQUERY,
+            2 => Self::STATUS,
+            4 => Self::NOTIFY,
+            5 => Self::UPDATE,
+            _ => Self::Unknown(input),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::QUERY => 0,
+            Opcode::IQUERY => 1,
+            Opcode::STATUS => 2,
+            Opcode::NOTIFY => 4,
+            Opcode::UPDATE => 5,
+            Opcode::Unknown(n) => n,
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::protocol::{answer::ResourceRecord, header::Header, question::Question};
+use alloc::vec::Vec;
+
+use crate::protocol::{
+    answer::ResourceRecord, header::Header, question::Question, record_type::RecordType,
+};
 
 #[derive(Clone, Debug)]
 pub struct DnsPacket {
@@ -11,3 +15,14 @@ pub struct DnsPacket {
     /// The list of resource records that upstream sent as additional data.
     pub additional: Vec<ResourceRecord>,
 }
+
+impl DnsPacket {
+    /// The largest response the sender of this query accepts via UDP, which is 512 bytes unless raised by an
+    /// EDNS OPT record, see https://datatracker.ietf.org/doc/html/rfc6891#section-6.2.5
+    pub fn max_udp_payload_size(&self) -> usize {
+        self.additional
+            .iter()
+            .find(|record| record.meta.record_type == RecordType::OPT)
+            .map_or(512, |opt| (opt.meta.class as usize).max(512))
+    }
+}
@@ -0,0 +1,80 @@
+use alloc::string::String;
+
+use crate::protocol::{class::CLASS_IN, record_type::RecordType};
+
+/// Describes a DNS query for a single question, see `serialize::serialize_query` for its wire format.
+///
+/// ```
+/// use dns::protocol::{query::{Edns, Query}, record_type::RecordType};
+///
+/// let query = Query::new("example.com", RecordType::AAAA).edns(Edns {
+///     dnssec_ok: true,
+///     ..Edns::default()
+/// });
+/// assert!(query.recursion_desired);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub domain: String,
+    pub record_type: RecordType,
+    pub class: u16,
+    /// Whether the server should resolve the query recursively (RD)
+    pub recursion_desired: bool,
+    /// Whether the server should skip DNSSEC validation (CD)
+    pub checking_disabled: bool,
+    /// Whether to attach an OPT record to the query
+    pub edns: Option<Edns>,
+}
+
+/// EDNS(0) parameters of a query, see https://datatracker.ietf.org/doc/html/rfc6891#section-6.1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    /// The largest UDP payload the client is able to receive
+    pub udp_payload_size: u16,
+    /// Whether the server should include DNSSEC records in the answer (DO)
+    pub dnssec_ok: bool,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Self {
+            // Recommended by https://www.dnsflagday.net/2020/ to avoid IP fragmentation
+            udp_payload_size: 1232,
+            dnssec_ok: false,
+        }
+    }
+}
+
+impl Query {
+    /// Creates a recursive INternet query for `record_type` records of `domain`
+    pub fn new(domain: &str, record_type: RecordType) -> Self {
+        Self {
+            domain: domain.into(),
+            record_type,
+            class: CLASS_IN,
+            recursion_desired: true,
+            checking_disabled: false,
+            edns: None,
+        }
+    }
+
+    pub fn class(mut self, class: u16) -> Self {
+        self.class = class;
+        self
+    }
+
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.recursion_desired = recursion_desired;
+        self
+    }
+
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.checking_disabled = checking_disabled;
+        self
+    }
+
+    pub fn edns(mut self, edns: Edns) -> Self {
+        self.edns = Some(edns);
+        self
+    }
+}
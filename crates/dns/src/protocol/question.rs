@@ -1,3 +1,5 @@
+use alloc::string::String;
+
 use super::record_type::RecordType;
 
 #[derive(Debug, Clone)]
@@ -1,3 +1,7 @@
+use core::str::FromStr;
+
+use crate::Error;
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 /// This enum models all possibly occurring record types in DNS resource records.
@@ -38,10 +42,11 @@ pub enum RecordType {
     MX,    // 15 mail exchange
     TXT,   // 16 text strings1
     // - 4 QTYPEs, see https://datatracker.ietf.org/doc/html/rfc1035#section-3.2.3
-    AXFR,  // 252 A request for a transfer of an entire zone
+    TSIG, // 250 Transaction signature, see RFC 8945 https://datatracker.ietf.org/doc/html/rfc8945#section-4.2
+    AXFR, // 252 A request for a transfer of an entire zone
     MAILB, // 253 A request for mailbox-related records (MB, MG or MR)
     MAILA, // 254 A request for mail agent RRs (Obsolete - see MX)
-    ANY,   // 255 A request for all records
+    ANY,  // 255 A request for all records
     // Pseudo RR type - see https://en.wikipedia.org/wiki/List_of_DNS_record_types
     OPT, // 41 A pseudo record type to support EDNS.
     // Later Extensions
@@ -72,6 +77,7 @@ impl From<u16> for RecordType {
             15 => Self::MX,
             16 => Self::TXT,
             // QTYPE
+            250 => Self::TSIG,
             252 => Self::AXFR,
             253 => Self::MAILB,
             254 => Self::MAILA,
@@ -108,6 +114,7 @@ impl From<RecordType> for u16 {
             RecordType::OPT => 41,
             RecordType::AAAA => 28,
             RecordType::HTTPS => 65,
+            RecordType::TSIG => 250,
             RecordType::AXFR => 252,
             RecordType::MAILB => 253,
             RecordType::MAILA => 254,
@@ -116,3 +123,87 @@ impl From<RecordType> for u16 {
         }
     }
 }
+
+impl FromStr for RecordType {
+    type Err = Error;
+
+    /// Parses a record type mnemonic such as `AAAA`, or a generic `TYPE<n>` as defined in
+    /// https://datatracker.ietf.org/doc/html/rfc3597#section-5
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let record_type = match input.to_ascii_uppercase().as_str() {
+            "A" => Self::A,
+            "NS" => Self::NS,
+            "MD" => Self::MD,
+            "MF" => Self::MF,
+            "CNAME" => Self::CNAME,
+            "SOA" => Self::SOA,
+            "MB" => Self::MB,
+            "MG" => Self::MG,
+            "MR" => Self::MR,
+            "NULL" => Self::NULL,
+            "WKS" => Self::WKS,
+            "PTR" => Self::PTR,
+            "HINFO" => Self::HINFO,
+            "MINFO" => Self::MINFO,
+            "MX" => Self::MX,
+            "TXT" => Self::TXT,
+            "TSIG" => Self::TSIG,
+            "AXFR" => Self::AXFR,
+            "MAILB" => Self::MAILB,
+            "MAILA" => Self::MAILA,
+            "ANY" | "*" => Self::ANY,
+            "OPT" => Self::OPT,
+            "AAAA" => Self::AAAA,
+            "HTTPS" => Self::HTTPS,
+            other => other
+                .strip_prefix("TYPE")
+                .filter(|n| n.bytes().all(|byte| byte.is_ascii_digit()))
+                .and_then(|n| n.parse::<u16>().ok())
+                .map(Self::from)
+                .ok_or(Error::UnknownRecordType)?,
+        };
+        Ok(record_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use crate::{Error, protocol::record_type::RecordType};
+
+    #[test]
+    fn test_record_type_from_str() {
+        for (input, record_type) in [
+            ("A", RecordType::A),
+            ("aaaa", RecordType::AAAA),
+            ("Mx", RecordType::MX),
+            ("HTTPS", RecordType::HTTPS),
+            ("*", RecordType::ANY),
+            ("TYPE1", RecordType::A),
+            ("type65", RecordType::HTTPS),
+            ("TYPE0", RecordType::Unknown(0)),
+            ("TYPE99", RecordType::Unknown(99)),
+            ("TYPE65535", RecordType::Unknown(65535)),
+        ] {
+            assert_eq!(RecordType::from_str(input), Ok(record_type), "{input}");
+        }
+
+        for input in [
+            "",
+            "AAA",
+            "TYPE",
+            "TYPE65536",
+            "TYPE-1",
+            "TYPE+1",
+            "TYPE 1",
+            " A",
+        ] {
+            assert_eq!(
+                RecordType::from_str(input),
+                Err(Error::UnknownRecordType),
+                "{input}"
+            );
+        }
+    }
+}
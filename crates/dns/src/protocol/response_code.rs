@@ -4,6 +4,12 @@ pub enum ResponseCode {
     FORMERR,
     NXDOMAIN,
     SERVFAIL,
+    // RFC 2136 defines additional response codes for DNS UPDATE, see https://datatracker.ietf.org/doc/html/rfc2136#section-2.2
+    YXDOMAIN,
+    YXRRSET,
+    NXRRSET,
+    NOTAUTH,
+    NOTZONE,
 }
 
 impl From<ResponseCode> for u8 {
@@ -13,6 +19,11 @@ impl From<ResponseCode> for u8 {
             ResponseCode::FORMERR => 1,
             ResponseCode::SERVFAIL => 2,
             ResponseCode::NXDOMAIN => 3,
+            ResponseCode::YXDOMAIN => 6,
+            ResponseCode::YXRRSET => 7,
+            ResponseCode::NXRRSET => 8,
+            ResponseCode::NOTAUTH => 9,
+            ResponseCode::NOTZONE => 10,
         }
     }
 }
@@ -0,0 +1,505 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    Error,
+    protocol::{
+        answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+        class::{CLASS_ANY, CLASS_IN, CLASS_NONE},
+        header::{Flags, Header},
+        opcode::Opcode,
+        record_type::RecordType,
+    },
+    serialize::encode_record_data,
+};
+
+/// A DNS UPDATE message as defined in https://datatracker.ietf.org/doc/html/rfc2136#section-2.
+///
+/// UPDATE messages share the header with regular DNS messages, but re-purpose its section counts as
+/// ZOCOUNT, PRCOUNT, UPCOUNT and ADCOUNT, which is why they are modelled separately from `DnsPacket`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdatePacket {
+    pub header: Header,
+    pub zone: Zone,
+    pub prerequisites: Vec<Prerequisite>,
+    pub updates: Vec<Update>,
+    pub additional: Vec<ResourceRecord>,
+}
+
+/// Zone section format https://datatracker.ietf.org/doc/html/rfc2136#section-2.3
+///
+/// The zone type is always `SOA`, so we only keep the zone name and class.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Zone {
+    pub name: String,
+    pub class: u16,
+}
+
+/// Prerequisite section format https://datatracker.ietf.org/doc/html/rfc2136#section-2.4
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Prerequisite {
+    /// At least one RR with the given name and type exists
+    RRsetExists {
+        name: String,
+        record_type: RecordType,
+    },
+    /// An RRset with the given name and type exists and contains exactly these RRs
+    RRsetExistsWithValue(ResourceRecord),
+    /// No RR with the given name and type exists
+    RRsetDoesNotExist {
+        name: String,
+        record_type: RecordType,
+    },
+    /// At least one RR with the given name exists
+    NameInUse { name: String },
+    /// No RR of any type with the given name exists
+    NameNotInUse { name: String },
+}
+
+/// Update section format https://datatracker.ietf.org/doc/html/rfc2136#section-2.5
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Update {
+    /// Add the RR to an RRset
+    Add(ResourceRecord),
+    /// Delete all RRs with the given name and type
+    DeleteRRset {
+        name: String,
+        record_type: RecordType,
+    },
+    /// Delete all RRsets with the given name
+    DeleteName { name: String },
+    /// Delete a single RR from an RRset, which is matched by name, type and RR data
+    DeleteRecord(ResourceRecord),
+}
+
+impl Prerequisite {
+    /// Classifies a parsed resource record from the prerequisite section
+    pub(crate) fn from_record(record: ResourceRecord, zone_class: u16) -> Result<Self, Error> {
+        let ResourceRecordMeta {
+            name,
+            record_type,
+            class,
+            ttl,
+            len,
+        } = record.meta.clone();
+
+        if ttl != 0 {
+            return Err(Error::MalformedUpdate);
+        }
+
+        match (class, record_type) {
+            // Prerequisites on names and RRsets carry no RR data
+            (CLASS_ANY | CLASS_NONE, _) if len != 0 => Err(Error::MalformedUpdate),
+            (CLASS_ANY, RecordType::ANY) => Ok(Prerequisite::NameInUse { name }),
+            (CLASS_ANY, record_type) => Ok(Prerequisite::RRsetExists { name, record_type }),
+            (CLASS_NONE, RecordType::ANY) => Ok(Prerequisite::NameNotInUse { name }),
+            (CLASS_NONE, record_type) => Ok(Prerequisite::RRsetDoesNotExist { name, record_type }),
+            (class, _) if class == zone_class => Ok(Prerequisite::RRsetExistsWithValue(record)),
+            _ => Err(Error::MalformedUpdate),
+        }
+    }
+
+    /// Converts the prerequisite into the resource record that represents it on the wire
+    pub(crate) fn to_record(&self, zone_class: u16) -> ResourceRecord {
+        match self {
+            Prerequisite::RRsetExists { name, record_type } => {
+                empty_record(name, *record_type, CLASS_ANY)
+            }
+            Prerequisite::RRsetExistsWithValue(record) => ResourceRecord::new(
+                ResourceRecordMeta {
+                    class: zone_class,
+                    ttl: 0,
+                    ..record.meta.clone()
+                },
+                record.value.clone(),
+            ),
+            Prerequisite::RRsetDoesNotExist { name, record_type } => {
+                empty_record(name, *record_type, CLASS_NONE)
+            }
+            Prerequisite::NameInUse { name } => empty_record(name, RecordType::ANY, CLASS_ANY),
+            Prerequisite::NameNotInUse { name } => empty_record(name, RecordType::ANY, CLASS_NONE),
+        }
+    }
+}
+
+impl Update {
+    /// Classifies a parsed resource record from the update section
+    pub(crate) fn from_record(record: ResourceRecord, zone_class: u16) -> Result<Self, Error> {
+        let ResourceRecordMeta {
+            name,
+            record_type,
+            class,
+            ttl,
+            len,
+        } = record.meta.clone();
+
+        match (class, record_type) {
+            (class, _) if class == zone_class => Ok(Update::Add(record)),
+            // Deletes have a TTL of 0, and those of RRsets and names carry no RR data
+            (CLASS_ANY, _) if ttl != 0 || len != 0 => Err(Error::MalformedUpdate),
+            (CLASS_NONE, _) if ttl != 0 => Err(Error::MalformedUpdate),
+            (CLASS_ANY, RecordType::ANY) => Ok(Update::DeleteName { name }),
+            (CLASS_ANY, record_type) => Ok(Update::DeleteRRset { name, record_type }),
+            (CLASS_NONE, _) => Ok(Update::DeleteRecord(record)),
+            _ => Err(Error::MalformedUpdate),
+        }
+    }
+
+    /// Converts the update into the resource record that represents it on the wire
+    pub(crate) fn to_record(&self, zone_class: u16) -> ResourceRecord {
+        match self {
+            Update::Add(record) => ResourceRecord::new(
+                ResourceRecordMeta {
+                    class: zone_class,
+                    ..record.meta.clone()
+                },
+                record.value.clone(),
+            ),
+            Update::DeleteRRset { name, record_type } => {
+                empty_record(name, *record_type, CLASS_ANY)
+            }
+            Update::DeleteName { name } => empty_record(name, RecordType::ANY, CLASS_ANY),
+            Update::DeleteRecord(record) => ResourceRecord::new(
+                ResourceRecordMeta {
+                    class: CLASS_NONE,
+                    ttl: 0,
+                    ..record.meta.clone()
+                },
+                record.value.clone(),
+            ),
+        }
+    }
+}
+
+fn empty_record(name: &str, record_type: RecordType, class: u16) -> ResourceRecord {
+    ResourceRecord::new(
+        ResourceRecordMeta {
+            name: name.into(),
+            record_type,
+            class,
+            ttl: 0,
+            len: 0,
+        },
+        ResourceRecordData::Empty,
+    )
+}
+
+/// Builds `UpdatePacket`s for a single zone.
+///
+/// ```
+/// use dns::protocol::{record_type::RecordType, update::UpdateBuilder};
+///
+/// let update = UpdateBuilder::new(42, "example.com")
+///     .require_name_not_in_use("host.example.com")
+///     .add("host.example.com", 3600, dns::protocol::answer::ResourceRecordData::A {
+///         ipv4: [192, 0, 2, 10].into(),
+///     })
+///     .delete_rrset("host.example.com", RecordType::AAAA)
+///     .build()
+///     .unwrap();
+/// assert_eq!(update.updates.len(), 2);
+/// ```
+#[derive(Debug)]
+pub struct UpdateBuilder {
+    request_id: u16,
+    zone: Zone,
+    prerequisites: Vec<Prerequisite>,
+    updates: Vec<Update>,
+    additional: Vec<ResourceRecord>,
+    /// The first error that occurred while building, which is reported by `build`
+    error: Option<Error>,
+}
+
+impl UpdateBuilder {
+    /// Starts building an update for the INternet class zone `zone`
+    pub fn new(request_id: u16, zone: &str) -> Self {
+        Self {
+            request_id,
+            zone: Zone {
+                name: zone.into(),
+                class: CLASS_IN,
+            },
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            additional: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Overrides the class of the updated zone
+    pub fn class(mut self, class: u16) -> Self {
+        self.zone.class = class;
+        self
+    }
+
+    /// Requires at least one RR of type `record_type` to exist for `name`
+    pub fn require_rrset_exists(mut self, name: &str, record_type: RecordType) -> Self {
+        self.prerequisites.push(Prerequisite::RRsetExists {
+            name: name.into(),
+            record_type,
+        });
+        self
+    }
+
+    /// Requires an RRset to exist for `name` that holds `data`. Pass multiple values of the same type
+    /// to require the RRset to consist of exactly those values.
+    pub fn require_rrset_value(mut self, name: &str, data: ResourceRecordData) -> Self {
+        if let Some(record) = self.typed_record(name, 0, data) {
+            self.prerequisites
+                .push(Prerequisite::RRsetExistsWithValue(record));
+        }
+        self
+    }
+
+    /// Requires that no RR of type `record_type` exists for `name`
+    pub fn require_rrset_does_not_exist(mut self, name: &str, record_type: RecordType) -> Self {
+        self.prerequisites.push(Prerequisite::RRsetDoesNotExist {
+            name: name.into(),
+            record_type,
+        });
+        self
+    }
+
+    /// Requires at least one RR of any type to exist for `name`
+    pub fn require_name_in_use(mut self, name: &str) -> Self {
+        self.prerequisites
+            .push(Prerequisite::NameInUse { name: name.into() });
+        self
+    }
+
+    /// Requires that no RR of any type exists for `name`
+    pub fn require_name_not_in_use(mut self, name: &str) -> Self {
+        self.prerequisites
+            .push(Prerequisite::NameNotInUse { name: name.into() });
+        self
+    }
+
+    /// Adds an RR with `data` to the RRset of `name`
+    pub fn add(mut self, name: &str, ttl: u32, data: ResourceRecordData) -> Self {
+        if let Some(record) = self.typed_record(name, ttl, data) {
+            self.updates.push(Update::Add(record));
+        }
+        self
+    }
+
+    /// Deletes all RRs of type `record_type` for `name`
+    pub fn delete_rrset(mut self, name: &str, record_type: RecordType) -> Self {
+        self.updates.push(Update::DeleteRRset {
+            name: name.into(),
+            record_type,
+        });
+        self
+    }
+
+    /// Deletes all RRs of any type for `name`
+    pub fn delete_name(mut self, name: &str) -> Self {
+        self.updates.push(Update::DeleteName { name: name.into() });
+        self
+    }
+
+    /// Deletes the single RR holding `data` for `name`
+    pub fn delete_record(mut self, name: &str, data: ResourceRecordData) -> Self {
+        if let Some(mut record) = self.typed_record(name, 0, data) {
+            record.meta.class = CLASS_NONE;
+            self.updates.push(Update::DeleteRecord(record));
+        }
+        self
+    }
+
+    /// Appends a resource record to the additional data section
+    pub fn additional(mut self, record: ResourceRecord) -> Self {
+        self.additional.push(record);
+        self
+    }
+
+    pub fn build(self) -> Result<UpdatePacket, Error> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let flags = Flags {
+            query: true,
+            opcode: Opcode::UPDATE.into(),
+            ..Flags::default()
+        };
+
+        Ok(UpdatePacket {
+            header: Header {
+                request_id: self.request_id,
+                flags,
+                question_count: 1,
+                answer_count: self.prerequisites.len() as u16,
+                authority_count: self.updates.len() as u16,
+                additional_count: self.additional.len() as u16,
+            },
+            zone: self.zone,
+            prerequisites: self.prerequisites,
+            updates: self.updates,
+            additional: self.additional,
+        })
+    }
+
+    fn typed_record(
+        &mut self,
+        name: &str,
+        ttl: u32,
+        data: ResourceRecordData,
+    ) -> Option<ResourceRecord> {
+        let Some(record_type) = data.record_type() else {
+            self.error.get_or_insert(Error::UntypedRecordData);
+            return None;
+        };
+
+        let len = match encode_record_data(&data) {
+            Ok(encoded) => encoded.len() as u16,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                return None;
+            }
+        };
+
+        Some(ResourceRecord::new(
+            ResourceRecordMeta {
+                name: name.into(),
+                record_type,
+                class: self.zone.class,
+                ttl,
+                len,
+            },
+            data,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Error,
+        parser::DnsParser,
+        protocol::{
+            answer::ResourceRecordData,
+            class::{CLASS_ANY, CLASS_IN, CLASS_NONE},
+            record_type::RecordType,
+            update::{Prerequisite, Update, UpdateBuilder},
+        },
+        serialize::serialize_update,
+    };
+
+    /// An UPDATE message for the zone `example.com` with a single A record for `host.example.com` in the
+    /// prerequisite or update section
+    fn update_message(update_section: bool, class: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+        let (prerequisites, updates) = if update_section { (0u16, 1u16) } else { (1, 0) };
+        let mut message = vec![0x12, 0x34, 0x28, 0x00, 0, 1];
+        message.extend_from_slice(&prerequisites.to_be_bytes());
+        message.extend_from_slice(&updates.to_be_bytes());
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(b"\x07example\x03com\x00\x00\x06\x00\x01");
+        // The name points at the zone's name
+        message.extend_from_slice(b"\x04host\xc0\x0c\x00\x01");
+        message.extend_from_slice(&class.to_be_bytes());
+        message.extend_from_slice(&ttl.to_be_bytes());
+        message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        message.extend_from_slice(data);
+        message
+    }
+
+    #[test]
+    fn test_update_builder_round_trip() {
+        let update = UpdateBuilder::new(1234, "example.com")
+            .require_name_in_use("example.com")
+            .require_rrset_exists("example.com", RecordType::SOA)
+            .require_rrset_does_not_exist("host.example.com", RecordType::CNAME)
+            .require_rrset_value(
+                "ns.example.com",
+                ResourceRecordData::A {
+                    ipv4: [192, 0, 2, 1].into(),
+                },
+            )
+            .require_name_not_in_use("new.example.com")
+            .add(
+                "host.example.com",
+                300,
+                ResourceRecordData::AAAA {
+                    ipv6: "2001:db8::10".parse().unwrap(),
+                },
+            )
+            .delete_rrset("host.example.com", RecordType::A)
+            .delete_name("old.example.com")
+            .delete_record(
+                "example.com",
+                ResourceRecordData::MX {
+                    preference: 10,
+                    exchange: "mx.example.com".into(),
+                },
+            )
+            .build()
+            .unwrap();
+
+        let raw = serialize_update(&update).unwrap();
+        let mut buffer = [0u8; 512];
+        buffer[..raw.len()].copy_from_slice(&raw);
+
+        assert_eq!(DnsParser::new(&buffer).parse_update(), Ok(update));
+        assert_eq!(
+            DnsParser::new(&buffer).parse().unwrap_err(),
+            Error::UnexpectedOpcode(5)
+        );
+    }
+
+    #[test]
+    fn test_parse_update_empty_records() {
+        let ip = [192, 0, 2, 1];
+        let parse = |update_section, class, ttl, data: &[u8]| {
+            DnsParser::new(&update_message(update_section, class, ttl, data)).parse_update()
+        };
+
+        assert_eq!(
+            parse(false, CLASS_ANY, 0, &[]).unwrap().prerequisites,
+            [Prerequisite::RRsetExists {
+                name: "host.example.com".into(),
+                record_type: RecordType::A
+            }]
+        );
+        assert_eq!(
+            parse(true, CLASS_ANY, 0, &[]).unwrap().updates,
+            [Update::DeleteRRset {
+                name: "host.example.com".into(),
+                record_type: RecordType::A
+            }]
+        );
+        assert!(matches!(
+            parse(true, CLASS_NONE, 0, &ip).unwrap().updates[..],
+            [Update::DeleteRecord(_)]
+        ));
+        assert!(matches!(
+            parse(true, CLASS_IN, 300, &ip).unwrap().updates[..],
+            [Update::Add(_)]
+        ));
+
+        // Only prerequisites on values and added RRs carry RR data, see
+        // https://datatracker.ietf.org/doc/html/rfc2136#section-2.4 and section 2.5, and deletes have a TTL of 0
+        for (update_section, class, ttl, data) in [
+            (false, CLASS_ANY, 0, &ip[..]),
+            (false, CLASS_NONE, 0, &ip[..]),
+            (false, CLASS_IN, 300, &ip[..]),
+            (true, CLASS_ANY, 0, &ip[..]),
+            (true, CLASS_ANY, 300, &[][..]),
+            (true, CLASS_NONE, 300, &ip[..]),
+        ] {
+            assert_eq!(
+                parse(update_section, class, ttl, data),
+                Err(Error::MalformedUpdate),
+                "{update_section} {class} {ttl} {data:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_builder_untyped_data() {
+        let update = UpdateBuilder::new(1, "example.com")
+            .add("host.example.com", 300, ResourceRecordData::Unknown)
+            .delete_name("host.example.com")
+            .build();
+
+        assert_eq!(update, Err(Error::UntypedRecordData));
+    }
+}
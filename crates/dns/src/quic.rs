@@ -0,0 +1,543 @@
+use std::{fmt::Debug, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use quinn::{
+    ClientConfig, Connection, ConnectionError, Endpoint, ReadError, ReadToEndError, RecvStream,
+    SendStream, VarInt, WriteError, crypto::rustls::QuicClientConfig,
+};
+use rustls_platform_verifier::ConfigVerifierExt;
+use tokio::sync::{Mutex, watch};
+use tokio_rustls::rustls::{self, RootCertStore, pki_types::ServerName};
+
+/// The ALPN token that identifies DNS-over-QUIC, see https://datatracker.ietf.org/doc/html/rfc9250#section-4.1
+const ALPN: &[u8] = b"doq";
+
+/// Error codes that DNS-over-QUIC peers use to close connections and reset streams,
+/// see https://datatracker.ietf.org/doc/html/rfc9250#section-4.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError,
+    InternalError,
+    ProtocolError,
+    RequestCancelled,
+    ExcessiveLoad,
+    UnspecifiedError,
+    Unknown(u64),
+}
+
+impl From<u64> for ErrorCode {
+    fn from(code: u64) -> Self {
+        match code {
+            0x0 => ErrorCode::NoError,
+            0x1 => ErrorCode::InternalError,
+            0x2 => ErrorCode::ProtocolError,
+            0x3 => ErrorCode::RequestCancelled,
+            0x4 => ErrorCode::ExcessiveLoad,
+            0x5 => ErrorCode::UnspecifiedError,
+            code => ErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for VarInt {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::NoError => VarInt::from_u32(0x0),
+            ErrorCode::InternalError => VarInt::from_u32(0x1),
+            ErrorCode::ProtocolError => VarInt::from_u32(0x2),
+            ErrorCode::RequestCancelled => VarInt::from_u32(0x3),
+            ErrorCode::ExcessiveLoad => VarInt::from_u32(0x4),
+            ErrorCode::UnspecifiedError => VarInt::from_u32(0x5),
+            ErrorCode::Unknown(code) => VarInt::from_u64(code).unwrap_or(VarInt::MAX),
+        }
+    }
+}
+
+/// Sends DNS messages to a single upstream server via DNS-over-QUIC, see https://datatracker.ietf.org/doc/html/rfc9250
+///
+/// Every query is sent on its own stream of one shared connection, so a lost packet only delays the query it belongs
+/// to. As required by the RFC, queries are sent with request ID 0 and the query's original ID is restored in its
+/// answer. A connection that failed or was closed by the server is replaced by a new one on the next exchange, which
+/// resumes the previous TLS session and sends standard queries as 0-RTT data, since they are safe to replay.
+pub struct QuicConnection {
+    server: String,
+    server_name: String,
+    config: ClientConfig,
+    connect_timeout: Duration,
+    timeout: Duration,
+    session: Mutex<Option<Session>>,
+}
+
+/// A connection along with whether its handshake completed, which 0-RTT connections only do after their first
+/// queries were sent
+#[derive(Clone)]
+struct Session {
+    connection: Connection,
+    /// `None` until the handshake completes, then whether the server accepted the queries sent as 0-RTT data
+    handshake: watch::Receiver<Option<bool>>,
+}
+
+impl QuicConnection {
+    /// Connects to `server`, whose certificate has to be valid for `server_name` according to the system's trust store
+    pub fn new(server: impl Into<String>, server_name: &str) -> Result<Self, io::Error> {
+        let config = rustls::ClientConfig::with_platform_verifier().map_err(io::Error::other)?;
+        Self::with_config(server, server_name, config)
+    }
+
+    /// Connects to `server`, whose certificate has to be issued for `server_name` by one of `roots`
+    pub fn with_roots(
+        server: impl Into<String>,
+        server_name: &str,
+        roots: RootCertStore,
+    ) -> Result<Self, io::Error> {
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Self::with_config(server, server_name, config)
+    }
+
+    fn with_config(
+        server: impl Into<String>,
+        server_name: &str,
+        mut config: rustls::ClientConfig,
+    ) -> Result<Self, io::Error> {
+        ServerName::try_from(server_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        config.alpn_protocols = vec![ALPN.to_vec()];
+        config.enable_early_data = true;
+        let config = QuicClientConfig::try_from(config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        Ok(Self {
+            server: server.into(),
+            server_name: server_name.to_string(),
+            config: ClientConfig::new(Arc::new(config)),
+            connect_timeout: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+            session: Mutex::new(None),
+        })
+    }
+
+    /// Sets how long to wait for the connection and TLS handshake to be established
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets how long to wait for the answer once the request was sent
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends the raw DNS message `request` and returns the server's answer with the same request ID
+    pub async fn exchange(&self, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        if request.len() < 12 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "dns quic: request is too short",
+            ));
+        }
+        let len = u16::try_from(request.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "dns quic: message exceeds 65535 bytes",
+            )
+        })?;
+
+        let session = self.session().await?;
+        match self.exchange_on(&session, len, request).await {
+            // The server may have closed a reused connection in the meantime, or rejected the 0-RTT data, in which
+            // case the connection is still usable
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionAborted | io::ErrorKind::Interrupted
+                ) =>
+            {
+                let session = self.session().await?;
+                self.exchange_on(&session, len, request).await
+            }
+            result => result,
+        }
+    }
+
+    async fn exchange_on(
+        &self,
+        session: &Session,
+        len: u16,
+        request: &[u8],
+    ) -> Result<Vec<u8>, io::Error> {
+        // Only standard queries are safe to replay, so anything else waits for the handshake to complete,
+        // see https://datatracker.ietf.org/doc/html/rfc9250#section-4.5
+        let opcode = (request[2] >> 3) & 0b1111;
+        if opcode != 0 {
+            let mut handshake = session.handshake.clone();
+            let _ = handshake.wait_for(Option::is_some).await;
+        }
+
+        let (mut send, mut recv) = session
+            .connection
+            .open_bi()
+            .await
+            .map_err(connection_lost)?;
+
+        // The message is prefixed with its length like via TCP and the request ID has to be 0,
+        // see https://datatracker.ietf.org/doc/html/rfc9250#section-4.2
+        let mut framed = Vec::with_capacity(request.len() + 2);
+        framed.extend_from_slice(&len.to_be_bytes());
+        framed.extend_from_slice(&[0, 0]);
+        framed.extend_from_slice(&request[2..]);
+
+        let result = tokio::time::timeout(
+            self.timeout,
+            exchange_on_stream(&mut send, &mut recv, &framed),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            let _ = send.reset(ErrorCode::RequestCancelled.into());
+            let _ = recv.stop(ErrorCode::RequestCancelled.into());
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "dns quic: answer timed out",
+            ))
+        });
+
+        match result {
+            Ok(mut response) => {
+                response[..2].copy_from_slice(&request[..2]);
+                Ok(response)
+            }
+            Err(e) => {
+                if e.kind() == io::ErrorKind::InvalidData {
+                    session
+                        .connection
+                        .close(ErrorCode::ProtocolError.into(), b"malformed answer");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the current connection or establishes a new one, if there is none or it was closed
+    async fn session(&self) -> Result<Session, io::Error> {
+        let mut current = self.session.lock().await;
+        if let Some(session) = current
+            .as_ref()
+            .filter(|session| session.connection.close_reason().is_none())
+        {
+            return Ok(session.clone());
+        }
+
+        let session = self.connect().await?;
+        *current = Some(session.clone());
+        Ok(session)
+    }
+
+    async fn connect(&self) -> Result<Session, io::Error> {
+        let timed_out =
+            |_| io::Error::new(io::ErrorKind::TimedOut, "dns quic: connection timed out");
+
+        let address = tokio::time::timeout(
+            self.connect_timeout,
+            tokio::net::lookup_host(self.server.as_str()),
+        )
+        .await
+        .map_err(timed_out)??
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "dns quic: server address did not resolve",
+            )
+        })?;
+        let local: SocketAddr = match address {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        // The endpoint lives as long as its connection
+        let endpoint = Endpoint::client(local)?;
+        let connecting = endpoint
+            .connect_with(self.config.clone(), address, &self.server_name)
+            .map_err(io::Error::other)?;
+
+        match connecting.into_0rtt() {
+            Ok((connection, accepted)) => {
+                let (complete, handshake) = watch::channel(None);
+                tokio::spawn(async move {
+                    let accepted = accepted.await;
+                    let _ = complete.send(Some(accepted));
+                });
+                Ok(Session {
+                    connection,
+                    handshake,
+                })
+            }
+            Err(connecting) => {
+                let connection = tokio::time::timeout(self.connect_timeout, connecting)
+                    .await
+                    .map_err(timed_out)?
+                    .map_err(connection_lost)?;
+                let (_, handshake) = watch::channel(Some(false));
+                Ok(Session {
+                    connection,
+                    handshake,
+                })
+            }
+        }
+    }
+}
+
+impl Drop for QuicConnection {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.get_mut() {
+            session.connection.close(ErrorCode::NoError.into(), b"");
+        }
+    }
+}
+
+impl Debug for QuicConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuicConnection")
+            .field("server", &self.server)
+            .field("server_name", &self.server_name)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends the already framed query on its own stream and returns the unframed answer
+async fn exchange_on_stream(
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    framed: &[u8],
+) -> Result<Vec<u8>, io::Error> {
+    send.write_all(framed).await.map_err(|e| match e {
+        WriteError::Stopped(code) => stream_error(code),
+        WriteError::ConnectionLost(e) => connection_lost(e),
+        WriteError::ZeroRttRejected => zero_rtt_rejected(),
+        WriteError::ClosedStream => io::Error::other("dns quic: stream closed"),
+    })?;
+    // The server only answers once the stream is finished
+    send.finish()
+        .map_err(|_| io::Error::other("dns quic: stream closed"))?;
+
+    let response = recv
+        .read_to_end(2 + u16::MAX as usize)
+        .await
+        .map_err(|e| match e {
+            ReadToEndError::Read(ReadError::Reset(code)) => stream_error(code),
+            ReadToEndError::Read(ReadError::ConnectionLost(e)) => connection_lost(e),
+            ReadToEndError::Read(ReadError::ZeroRttRejected) => zero_rtt_rejected(),
+            ReadToEndError::Read(e) => io::Error::other(e),
+            ReadToEndError::TooLong => malformed(),
+        })?;
+
+    let len = response
+        .get(..2)
+        .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+        .ok_or_else(malformed)?;
+    if len != response.len() - 2 || len < 12 {
+        return Err(malformed());
+    }
+    Ok(response[2..].to_vec())
+}
+
+fn stream_error(code: VarInt) -> io::Error {
+    io::Error::other(format!(
+        "dns quic: server aborted the query with {:?}",
+        ErrorCode::from(code.into_inner())
+    ))
+}
+
+fn connection_lost(error: ConnectionError) -> io::Error {
+    let message = match error {
+        ConnectionError::ApplicationClosed(close) => format!(
+            "dns quic: server closed the connection with {:?}",
+            ErrorCode::from(close.error_code.into_inner())
+        ),
+        ConnectionError::TimedOut => {
+            return io::Error::new(io::ErrorKind::TimedOut, "dns quic: connection timed out");
+        }
+        error => format!("dns quic: {error}"),
+    };
+    io::Error::new(io::ErrorKind::ConnectionAborted, message)
+}
+
+fn zero_rtt_rejected() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Interrupted,
+        "dns quic: 0-RTT data was rejected",
+    )
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "dns quic: answer is malformed")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use quinn::{Endpoint, ServerConfig, crypto::rustls::QuicServerConfig};
+    use tokio_rustls::rustls::RootCertStore;
+
+    use crate::{
+        quic::{ALPN, ErrorCode, QuicConnection},
+        test_support::{self, query},
+    };
+
+    /// Issues certificates like `test_support::certificates` for a DNS-over-QUIC server that accepts 0-RTT data
+    fn certificates() -> (RootCertStore, ServerConfig) {
+        let (roots, mut config) = test_support::certificates();
+        config.alpn_protocols = vec![ALPN.to_vec()];
+        // Allows resumed connections to carry queries as 0-RTT data
+        config.max_early_data_size = u32::MAX;
+        let config =
+            ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(config).unwrap()));
+        (roots, config)
+    }
+
+    /// How the stub server treats the queries it receives
+    #[derive(Clone, Copy)]
+    enum Behaviour {
+        Answer,
+        /// Answers and then closes the connection
+        AnswerOnce,
+        /// Resets every stream with the given error code
+        Reset(ErrorCode),
+    }
+
+    /// Echoes every query as answer on its stream, which checks that queries are sent with request ID 0
+    async fn stub_server(config: ServerConfig, behaviour: Behaviour) -> (String, Arc<AtomicUsize>) {
+        let endpoint = Endpoint::server(config, SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let address = endpoint.local_addr().unwrap().to_string();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let Ok(connection) = incoming.await else {
+                        return;
+                    };
+                    while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                        let mut query = recv.read_to_end(u16::MAX as usize).await.unwrap();
+                        assert_eq!(query[2..4], [0, 0], "request ID has to be 0");
+
+                        match behaviour {
+                            Behaviour::Reset(code) => {
+                                send.reset(code.into()).unwrap();
+                            }
+                            Behaviour::Answer | Behaviour::AnswerOnce => {
+                                query[4] |= 0b1000_0000;
+                                send.write_all(&query).await.unwrap();
+                                send.finish().unwrap();
+                            }
+                        }
+                        if let Behaviour::AnswerOnce = behaviour {
+                            let _ = send.stopped().await;
+                            connection.close(ErrorCode::NoError.into(), b"");
+                        }
+                    }
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    #[tokio::test]
+    async fn test_exchange_reuses_connection() {
+        let (roots, config) = certificates();
+        let (address, connections) = stub_server(config, Behaviour::Answer).await;
+        let connection = QuicConnection::with_roots(address, "dns.test", roots).unwrap();
+
+        let (first, second) = (query(1), query(2));
+        let (first, second) =
+            tokio::join!(connection.exchange(&first), connection.exchange(&second));
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(first[..3], [0x13, 0x37, 0x81]);
+        assert_eq!(first[12], 1);
+        assert_eq!(second[..3], [0x13, 0x37, 0x81]);
+        assert_eq!(second[12], 2);
+
+        assert_eq!(connection.exchange(&query(3)).await.unwrap()[12], 3);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_reconnects_closed_connection() {
+        let (roots, config) = certificates();
+        let (address, connections) = stub_server(config, Behaviour::AnswerOnce).await;
+        let connection = QuicConnection::with_roots(address, "dns.test", roots).unwrap();
+
+        for marker in 0..3 {
+            let answer = connection.exchange(&query(marker)).await.unwrap();
+            assert_eq!(answer[12], marker);
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    /// Waits for the handshake of the current connection and returns whether the server accepted its 0-RTT data
+    async fn accepted_0rtt(connection: &QuicConnection) -> bool {
+        let session = connection.session.lock().await.clone().unwrap();
+        let mut handshake = session.handshake;
+        handshake.wait_for(Option::is_some).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exchange_resumes_session_with_0rtt() {
+        let (roots, config) = certificates();
+        let (address, connections) = stub_server(config, Behaviour::AnswerOnce).await;
+        let connection = QuicConnection::with_roots(address, "dns.test", roots).unwrap();
+
+        // There is no session to resume yet, so the first connection waits for its handshake
+        assert_eq!(connection.exchange(&query(1)).await.unwrap()[12], 1);
+        assert!(!accepted_0rtt(&connection).await);
+        let session = connection.session.lock().await.clone().unwrap();
+        session.connection.closed().await;
+
+        // The reconnect resumes the session of the closed connection and sends the query as 0-RTT data
+        assert_eq!(connection.exchange(&query(2)).await.unwrap()[12], 2);
+        assert!(accepted_0rtt(&connection).await);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_reports_error_code() {
+        let (roots, config) = certificates();
+        let (address, _) = stub_server(config, Behaviour::Reset(ErrorCode::ExcessiveLoad)).await;
+        let connection = QuicConnection::with_roots(address, "dns.test", roots).unwrap();
+
+        let error = connection.exchange(&query(1)).await.unwrap_err();
+        assert!(error.to_string().contains("ExcessiveLoad"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_exchange_validates_certificate() {
+        let (_, config) = certificates();
+        let (untrusted_roots, _) = certificates();
+        let (address, _) = stub_server(config, Behaviour::Answer).await;
+
+        let connection = QuicConnection::with_roots(address, "dns.test", untrusted_roots).unwrap();
+        assert!(connection.exchange(&query(1)).await.is_err());
+    }
+
+    #[test]
+    fn test_error_code_conversion() {
+        for code in 0..=6u64 {
+            let error_code = ErrorCode::from(code);
+            assert_eq!(quinn::VarInt::from(error_code).into_inner(), code);
+        }
+        assert_eq!(ErrorCode::from(0x4), ErrorCode::ExcessiveLoad);
+        assert_eq!(ErrorCode::from(0xd098ea5e), ErrorCode::Unknown(0xd098ea5e));
+    }
+}
@@ -1,77 +1,83 @@
-use std::{net::UdpSocket, time::Duration};
+use std::{
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
 
 use crate::{
-    parser::{DnsPacketBuffer, DnsParser, encode_domain_name},
-    protocol::answer::ResourceRecord,
-    serialize::generate_nx_response,
+    client::{MAX_UDP_RESPONSE, random_id},
+    parser::{DnsPacketBuffer, DnsParser},
+    protocol::{answer::ResourceRecord, query::Query},
+    serialize::{generate_nx_response, serialize_query},
+    tcp::{frame, read_message_blocking},
 };
 
-/// Synchronously resolves INternet A records for `domain` using the DNS server `dns`
+/// How long `resolve_domain` waits for an answer
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `resolve_domain` waits for a TCP connection to be established
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Synchronously resolves `query` using the DNS server `dns`
+///
+/// Without an explicit `id`, a random request ID is used. Truncated answers are retried via TCP. Gives up after
+/// `RESOLVE_TIMEOUT` without an answer, see `client::Client` for a resolver that also retries and verifies answers.
 pub fn resolve_domain(
-    domain: &str,
+    query: &Query,
     dns: &str,
     id: Option<u16>,
     socket: Option<UdpSocket>,
-) -> Result<(Vec<ResourceRecord>, [u8; 512]), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(Vec<ResourceRecord>, Vec<u8>), Box<dyn std::error::Error + Send + Sync>> {
     let socket = socket.unwrap_or_else(|| UdpSocket::bind(("0.0.0.0", 0)).unwrap());
-
-    let request = generate_request(domain, id);
+    socket.set_read_timeout(Some(RESOLVE_TIMEOUT))?;
+
+    let id = match id {
+        Some(id) => id,
+        None => random_id()?,
+    };
+    let domain = &query.domain;
+    let request = serialize_query(query, id);
     if let Err(e) = socket.send_to(&request, dns) {
         println!("Failed to send request for {domain} to {dns:?}: {e:?}");
         return Err(e.into());
     }
 
-    let mut response = [0; 512];
-    let (_, _) = socket.recv_from(&mut response).map_err(|e| {
+    let mut response = vec![0; MAX_UDP_RESPONSE];
+    let (len, _) = socket.recv_from(&mut response).map_err(|e| {
         println!("Failed to receive response for {domain} from {dns:?}: {e:?}");
         e
     })?;
-
-    let packet = DnsParser::new(&response).parse()?;
-    Ok((packet.answers, response))
-}
-
-#[allow(unused)]
-async fn resolve_domain_async(
-    domain: &str,
-    dns: &str,
-    id: Option<u16>,
-    socket: &tokio::net::UdpSocket,
-) -> Result<(Vec<ResourceRecord>, [u8; 512]), Box<dyn std::error::Error + Send + Sync>> {
-    let request = generate_request(domain, id);
-    if let Err(e) = socket.send_to(&request, dns).await {
-        println!("Failed to send request for {domain} to {dns:?}: {e:?}");
-        return Err(e.into());
+    response.truncate(len);
+    let mut packet = DnsParser::new(&response).parse()?;
+    if packet.header.flags.truncation {
+        response = resolve_over_tcp(&request, dns)?;
+        packet = DnsParser::new(&response).parse()?;
     }
 
-    let mut response = [0; 512];
-    let (_, _) = socket.recv_from(&mut response).await.map_err(|e| {
-        println!("Failed to receive response for {domain} from {dns:?}: {e:?}");
-        e
-    })?;
-
-    let packet = DnsParser::new(&response).parse()?;
     Ok((packet.answers, response))
 }
 
-/// Asynchronously send the incoming raw DNS packet to the relay DNS server and
-/// pipes the response back to the originating socket.
-pub async fn relay_query_async(
-    original_query: &DnsPacketBuffer,
-    upstream_dns: &str,
-    socket: &tokio::net::UdpSocket,
-) -> Result<DnsPacketBuffer, Box<dyn std::error::Error + Send + Sync>> {
-    if let Err(e) = socket.send_to(original_query, upstream_dns).await {
-        println!("Failed to send request to {upstream_dns:?}: {e:?}");
-        return Err(e.into());
+/// Sends the raw DNS message `request` to the DNS server `dns` via TCP and returns its answer
+///
+/// A new connection is opened for every query, since the blocking `resolve_domain` has nowhere to keep one open,
+/// see `tcp::TcpConnection` for one that is reused.
+fn resolve_over_tcp(request: &[u8], dns: &str) -> Result<Vec<u8>, io::Error> {
+    let address = dns
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address for dns server"))?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(RESOLVE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESOLVE_TIMEOUT))?;
+
+    stream.write_all(&frame(request)?)?;
+    let response = read_message_blocking(&mut stream)?;
+    if response.get(..2) != request.get(..2) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "dns tcp: answer has a different request ID",
+        ));
     }
-
-    let mut response = [0; 512];
-    let (_, _) = socket.recv_from(&mut response).await.map_err(|e| {
-        println!("Failed to receive response from {upstream_dns:?}: {e:?}");
-        e
-    })?;
-
     Ok(response)
 }
 
@@ -86,45 +92,77 @@ pub async fn stub_response_with_delay(
     Ok((packet.answers, response))
 }
 
-/// Generates a recursive DNS query for INternet A records
-pub(crate) fn generate_request(domain: &str, id: Option<u16>) -> Vec<u8> {
-    const DEFAULT_ID: [u8; 2] = [(1337u16 >> 4) as u8, (1337 & 0xFF) as u8];
-    let id = id
-        .map(|n| [(n >> 8) as u8, (n & 0xFF) as u8])
-        .unwrap_or(DEFAULT_ID);
-    const QTYPE: [u8; 2] = [0x00, 0x01];
-    const QCLASS: [u8; 2] = [0x00, 0x01];
-
-    let request_header: [u8; 12] = [
-        id[0], id[1], // identification
-        0x01, 0x00, // flags
-        0x00, 0x01, // question section
-        0x00, 0x00, // answer section
-        0x00, 0x00, // authority section
-        0x00, 0x00, // additional section
-    ];
-    let mut request = Vec::with_capacity(16 + domain.len());
-    request.extend(request_header);
-    request.extend(encode_domain_name(domain));
-    request.extend(QTYPE);
-    request.extend(QCLASS);
-    request
-}
-
 #[cfg(test)]
 mod tests {
-    use crate::{protocol::answer::ResourceRecordData, resolver::resolve_domain};
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, UdpSocket},
+        thread,
+    };
+
+    use crate::{
+        protocol::{answer::ResourceRecordData, query::Query, record_type::RecordType},
+        resolver::resolve_domain,
+    };
 
     const DNS_SERVERS: [&str; 1] = ["1.1.1.1:53"];
 
     #[test]
     fn test_resolve_a_records() {
         for dns_root in DNS_SERVERS {
-            let (answers, _) = resolve_domain("www.example.com", dns_root, None, None).unwrap();
+            let query = Query::new("www.example.com", RecordType::A);
+            let (answers, _) = resolve_domain(&query, dns_root, None, None).unwrap();
             assert!(matches!(
                 answers.last().unwrap().value,
                 ResourceRecordData::A { ipv4: _ }
             ));
         }
     }
+
+    #[test]
+    fn test_resolve_truncated_via_tcp() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(address).unwrap();
+
+        // Only answers with the header and question and sets TC
+        thread::spawn(move || {
+            let mut request = [0u8; 512];
+            let (len, client) = udp.recv_from(&mut request).unwrap();
+            let mut truncated = request[..len].to_vec();
+            truncated[2] |= 0b1000_0010;
+            udp.send_to(&truncated, client).unwrap();
+        });
+
+        // Answers with more A records than fit into 512 bytes
+        thread::spawn(move || {
+            let (mut stream, _) = tcp.accept().unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut response).unwrap();
+
+            response[2] |= 0b1000_0000;
+            response[6..8].copy_from_slice(&40u16.to_be_bytes());
+            for i in 0..40u8 {
+                // Name pointer to the question, type A, class IN, TTL 60, 4 bytes of data
+                response.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                response.extend_from_slice(&[192, 0, 2, i]);
+            }
+            let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+            framed.extend_from_slice(&response);
+            stream.write_all(&framed).unwrap();
+        });
+
+        let query = Query::new("www.example.com", RecordType::A);
+        let (answers, response) = resolve_domain(&query, &address.to_string(), None, None).unwrap();
+        assert!(response.len() > 512);
+        assert_eq!(answers.len(), 40);
+        assert_eq!(
+            answers[39].value,
+            ResourceRecordData::A {
+                ipv4: [192, 0, 2, 39].into()
+            }
+        );
+    }
 }
@@ -0,0 +1,58 @@
+use alloc::string::String;
+use core::{fmt::Write, net::IpAddr};
+
+/// Builds the domain name to query PTR records of `ip` for, see https://datatracker.ietf.org/doc/html/rfc1035#section-3.5
+/// and https://datatracker.ietf.org/doc/html/rfc3596#section-2.5.
+///
+/// IPv4 addresses are written as their octets in reverse order below `in-addr.arpa`, IPv6 addresses as their nibbles
+/// in reverse order below `ip6.arpa`.
+///
+/// ```
+/// use std::net::IpAddr;
+///
+/// let ip: IpAddr = "192.0.2.1".parse().unwrap();
+/// assert_eq!(dns::reverse_name(ip), "1.2.0.192.in-addr.arpa");
+/// ```
+pub fn reverse_name(ip: IpAddr) -> String {
+    let mut name = String::with_capacity(72);
+    match ip {
+        IpAddr::V4(ipv4) => {
+            for octet in ipv4.octets().iter().rev() {
+                write!(name, "{octet}.").unwrap();
+            }
+            name.push_str("in-addr.arpa");
+        }
+        IpAddr::V6(ipv6) => {
+            for octet in ipv6.octets().iter().rev() {
+                write!(name, "{:x}.{:x}.", octet & 0xf, octet >> 4).unwrap();
+            }
+            name.push_str("ip6.arpa");
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use crate::reverse::reverse_name;
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(
+            reverse_name(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 254))),
+            "254.0.0.10.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name(IpAddr::V6(Ipv6Addr::new(
+                0x2001, 0xdb8, 0, 0, 0, 0, 0x0567, 0x89ab
+            ))),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+        assert_eq!(
+            reverse_name(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.ip6.arpa"
+        );
+    }
+}
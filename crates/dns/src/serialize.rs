@@ -1,12 +1,20 @@
+use alloc::vec::Vec;
+
 use crate::{
-    parser::DnsPacketBuffer,
+    Error,
+    parser::{DnsPacketBuffer, encode_domain_name},
     protocol::{
+        answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
         header::{Flags, Header},
+        packet::DnsPacket,
+        query::Query,
+        record_type::RecordType,
         response_code::ResponseCode,
+        update::UpdatePacket,
     },
 };
 
-pub fn generate_nx_response(id: u16) -> Result<DnsPacketBuffer, Box<dyn std::error::Error>> {
+pub fn generate_nx_response(id: u16) -> Result<DnsPacketBuffer, Error> {
     let flags = Flags {
         response_code: ResponseCode::NXDOMAIN.into(),
         query: false,
@@ -28,7 +36,7 @@ pub fn generate_nx_response(id: u16) -> Result<DnsPacketBuffer, Box<dyn std::err
 pub fn generate_response_with_answer(
     id: u16,
     response_code: ResponseCode,
-) -> Result<DnsPacketBuffer, Box<dyn std::error::Error>> {
+) -> Result<DnsPacketBuffer, Error> {
     let flags = Flags {
         response_code: response_code.into(),
         query: false,
@@ -47,3 +55,217 @@ pub fn generate_response_with_answer(
     packet.extend_from_slice(&[0; 500]);
     Ok(packet.try_into().unwrap())
 }
+
+/// Generates the header and question of the `response` with the truncation flag set, which tells clients to repeat
+/// their query via TCP, for when the complete response does not fit into a UDP datagram.
+pub fn generate_truncated_response(response: &DnsPacket) -> Vec<u8> {
+    let header = Header {
+        flags: Flags {
+            truncation: true,
+            ..response.header.flags.clone()
+        },
+        question_count: 1,
+        answer_count: 0,
+        authority_count: 0,
+        additional_count: 0,
+        ..response.header.clone()
+    };
+
+    let mut out = Vec::with_capacity(16 + response.question.domain_name.len());
+    let h: [u8; 12] = header.into();
+    out.extend_from_slice(&h);
+    out.extend_from_slice(&encode_domain_name(&response.question.domain_name));
+    out.extend_from_slice(&u16::from(response.question.r#type).to_be_bytes());
+    out.extend_from_slice(&response.question.class.to_be_bytes());
+    out
+}
+
+/// Serializes a query with the request ID `id`, see https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.
+pub fn serialize_query(query: &Query, id: u16) -> Vec<u8> {
+    let flags = Flags {
+        query: true,
+        recursion_desired: query.recursion_desired,
+        z: if query.checking_disabled {
+            Flags::CHECKING_DISABLED
+        } else {
+            0
+        },
+        ..Flags::default()
+    };
+
+    let header = Header {
+        request_id: id,
+        flags,
+        question_count: 1,
+        additional_count: query.edns.is_some().into(),
+        ..Header::default()
+    };
+
+    let mut out = Vec::with_capacity(32 + query.domain.len());
+    let h: [u8; 12] = header.into();
+    out.extend_from_slice(h.as_slice());
+    out.extend(encode_domain_name(&query.domain));
+    out.extend_from_slice(&u16::from(query.record_type).to_be_bytes());
+    out.extend_from_slice(&query.class.to_be_bytes());
+
+    // The OPT pseudo record re-purposes CLASS as the UDP payload size and TTL as extended RCODE, version and flags,
+    // see https://datatracker.ietf.org/doc/html/rfc6891#section-6.1.2
+    if let Some(edns) = &query.edns {
+        let opt = ResourceRecord::new(
+            ResourceRecordMeta {
+                name: "".into(),
+                record_type: RecordType::OPT,
+                class: edns.udp_payload_size,
+                ttl: if edns.dnssec_ok { 0x8000 } else { 0 },
+                len: 0,
+            },
+            ResourceRecordData::Empty,
+        );
+        encode_resource_record(&mut out, &opt)
+            .expect("OPT records without options are serializable");
+    }
+
+    out
+}
+
+/// Serializes a response with a single question, see https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.
+///
+/// The section counts in the header are derived from the sections, so they never disagree. Domain names are not
+/// compressed, and records whose data the parser does not understand cannot be serialized.
+pub fn serialize_response(packet: &DnsPacket) -> Result<Vec<u8>, Error> {
+    let header = Header {
+        question_count: 1,
+        answer_count: packet.answers.len() as u16,
+        authority_count: packet.authorities.len() as u16,
+        additional_count: packet.additional.len() as u16,
+        ..packet.header.clone()
+    };
+
+    let mut out = Vec::with_capacity(512);
+    let h: [u8; 12] = header.into();
+    out.extend_from_slice(h.as_slice());
+    out.extend(encode_domain_name(&packet.question.domain_name));
+    out.extend_from_slice(&u16::from(packet.question.r#type).to_be_bytes());
+    out.extend_from_slice(&packet.question.class.to_be_bytes());
+
+    for record in packet
+        .answers
+        .iter()
+        .chain(&packet.authorities)
+        .chain(&packet.additional)
+    {
+        encode_resource_record(&mut out, record)?;
+    }
+
+    Ok(out)
+}
+
+/// Serializes a DNS UPDATE message, see https://datatracker.ietf.org/doc/html/rfc2136#section-2.
+///
+/// The section counts in the header are derived from the sections, so they never disagree.
+pub fn serialize_update(packet: &UpdatePacket) -> Result<Vec<u8>, Error> {
+    let header = Header {
+        question_count: 1,
+        answer_count: packet.prerequisites.len() as u16,
+        authority_count: packet.updates.len() as u16,
+        additional_count: packet.additional.len() as u16,
+        ..packet.header.clone()
+    };
+
+    let mut out = Vec::with_capacity(512);
+    let h: [u8; 12] = header.into();
+    out.extend_from_slice(h.as_slice());
+
+    // Zone section, whose type is always SOA
+    out.extend(encode_domain_name(&packet.zone.name));
+    out.extend_from_slice(&u16::from(RecordType::SOA).to_be_bytes());
+    out.extend_from_slice(&packet.zone.class.to_be_bytes());
+
+    for prerequisite in &packet.prerequisites {
+        encode_resource_record(&mut out, &prerequisite.to_record(packet.zone.class))?;
+    }
+    for update in &packet.updates {
+        encode_resource_record(&mut out, &update.to_record(packet.zone.class))?;
+    }
+    for record in &packet.additional {
+        encode_resource_record(&mut out, record)?;
+    }
+
+    Ok(out)
+}
+
+/// Appends the wire format of `record` to `out`, see https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.3.
+///
+/// `RDLENGTH` is derived from the encoded RR data and `record.meta.len` is ignored.
+pub fn encode_resource_record(out: &mut Vec<u8>, record: &ResourceRecord) -> Result<(), Error> {
+    let data = encode_record_data(&record.value)
+        .map_err(|_| Error::UnsupportedRecordData(record.meta.record_type))?;
+
+    out.extend(encode_domain_name(&record.meta.name));
+    out.extend_from_slice(&u16::from(record.meta.record_type).to_be_bytes());
+    out.extend_from_slice(&record.meta.class.to_be_bytes());
+    out.extend_from_slice(&record.meta.ttl.to_be_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend(data);
+    Ok(())
+}
+
+/// Encodes the `RDATA` field for all record types that the parser understands.
+pub fn encode_record_data(data: &ResourceRecordData) -> Result<Vec<u8>, Error> {
+    let encoded = match data {
+        ResourceRecordData::A { ipv4 } => ipv4.octets().to_vec(),
+        ResourceRecordData::AAAA { ipv6 } => ipv6.octets().to_vec(),
+        ResourceRecordData::CNAME { cname: name }
+        | ResourceRecordData::NS { ns: name }
+        | ResourceRecordData::MB { domain_name: name }
+        | ResourceRecordData::PTR { domain_name: name } => encode_domain_name(name),
+        ResourceRecordData::MX {
+            preference,
+            exchange,
+        } => {
+            let mut encoded = preference.to_be_bytes().to_vec();
+            encoded.extend(encode_domain_name(exchange));
+            encoded
+        }
+        ResourceRecordData::SOA {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => {
+            let mut encoded = encode_domain_name(mname);
+            encoded.extend(encode_domain_name(rname));
+            for value in [serial, refresh, retry, expire, minimum] {
+                encoded.extend_from_slice(&value.to_be_bytes());
+            }
+            encoded
+        }
+        ResourceRecordData::TSIG {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        } => {
+            let mut encoded = encode_domain_name(algorithm);
+            encoded.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+            encoded.extend_from_slice(&fudge.to_be_bytes());
+            encoded.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(mac);
+            encoded.extend_from_slice(&original_id.to_be_bytes());
+            encoded.extend_from_slice(&error.to_be_bytes());
+            encoded.extend_from_slice(&(other.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(other);
+            encoded
+        }
+        ResourceRecordData::Empty => Vec::new(),
+        ResourceRecordData::Raw { data, .. } => data.clone(),
+        ResourceRecordData::Unknown => return Err(Error::UntypedRecordData),
+    };
+    Ok(encoded)
+}
@@ -0,0 +1,602 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    client::{Client, ClientError, ptr_names},
+    protocol::{
+        answer::ResourceRecordData, packet::DnsPacket, query::Query, record_type::RecordType,
+        response_code::ResponseCode,
+    },
+    reverse::reverse_name,
+};
+
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+/// glibc only uses the first three name servers, see `MAXNS` in `resolv.h`
+const MAX_NAMESERVERS: usize = 3;
+
+/// glibc only uses the first six search domains, see `MAXDNSRCH` in `resolv.h`
+const MAX_SEARCH_DOMAINS: usize = 6;
+
+/// The resolver configuration of the host, see https://man7.org/linux/man-pages/man5/resolv.conf.5.html
+///
+/// Parsing is as lenient as glibc's: unknown keywords and malformed values are ignored, and options are clamped to
+/// the limits glibc enforces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvConf {
+    /// The name servers to ask in order, which default to the local host if there are none
+    pub nameservers: Vec<SocketAddr>,
+    /// The domains to append to names with less than `ndots` dots, from the last `search` or `domain` line
+    pub search: Vec<String>,
+    /// How many dots a name needs to be tried as is before the search domains are appended
+    pub ndots: u8,
+    /// How long to wait for each name server
+    pub timeout: Duration,
+    /// How often to ask all name servers before giving up
+    pub attempts: u8,
+    /// Whether to spread queries across the name servers instead of always asking the first one first
+    pub rotate: bool,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        Self {
+            nameservers: vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53)],
+            search: vec![],
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            rotate: false,
+        }
+    }
+}
+
+impl ResolvConf {
+    /// Reads `/etc/resolv.conf` and applies the `LOCALDOMAIN` and `RES_OPTIONS` environment variables like glibc,
+    /// falling back to the defaults if the file cannot be read
+    pub fn load() -> Self {
+        let mut conf = Self::from_path(RESOLV_CONF_PATH).unwrap_or_default();
+        if let Ok(domains) = std::env::var("LOCALDOMAIN") {
+            conf.search = parse_search(domains.split_whitespace());
+        }
+        if let Ok(options) = std::env::var("RES_OPTIONS") {
+            conf.apply_options(options.split_whitespace());
+        }
+        conf
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut conf = Self {
+            nameservers: vec![],
+            ..Self::default()
+        };
+
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("nameserver") if conf.nameservers.len() < MAX_NAMESERVERS => {
+                    // Scope IDs of link-local addresses are ignored
+                    let address = fields.next().and_then(|ip| {
+                        ip.split_once('%')
+                            .map_or(ip, |(ip, _)| ip)
+                            .parse::<IpAddr>()
+                            .ok()
+                    });
+                    conf.nameservers
+                        .extend(address.map(|ip| SocketAddr::new(ip, 53)));
+                }
+                Some("domain") => conf.search = parse_search(fields.take(1)),
+                Some("search") => conf.search = parse_search(fields),
+                Some("options") => conf.apply_options(fields),
+                _ => {}
+            }
+        }
+
+        if conf.nameservers.is_empty() {
+            conf.nameservers = Self::default().nameservers;
+        }
+        conf
+    }
+
+    fn apply_options<'a>(&mut self, options: impl Iterator<Item = &'a str>) {
+        for option in options {
+            let (name, value) = option.split_once(':').unwrap_or((option, ""));
+            let value = value.parse::<u32>().ok();
+            match (name, value) {
+                ("ndots", Some(ndots)) => self.ndots = ndots.min(15) as u8,
+                ("timeout", Some(timeout)) => {
+                    self.timeout = Duration::from_secs(timeout.clamp(1, 30).into())
+                }
+                ("attempts", Some(attempts)) => self.attempts = attempts.clamp(1, 5) as u8,
+                ("rotate", _) => self.rotate = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the names to try in order when resolving `name`, see `res_search` in glibc
+    ///
+    /// Names ending with a dot are fully qualified and tried as is only. Names with at least `ndots` dots are tried
+    /// as is first and with the search domains appended afterwards, all others the other way around.
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        if let Some(name) = name.strip_suffix('.') {
+            return vec![name.to_string()];
+        }
+
+        let searched = self.search.iter().map(|domain| format!("{name}.{domain}"));
+        if name.matches('.').count() >= self.ndots.into() {
+            std::iter::once(name.to_string()).chain(searched).collect()
+        } else {
+            searched.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+fn parse_search<'a>(domains: impl Iterator<Item = &'a str>) -> Vec<String> {
+    domains
+        .map(|domain| domain.trim_end_matches('.').to_ascii_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .take(MAX_SEARCH_DOMAINS)
+        .collect()
+}
+
+/// The static host names of the host, see https://man7.org/linux/man-pages/man5/hosts.5.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hosts {
+    addresses: HashMap<String, Vec<IpAddr>>,
+    names: HashMap<IpAddr, Vec<String>>,
+}
+
+impl Hosts {
+    /// Reads `/etc/hosts`, which is treated as empty if it cannot be read
+    pub fn load() -> Self {
+        Self::from_path(HOSTS_PATH).unwrap_or_default()
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut hosts = Self::default();
+
+        for line in content.lines() {
+            let line = line.split_once('#').map_or(line, |(line, _)| line);
+            let mut fields = line.split_whitespace();
+            let Some(Ok(ip)) = fields.next().map(str::parse::<IpAddr>) else {
+                continue;
+            };
+
+            // The first name is the canonical one, all others are aliases
+            for name in fields {
+                let name = name.trim_end_matches('.').to_ascii_lowercase();
+                let addresses = hosts.addresses.entry(name.clone()).or_default();
+                if !addresses.contains(&ip) {
+                    addresses.push(ip);
+                }
+                let names = hosts.names.entry(ip).or_default();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        hosts
+    }
+
+    /// Returns the addresses of `name` in the order they are listed
+    pub fn addresses(&self, name: &str) -> &[IpAddr] {
+        self.addresses
+            .get(&name.trim_end_matches('.').to_ascii_lowercase())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the names of `ip`, starting with the canonical name of its first line
+    pub fn names(&self, ip: IpAddr) -> &[String] {
+        self.names.get(&ip).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Resolves names like the host's C library would, using its `ResolvConf` and `Hosts`.
+///
+/// Queries go to the configured name servers one after another and names are expanded with the search domains, see
+/// `ResolvConf::candidates`. Address lookups via `lookup_ip` consult the hosts file first.
+#[derive(Debug)]
+pub struct SystemResolver {
+    conf: ResolvConf,
+    hosts: Hosts,
+    /// Where to start in the list of name servers with the `rotate` option
+    next_server: AtomicUsize,
+}
+
+impl SystemResolver {
+    pub fn new(conf: ResolvConf, hosts: Hosts) -> Self {
+        Self {
+            conf,
+            hosts,
+            next_server: AtomicUsize::new(0),
+        }
+    }
+
+    /// Uses the configuration of the host, see `ResolvConf::load` and `Hosts::load`
+    pub fn load() -> Self {
+        Self::new(ResolvConf::load(), Hosts::load())
+    }
+
+    pub fn conf(&self) -> &ResolvConf {
+        &self.conf
+    }
+
+    /// Resolves `query` for every candidate name of its domain until one has answers, and returns the answer for
+    /// the last candidate otherwise
+    pub async fn query(&self, query: &Query) -> Result<DnsPacket, ClientError> {
+        let mut last = Err(ClientError::Timeout);
+        for candidate in self.conf.candidates(&query.domain) {
+            let query = Query {
+                domain: candidate,
+                ..query.clone()
+            };
+            last = match self.query_servers(&query).await {
+                Ok(packet)
+                    if packet.header.flags.response_code == u8::from(ResponseCode::NOERROR)
+                        && !packet.answers.is_empty() =>
+                {
+                    return Ok(packet);
+                }
+                // Both a missing name and a name without records of the type continue the search
+                Ok(packet) => Ok(packet),
+                Err(e) => match last {
+                    Ok(packet) => Ok(packet),
+                    Err(_) => Err(e),
+                },
+            };
+        }
+        last
+    }
+
+    /// Returns the IPv4 and IPv6 addresses of `name`, preferring the hosts file over querying the name servers
+    pub async fn lookup_ip(&self, name: &str) -> Result<Vec<IpAddr>, ClientError> {
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let addresses = self.hosts.addresses(name);
+        if !addresses.is_empty() {
+            return Ok(addresses.to_vec());
+        }
+
+        let ipv4_query = Query::new(name, RecordType::A);
+        let ipv6_query = Query::new(name, RecordType::AAAA);
+        let (ipv4, ipv6) = match tokio::join!(self.query(&ipv4_query), self.query(&ipv6_query)) {
+            (Err(e), Err(_)) => return Err(e),
+            answers => answers,
+        };
+
+        Ok(ipv4
+            .into_iter()
+            .chain(ipv6)
+            .flat_map(|packet| packet.answers)
+            .filter_map(|record| match record.value {
+                ResourceRecordData::A { ipv4 } => Some(ipv4.into()),
+                ResourceRecordData::AAAA { ipv6 } => Some(ipv6.into()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the domain names that `ip` points back to, preferring the hosts file over querying the name servers
+    /// for its PTR records, see `Client::resolve_ptr`
+    pub async fn resolve_ptr(&self, ip: IpAddr) -> Result<Vec<String>, ClientError> {
+        let names = self.hosts.names(ip);
+        if !names.is_empty() {
+            return Ok(names.to_vec());
+        }
+
+        // Reverse names are fully qualified, so the search domains are not appended to them
+        let packet = self
+            .query_servers(&Query::new(&reverse_name(ip), RecordType::PTR))
+            .await?;
+        Ok(ptr_names(packet))
+    }
+
+    /// Asks the name servers in turn until one gives a definite answer, for the configured number of attempts
+    async fn query_servers(&self, query: &Query) -> Result<DnsPacket, ClientError> {
+        let servers = &self.conf.nameservers;
+        let start = if self.conf.rotate {
+            self.next_server.fetch_add(1, Ordering::Relaxed) % servers.len()
+        } else {
+            0
+        };
+
+        let mut last = Err(ClientError::Timeout);
+        for _ in 0..self.conf.attempts {
+            for &server in servers.iter().cycle().skip(start).take(servers.len()) {
+                let client = Client::new(server)
+                    .with_timeout(self.conf.timeout)
+                    .with_retries(0);
+                match client.query(query).await {
+                    Ok(packet)
+                        if packet.header.flags.response_code == u8::from(ResponseCode::NOERROR)
+                            || packet.header.flags.response_code
+                                == u8::from(ResponseCode::NXDOMAIN) =>
+                    {
+                        return Ok(packet);
+                    }
+                    // Servers that fail or refuse to answer are skipped, but their answer is kept in case no
+                    // other server does better
+                    Ok(packet) => last = Ok(packet),
+                    Err(e) if last.is_err() => last = Err(e),
+                    Err(_) => {}
+                }
+            }
+        }
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, SocketAddr},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::net::UdpSocket;
+
+    use crate::{
+        parser::DnsParser,
+        protocol::{
+            answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+            query::Query,
+            record_type::RecordType,
+            response_code::ResponseCode,
+        },
+        serialize::serialize_response,
+        system::{Hosts, ResolvConf, SystemResolver},
+    };
+
+    #[test]
+    fn test_resolv_conf_parse() {
+        let conf = ResolvConf::parse(
+            "# Generated by NetworkManager
+            domain home.arpa
+            search corp.example. lab.corp.example
+            nameserver 192.0.2.53
+            ; comment
+            nameserver fe80::1%eth0
+            nameserver not-an-ip
+            nameserver 2001:db8::53
+            nameserver 192.0.2.54
+            options ndots:20 timeout:0 attempts:3 rotate edns0
+            sortlist 130.155.160.0/255.255.240.0",
+        );
+        assert_eq!(
+            conf,
+            ResolvConf {
+                nameservers: ["192.0.2.53:53", "[fe80::1]:53", "[2001:db8::53]:53"]
+                    .map(|address| address.parse().unwrap())
+                    .to_vec(),
+                search: vec!["corp.example".into(), "lab.corp.example".into()],
+                ndots: 15,
+                timeout: Duration::from_secs(1),
+                attempts: 3,
+                rotate: true,
+            }
+        );
+
+        assert_eq!(ResolvConf::parse(""), ResolvConf::default());
+        assert_eq!(
+            ResolvConf::parse("search a.example b.example\ndomain c.example").search,
+            ["c.example"]
+        );
+    }
+
+    #[test]
+    fn test_resolv_conf_candidates() {
+        let conf = ResolvConf {
+            search: vec!["corp.example".into(), "example".into()],
+            ..ResolvConf::default()
+        };
+        assert_eq!(
+            conf.candidates("intranet"),
+            ["intranet.corp.example", "intranet.example", "intranet"]
+        );
+        assert_eq!(
+            conf.candidates("www.example.org"),
+            [
+                "www.example.org",
+                "www.example.org.corp.example",
+                "www.example.org.example"
+            ]
+        );
+        assert_eq!(conf.candidates("intranet."), ["intranet"]);
+
+        let conf = ResolvConf { ndots: 5, ..conf };
+        assert_eq!(
+            conf.candidates("www.example.org"),
+            [
+                "www.example.org.corp.example",
+                "www.example.org.example",
+                "www.example.org"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hosts_parse() {
+        let hosts = Hosts::parse(
+            "127.0.0.1 localhost
+            ::1       localhost ip6-localhost # loopback
+            # 192.0.2.9 commented.example
+            192.0.2.10 NAS.home.arpa nas
+            192.0.2.11 nas
+            not-an-ip broken",
+        );
+        assert_eq!(
+            hosts.addresses("localhost"),
+            [
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            hosts.addresses("nas."),
+            [
+                "192.0.2.10".parse::<IpAddr>().unwrap(),
+                "192.0.2.11".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            hosts.names("192.0.2.10".parse().unwrap()),
+            ["nas.home.arpa", "nas"]
+        );
+        assert!(hosts.addresses("commented.example").is_empty());
+        assert!(hosts.addresses("broken").is_empty());
+    }
+
+    /// A name server that only knows `intranet.corp.example`, answers NXDOMAIN otherwise and records the names
+    /// it was asked for
+    async fn stub_server() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let asked = Arc::new(Mutex::new(vec![]));
+        let names = Arc::clone(&asked);
+
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            loop {
+                let (_, client) = socket.recv_from(&mut query).await.unwrap();
+                let mut packet = DnsParser::new(&query).parse().unwrap();
+                names
+                    .lock()
+                    .unwrap()
+                    .push(packet.question.domain_name.clone());
+
+                packet.header.flags.query = false;
+                packet.additional.clear();
+                if packet.question.domain_name == "intranet.corp.example"
+                    && packet.question.r#type == RecordType::A
+                {
+                    packet.answers = vec![ResourceRecord::new(
+                        ResourceRecordMeta {
+                            name: packet.question.domain_name.clone(),
+                            record_type: RecordType::A,
+                            class: 1,
+                            ttl: 60,
+                            len: 0,
+                        },
+                        ResourceRecordData::A {
+                            ipv4: [192, 0, 2, 80].into(),
+                        },
+                    )];
+                } else if packet.question.domain_name != "intranet.corp.example" {
+                    packet.header.flags.response_code = ResponseCode::NXDOMAIN.into();
+                }
+                let response = serialize_response(&packet).unwrap();
+                socket.send_to(&response, client).await.unwrap();
+            }
+        });
+        (address, asked)
+    }
+
+    fn system_resolver(nameserver: SocketAddr, hosts: &str) -> SystemResolver {
+        let conf = ResolvConf {
+            nameservers: vec![nameserver],
+            search: vec!["example".into(), "corp.example".into()],
+            timeout: Duration::from_millis(200),
+            ..ResolvConf::default()
+        };
+        SystemResolver::new(conf, Hosts::parse(hosts))
+    }
+
+    #[tokio::test]
+    async fn test_query_expands_search_domains() {
+        let (address, asked) = stub_server().await;
+        let resolver = system_resolver(address, "");
+
+        let packet = resolver
+            .query(&Query::new("intranet", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(packet.question.domain_name, "intranet.corp.example");
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(
+            *asked.lock().unwrap(),
+            ["intranet.example", "intranet.corp.example"]
+        );
+
+        // The last candidate's answer is returned if none has records
+        asked.lock().unwrap().clear();
+        let packet = resolver
+            .query(&Query::new("missing.example.org", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(
+            packet.header.flags.response_code,
+            u8::from(ResponseCode::NXDOMAIN)
+        );
+        assert_eq!(
+            *asked.lock().unwrap(),
+            [
+                "missing.example.org",
+                "missing.example.org.example",
+                "missing.example.org.corp.example"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_ip_prefers_hosts() {
+        let (address, asked) = stub_server().await;
+        let resolver = system_resolver(address, "192.0.2.10 intranet");
+
+        assert_eq!(
+            resolver.lookup_ip("intranet").await.unwrap(),
+            ["192.0.2.10".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            resolver.lookup_ip("2001:db8::1").await.unwrap(),
+            ["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+        assert!(asked.lock().unwrap().is_empty());
+
+        let resolver = system_resolver(address, "");
+        assert_eq!(
+            resolver.lookup_ip("intranet").await.unwrap(),
+            ["192.0.2.80".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ptr() {
+        let (address, asked) = stub_server().await;
+        let resolver = system_resolver(address, "192.0.2.10 nas.home.arpa nas");
+
+        assert_eq!(
+            resolver.resolve_ptr([192, 0, 2, 10].into()).await.unwrap(),
+            ["nas.home.arpa", "nas"]
+        );
+        assert!(asked.lock().unwrap().is_empty());
+
+        // Other addresses are looked up without the search domains
+        assert!(
+            resolver
+                .resolve_ptr([192, 0, 2, 11].into())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(*asked.lock().unwrap(), ["11.2.0.192.in-addr.arpa"]);
+    }
+}
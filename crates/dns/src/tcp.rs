@@ -0,0 +1,235 @@
+use std::{io, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+/// Sends DNS messages to a single upstream server via TCP, see https://datatracker.ietf.org/doc/html/rfc7766
+///
+/// The connection is kept open after an exchange and reused for the next one. If the server closed it in the
+/// meantime, the exchange is transparently retried once on a new connection. Exchanges are serialized, so
+/// there is at most one message in flight per connection.
+#[derive(Debug)]
+pub struct TcpConnection {
+    server: String,
+    connect_timeout: Duration,
+    timeout: Duration,
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl TcpConnection {
+    pub fn new(server: impl Into<String>) -> Self {
+        Self {
+            server: server.into(),
+            connect_timeout: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+            stream: Mutex::new(None),
+        }
+    }
+
+    /// Sets how long to wait for the connection to be established
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets how long to wait for the answer once the request was sent
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends the raw DNS message `request` and returns the server's answer with the same request ID
+    pub async fn exchange(&self, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let mut stream = self.stream.lock().await;
+
+        if let Some(connection) = stream.as_mut() {
+            match self.exchange_on(connection, request).await {
+                Ok(response) => return Ok(response),
+                // Waiting for the same answer on a new connection is unlikely to help
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    *stream = None;
+                    return Err(e);
+                }
+                Err(_) => *stream = None,
+            }
+        }
+
+        let mut connection = tokio::time::timeout(
+            self.connect_timeout,
+            TcpStream::connect(self.server.as_str()),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "dns tcp: connection timed out"))??;
+        connection.set_nodelay(true)?;
+
+        let response = self.exchange_on(&mut connection, request).await?;
+        *stream = Some(connection);
+        Ok(response)
+    }
+
+    async fn exchange_on(
+        &self,
+        connection: &mut TcpStream,
+        request: &[u8],
+    ) -> Result<Vec<u8>, io::Error> {
+        let response = tokio::time::timeout(self.timeout, async {
+            write_message(connection, request).await?;
+            read_message(connection).await
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "dns tcp: answer timed out"))??;
+
+        if response.get(..2) != request.get(..2) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "dns tcp: answer has a different request ID",
+            ));
+        }
+        Ok(response)
+    }
+}
+
+/// Writes `message` prefixed with its two byte length, see https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &[u8],
+) -> Result<(), io::Error> {
+    // Write prefix and message at once, some servers do not like receiving them in separate segments
+    writer.write_all(&frame(message)?).await?;
+    writer.flush().await
+}
+
+/// Reads a single message that is prefixed with its two byte length
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
+    let len = reader.read_u16().await?;
+    let mut message = vec![0; len as usize];
+    reader.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+/// Prefixes `message` with its two byte length
+pub(crate) fn frame(message: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let len = u16::try_from(message.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "dns tcp: message exceeds 65535 bytes",
+        )
+    })?;
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(message);
+    Ok(framed)
+}
+
+/// Like `read_message`, but for blocking readers like `std::net::TcpStream`
+pub(crate) fn read_message_blocking<R: io::Read>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
+    let mut len = [0; 2];
+    reader.read_exact(&mut len)?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use tokio::net::TcpListener;
+
+    use crate::tcp::{TcpConnection, frame, read_message, read_message_blocking, write_message};
+
+    #[tokio::test]
+    async fn test_framing_round_trip() {
+        let mut framed = vec![];
+        write_message(&mut framed, &[0xAB; 600]).await.unwrap();
+        write_message(&mut framed, b"\x13\x37").await.unwrap();
+        assert_eq!(framed[..2], 600u16.to_be_bytes());
+        assert_eq!(framed.len(), 2 + 600 + 2 + 2);
+
+        let mut reader = framed.as_slice();
+        assert_eq!(read_message(&mut reader).await.unwrap(), [0xAB; 600]);
+        assert_eq!(read_message(&mut reader).await.unwrap(), b"\x13\x37");
+        assert!(read_message(&mut reader).await.is_err());
+
+        assert!(write_message(&mut framed, &vec![0; 70_000]).await.is_err());
+
+        let mut reader = framed.as_slice();
+        assert_eq!(read_message_blocking(&mut reader).unwrap(), [0xAB; 600]);
+        assert_eq!(read_message_blocking(&mut reader).unwrap(), b"\x13\x37");
+        assert!(read_message_blocking(&mut reader).is_err());
+        assert_eq!(frame(b"\x13\x37").unwrap(), b"\x00\x02\x13\x37");
+    }
+
+    /// Echoes every request, but closes each connection after `per_connection` requests
+    async fn echo_server(per_connection: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    for _ in 0..per_connection {
+                        let Ok(request) = read_message(&mut stream).await else {
+                            return;
+                        };
+                        write_message(&mut stream, &request).await.unwrap();
+                    }
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    #[tokio::test]
+    async fn test_exchange_reuses_connection() {
+        let (address, connections) = echo_server(usize::MAX).await;
+        let connection = TcpConnection::new(address);
+
+        for id in 0..3u8 {
+            let response = connection.exchange(&[0, id, 1, 2, 3]).await.unwrap();
+            assert_eq!(response, [0, id, 1, 2, 3]);
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_reconnects_closed_connection() {
+        let (address, connections) = echo_server(1).await;
+        let connection = TcpConnection::new(address);
+
+        for id in 0..3u8 {
+            let response = connection.exchange(&[0, id, 1, 2, 3]).await.unwrap();
+            assert_eq!(response, [0, id, 1, 2, 3]);
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        // Accept, but never answer
+        tokio::spawn(async move {
+            let mut streams = vec![];
+            loop {
+                streams.push(listener.accept().await.unwrap());
+            }
+        });
+
+        let connection = TcpConnection::new(address).with_timeout(Duration::from_millis(50));
+        let error = connection.exchange(&[0, 1, 2]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
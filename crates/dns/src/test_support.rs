@@ -0,0 +1,36 @@
+//! Helpers shared by the tests of the `tls` and `quic` modules
+
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+use tokio_rustls::rustls::{
+    RootCertStore, ServerConfig,
+    pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+};
+
+/// Issues a certificate for `dns.test` by a freshly generated CA and returns both
+pub(crate) fn certificates() -> (RootCertStore, ServerConfig) {
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+
+    let key = KeyPair::generate().unwrap();
+    let certificate = CertificateParams::new(vec!["dns.test".to_string()])
+        .unwrap()
+        .signed_by(&key, &ca)
+        .unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![certificate.der().clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        )
+        .unwrap();
+    (roots, config)
+}
+
+/// A query with request ID 0x1337 whose question section is just a marker byte
+pub(crate) fn query(marker: u8) -> Vec<u8> {
+    vec![0x13, 0x37, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, marker]
+}
@@ -0,0 +1,407 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use rustls_platform_verifier::ConfigVerifierExt;
+use tokio::{
+    io::WriteHalf,
+    net::TcpStream,
+    sync::{Mutex, oneshot},
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    TlsConnector,
+    client::TlsStream,
+    rustls::{ClientConfig, RootCertStore, pki_types::ServerName},
+};
+
+use crate::{
+    client::random_id,
+    tcp::{read_message, write_message},
+};
+
+/// Sends DNS messages to a single upstream server via DNS-over-TLS, see https://datatracker.ietf.org/doc/html/rfc7858
+///
+/// Queries are pipelined over one persistent connection, so several of them can be in flight at the same time.
+/// Every query gets a request ID that is unique on the connection, which is used to match the answers in whatever
+/// order the server sends them, and the query's original ID is restored in its answer. A connection that failed or
+/// was closed by the server is replaced by a new one on the next exchange.
+pub struct TlsConnection {
+    server: String,
+    server_name: ServerName<'static>,
+    connector: TlsConnector,
+    connect_timeout: Duration,
+    timeout: Duration,
+    pipeline: Mutex<Option<Arc<Pipeline>>>,
+}
+
+impl TlsConnection {
+    /// Connects to `server`, whose certificate has to be valid for `server_name` according to the system's trust store
+    pub fn new(server: impl Into<String>, server_name: &str) -> Result<Self, io::Error> {
+        let config = ClientConfig::with_platform_verifier().map_err(io::Error::other)?;
+        Self::with_config(server, server_name, config)
+    }
+
+    /// Connects to `server`, whose certificate has to be issued for `server_name` by one of `roots`
+    pub fn with_roots(
+        server: impl Into<String>,
+        server_name: &str,
+        roots: RootCertStore,
+    ) -> Result<Self, io::Error> {
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Self::with_config(server, server_name, config)
+    }
+
+    fn with_config(
+        server: impl Into<String>,
+        server_name: &str,
+        config: ClientConfig,
+    ) -> Result<Self, io::Error> {
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        Ok(Self {
+            server: server.into(),
+            server_name,
+            connector: TlsConnector::from(Arc::new(config)),
+            connect_timeout: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+            pipeline: Mutex::new(None),
+        })
+    }
+
+    /// Sets how long to wait for the connection and TLS handshake to be established
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets how long to wait for the answer once the request was sent
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends the raw DNS message `request` and returns the server's answer with the same request ID
+    pub async fn exchange(&self, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        if request.len() < 12 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "dns tls: request is too short",
+            ));
+        }
+
+        let pipeline = self.pipeline().await?;
+        match self.exchange_on(&pipeline, request).await {
+            // Waiting for the same answer on a new connection is unlikely to help
+            Err(e) if e.kind() != io::ErrorKind::TimedOut => {
+                // The server may have closed a reused connection in the meantime
+                self.discard(&pipeline).await;
+                let pipeline = self.pipeline().await?;
+                self.exchange_on(&pipeline, request).await
+            }
+            result => result,
+        }
+    }
+
+    async fn exchange_on(&self, pipeline: &Pipeline, request: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let (id, answer) = pipeline.register()?;
+        let mut request = request.to_vec();
+        let original_id = [request[0], request[1]];
+        request[..2].copy_from_slice(&id.to_be_bytes());
+
+        let mut written = false;
+        let result = tokio::time::timeout(self.timeout, async {
+            write_message(&mut *pipeline.writer.lock().await, &request).await?;
+            written = true;
+            answer.await.map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "dns tls: connection closed",
+                )
+            })
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "dns tls: answer timed out",
+            ))
+        });
+
+        match result {
+            Ok(mut response) => {
+                response[..2].copy_from_slice(&original_id);
+                Ok(response)
+            }
+            Err(e) => {
+                pipeline.unregister(id);
+                // A partially written message corrupts the framing of all following ones
+                if !written {
+                    self.discard(pipeline).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the current connection or establishes a new one, if there is none or it was closed
+    async fn pipeline(&self) -> Result<Arc<Pipeline>, io::Error> {
+        let mut current = self.pipeline.lock().await;
+        if let Some(pipeline) = current.as_ref().filter(|pipeline| !pipeline.is_closed()) {
+            return Ok(Arc::clone(pipeline));
+        }
+
+        let pipeline = Arc::new(self.connect().await?);
+        *current = Some(Arc::clone(&pipeline));
+        Ok(pipeline)
+    }
+
+    /// Drops `pipeline` if it is still the current connection
+    async fn discard(&self, pipeline: &Pipeline) {
+        let mut current = self.pipeline.lock().await;
+        if current
+            .as_ref()
+            .is_some_and(|current| std::ptr::eq(Arc::as_ptr(current), pipeline))
+        {
+            *current = None;
+        }
+    }
+
+    async fn connect(&self) -> Result<Pipeline, io::Error> {
+        let timed_out =
+            |_| io::Error::new(io::ErrorKind::TimedOut, "dns tls: connection timed out");
+
+        let stream = tokio::time::timeout(
+            self.connect_timeout,
+            TcpStream::connect(self.server.as_str()),
+        )
+        .await
+        .map_err(timed_out)??;
+        stream.set_nodelay(true)?;
+
+        let stream = tokio::time::timeout(
+            self.connect_timeout,
+            self.connector.connect(self.server_name.clone(), stream),
+        )
+        .await
+        .map_err(timed_out)??;
+
+        Ok(Pipeline::new(stream))
+    }
+}
+
+impl Debug for TlsConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConnection")
+            .field("server", &self.server)
+            .field("server_name", &self.server_name)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A single TLS connection with a task that dispatches incoming answers to the queries waiting for them
+struct Pipeline {
+    writer: Mutex<WriteHalf<TlsStream<TcpStream>>>,
+    pending: Arc<StdMutex<PendingAnswers>>,
+    reader: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct PendingAnswers {
+    /// Set once the connection can no longer receive answers
+    closed: bool,
+    answers: HashMap<u16, oneshot::Sender<Vec<u8>>>,
+}
+
+impl Pipeline {
+    fn new(stream: TlsStream<TcpStream>) -> Self {
+        let (mut reader, writer) = tokio::io::split(stream);
+        let pending = Arc::new(StdMutex::new(PendingAnswers::default()));
+
+        let dispatched = Arc::clone(&pending);
+        let reader = tokio::spawn(async move {
+            while let Ok(answer) = read_message(&mut reader).await {
+                if answer.len() < 12 {
+                    break;
+                }
+                let id = u16::from_be_bytes([answer[0], answer[1]]);
+                // Answers to queries that already timed out are dropped
+                if let Some(sender) = dispatched.lock().unwrap().answers.remove(&id) {
+                    let _ = sender.send(answer);
+                }
+            }
+
+            // Dropping the senders wakes up every query that still waits for an answer
+            let mut pending = dispatched.lock().unwrap();
+            pending.closed = true;
+            pending.answers.clear();
+        });
+
+        Self {
+            writer: Mutex::new(writer),
+            pending,
+            reader,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().closed
+    }
+
+    /// Reserves a request ID that is not in use on this connection and returns it with the receiver for its answer
+    fn register(&self) -> Result<(u16, oneshot::Receiver<Vec<u8>>), io::Error> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "dns tls: connection closed",
+            ));
+        }
+
+        let id = loop {
+            let id = random_id().map_err(io::Error::other)?;
+            if !pending.answers.contains_key(&id) {
+                break id;
+            }
+        };
+        let (sender, receiver) = oneshot::channel();
+        pending.answers.insert(id, sender);
+        Ok((id, receiver))
+    }
+
+    fn unregister(&self, id: u16) {
+        self.pending.lock().unwrap().answers.remove(&id);
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use tokio::net::TcpListener;
+    use tokio_rustls::{TlsAcceptor, rustls::ServerConfig};
+
+    use crate::{
+        tcp::{read_message, write_message},
+        test_support::{certificates, query},
+        tls::TlsConnection,
+    };
+
+    /// Answers batches of `batch` queries in reverse order and closes each connection after `per_connection` queries
+    async fn stub_server(
+        config: ServerConfig,
+        batch: usize,
+        per_connection: usize,
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut answered = 0;
+                    while answered < per_connection {
+                        let mut queries = vec![];
+                        for _ in 0..batch {
+                            let Ok(query) = read_message(&mut stream).await else {
+                                return;
+                            };
+                            queries.push(query);
+                        }
+                        for mut query in queries.into_iter().rev() {
+                            query[2] |= 0b1000_0000;
+                            write_message(&mut stream, &query).await.unwrap();
+                            answered += 1;
+                        }
+                    }
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    #[tokio::test]
+    async fn test_exchange_pipelines_queries() {
+        let (roots, config) = certificates();
+        let (address, connections) = stub_server(config, 2, usize::MAX).await;
+        let connection = TlsConnection::with_roots(address, "dns.test", roots).unwrap();
+
+        // Both queries have to be in flight at once, since the server only answers pairs of queries
+        let (first, second) = (query(1), query(2));
+        let (first, second) =
+            tokio::join!(connection.exchange(&first), connection.exchange(&second));
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(first[..3], [0x13, 0x37, 0x81]);
+        assert_eq!(first[12], 1);
+        assert_eq!(second[..3], [0x13, 0x37, 0x81]);
+        assert_eq!(second[12], 2);
+
+        let (third, fourth) = (query(3), query(4));
+        let (third, fourth) =
+            tokio::join!(connection.exchange(&third), connection.exchange(&fourth));
+        assert_eq!(third.unwrap()[12], 3);
+        assert_eq!(fourth.unwrap()[12], 4);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_reconnects_closed_connection() {
+        let (roots, config) = certificates();
+        let (address, connections) = stub_server(config, 1, 1).await;
+        let connection = TlsConnection::with_roots(address, "dns.test", roots).unwrap();
+
+        for marker in 0..3 {
+            let answer = connection.exchange(&query(marker)).await.unwrap();
+            assert_eq!(answer[12], marker);
+            // Give the connection time to notice that the server closed it
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_validates_certificate() {
+        let (_, config) = certificates();
+        let (untrusted_roots, _) = certificates();
+        let (address, _) = stub_server(config, 1, usize::MAX).await;
+
+        let connection =
+            TlsConnection::with_roots(address.clone(), "dns.test", untrusted_roots).unwrap();
+        assert!(connection.exchange(&query(1)).await.is_err());
+
+        let (roots, config) = certificates();
+        let (address, _) = stub_server(config, 1, usize::MAX).await;
+        let connection = TlsConnection::with_roots(address, "other.test", roots).unwrap();
+        assert!(connection.exchange(&query(1)).await.is_err());
+    }
+}
@@ -0,0 +1,749 @@
+//! Transaction signatures (TSIG) for authenticating DNS messages, see https://datatracker.ietf.org/doc/html/rfc8945.
+//!
+//! Messages are signed after serialization by appending a TSIG resource record to their additional section,
+//! e.g. `sign(&mut serialize_update(&update)?, &key, now, None)`, and verified before parsing them.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Display;
+
+use base64::Engine;
+use hmac::{Hmac, Mac, digest::KeyInit};
+use sha2::{Sha256, Sha512};
+
+use crate::{
+    Error,
+    parser::{Collate, DnsParser, encode_domain_name},
+    protocol::{
+        answer::{ResourceRecord, ResourceRecordData, ResourceRecordMeta},
+        class::CLASS_ANY,
+        record_type::RecordType,
+    },
+    serialize::encode_resource_record,
+};
+
+/// The permitted difference in seconds between the signing time and the time of verification,
+/// as recommended by https://datatracker.ietf.org/doc/html/rfc8945#section-10.
+pub const DEFAULT_FUDGE: u16 = 300;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    /// The algorithm name as used in TSIG records and BIND key files
+    pub fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    /// Looks up an algorithm by name, ignoring case and a trailing dot
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim_end_matches('.');
+        [TsigAlgorithm::HmacSha256, TsigAlgorithm::HmacSha512]
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The length of an untruncated MAC in bytes
+    pub fn output_len(&self) -> usize {
+        match self {
+            TsigAlgorithm::HmacSha256 => 32,
+            TsigAlgorithm::HmacSha512 => 64,
+        }
+    }
+
+    fn sign(&self, secret: &[u8], chunks: &[&[u8]]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => hmac::<Hmac<Sha256>>(secret, chunks)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+            TsigAlgorithm::HmacSha512 => hmac::<Hmac<Sha512>>(secret, chunks)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+        }
+    }
+
+    /// Compares the (possibly truncated) `mac` in constant time
+    fn verify(&self, secret: &[u8], chunks: &[&[u8]], mac: &[u8]) -> bool {
+        match self {
+            TsigAlgorithm::HmacSha256 => hmac::<Hmac<Sha256>>(secret, chunks)
+                .verify_truncated_left(mac)
+                .is_ok(),
+            TsigAlgorithm::HmacSha512 => hmac::<Hmac<Sha512>>(secret, chunks)
+                .verify_truncated_left(mac)
+                .is_ok(),
+        }
+    }
+}
+
+fn hmac<M: Mac + KeyInit>(secret: &[u8], chunks: &[&[u8]]) -> M {
+    let mut mac = <M as KeyInit>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    for chunk in chunks {
+        mac.update(chunk);
+    }
+    mac
+}
+
+/// A shared secret that is used to sign and verify messages.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl core::fmt::Debug for TsigKey {
+    // Never leak the secret into logs
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TsigKey")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TsigKey {
+    pub fn new(name: &str, algorithm: TsigAlgorithm, secret: Vec<u8>) -> Self {
+        Self {
+            name: name.into(),
+            algorithm,
+            secret,
+        }
+    }
+
+    /// Parses all `key` statements of a BIND configuration snippet, as generated by `tsig-keygen`:
+    ///
+    /// ```text
+    /// key "dhcp-updater" {
+    ///     algorithm hmac-sha256;
+    ///     secret "TWFueSBoYW5kcyBtYWtlIGxpZ2h0IHdvcmsu";
+    /// };
+    /// ```
+    pub fn from_bind_config(config: &str) -> Result<Vec<Self>, TsigError> {
+        let tokens = tokenize_bind_config(config)?;
+        let mut tokens = tokens.into_iter();
+        let mut keys = Vec::new();
+
+        while let Some(statement) = tokens.next() {
+            if statement != "key" {
+                return Err(TsigError::InvalidKeyConfig(alloc::format!(
+                    "unsupported statement '{statement}'"
+                )));
+            }
+
+            let name = tokens.next().ok_or(missing("key name"))?;
+            expect_token(&mut tokens, "{")?;
+
+            let mut algorithm = None;
+            let mut secret = None;
+            loop {
+                let option = tokens.next().ok_or(missing("'}'"))?;
+                if option == "}" {
+                    break;
+                }
+                let value = tokens.next().ok_or(missing("option value"))?;
+                expect_token(&mut tokens, ";")?;
+
+                match option.as_str() {
+                    "algorithm" => {
+                        algorithm = Some(TsigAlgorithm::from_name(&value).ok_or_else(|| {
+                            TsigError::InvalidKeyConfig(alloc::format!(
+                                "unsupported algorithm '{value}'"
+                            ))
+                        })?);
+                    }
+                    "secret" => {
+                        secret = Some(
+                            base64::engine::general_purpose::STANDARD
+                                .decode(&value)
+                                .map_err(|_| {
+                                    TsigError::InvalidKeyConfig(alloc::format!(
+                                        "secret of key '{name}' is not valid base64"
+                                    ))
+                                })?,
+                        );
+                    }
+                    _ => {
+                        return Err(TsigError::InvalidKeyConfig(alloc::format!(
+                            "unsupported key option '{option}'"
+                        )));
+                    }
+                }
+            }
+            expect_token(&mut tokens, ";")?;
+
+            keys.push(TsigKey::new(
+                &name,
+                algorithm.ok_or(missing("algorithm"))?,
+                secret.ok_or(missing("secret"))?,
+            ));
+        }
+
+        Ok(keys)
+    }
+
+    /// Reads all keys from a BIND key file, see `TsigKey::from_bind_config`.
+    #[cfg(feature = "std")]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Vec<Self>, TsigError> {
+        let config = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+            TsigError::InvalidKeyConfig(alloc::format!("{}: {e}", path.as_ref().display()))
+        })?;
+        Self::from_bind_config(&config)
+    }
+
+    fn matches(&self, name: &str, algorithm: &str) -> bool {
+        canonical_name(&self.name) == canonical_name(name)
+            && TsigAlgorithm::from_name(algorithm) == Some(self.algorithm)
+    }
+}
+
+fn missing(what: &str) -> TsigError {
+    TsigError::InvalidKeyConfig(alloc::format!("missing {what}"))
+}
+
+fn expect_token(
+    tokens: &mut impl Iterator<Item = String>,
+    expected: &str,
+) -> Result<(), TsigError> {
+    match tokens.next() {
+        Some(token) if token == expected => Ok(()),
+        Some(token) => Err(TsigError::InvalidKeyConfig(alloc::format!(
+            "expected '{expected}', found '{token}'"
+        ))),
+        None => Err(missing(&alloc::format!("'{expected}'"))),
+    }
+}
+
+/// Splits a BIND configuration into words, quoted strings and the punctuation `{`, `}` and `;`,
+/// while dropping `#`, `//` and `/* */` comments.
+fn tokenize_bind_config(config: &str) -> Result<Vec<String>, TsigError> {
+    let mut tokens = Vec::new();
+    let mut chars = config.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => loop {
+                match chars.next() {
+                    Some('*') if chars.next_if_eq(&'/').is_some() => break,
+                    Some(_) => {}
+                    None => return Err(missing("end of comment")),
+                }
+            },
+            '{' | '}' | ';' => tokens.push(c.to_string()),
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => token.push(c),
+                        None => return Err(missing("closing quote")),
+                    }
+                }
+                tokens.push(token);
+            }
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|&c| !c.is_whitespace() && !matches!(c, '{' | '}' | ';' | '"'))
+                {
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TsigError {
+    /// The message does not end with a TSIG record
+    Missing,
+    /// The TSIG record is malformed, e.g. its MAC is longer than the algorithm's output
+    FormErr,
+    /// The message was signed with an unknown key or algorithm
+    BadKey,
+    /// The MAC does not match the message
+    BadSig,
+    /// The message was signed outside of the permitted time window
+    BadTime,
+    /// The MAC was truncated below the permitted minimum length
+    BadTrunc,
+    /// The message could not be parsed
+    Parse(Error),
+    /// A BIND key file could not be read or parsed
+    InvalidKeyConfig(String),
+}
+
+impl TsigError {
+    /// The TSIG error code to report back to the signer, see https://datatracker.ietf.org/doc/html/rfc8945#section-3
+    pub fn rcode(&self) -> Option<u16> {
+        match self {
+            TsigError::BadSig => Some(16),
+            TsigError::BadKey => Some(17),
+            TsigError::BadTime => Some(18),
+            TsigError::BadTrunc => Some(22),
+            _ => None,
+        }
+    }
+}
+
+impl Display for TsigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TsigError::Missing => f.write_str("tsig: message is not signed"),
+            TsigError::FormErr => f.write_str("tsig: malformed record"),
+            TsigError::BadKey => f.write_str("tsig: unknown key or algorithm"),
+            TsigError::BadSig => f.write_str("tsig: signature does not match"),
+            TsigError::BadTime => f.write_str("tsig: signing time is outside of the fudge window"),
+            TsigError::BadTrunc => f.write_str("tsig: signature is truncated too much"),
+            TsigError::Parse(e) => write!(f, "tsig: {e}"),
+            TsigError::InvalidKeyConfig(reason) => write!(f, "tsig: invalid key file: {reason}"),
+        }
+    }
+}
+
+impl core::error::Error for TsigError {}
+
+impl From<Error> for TsigError {
+    fn from(e: Error) -> Self {
+        TsigError::Parse(e)
+    }
+}
+
+/// Signs the serialized `message` by appending a TSIG record with the given signing time in seconds
+/// since the UNIX epoch and returns the MAC.
+///
+/// Responses to signed requests have to pass the request's MAC as `request_mac`.
+pub fn sign(
+    message: &mut Vec<u8>,
+    key: &TsigKey,
+    time_signed: u64,
+    request_mac: Option<&[u8]>,
+) -> Result<Vec<u8>, TsigError> {
+    if message.len() < 12 {
+        return Err(TsigError::FormErr);
+    }
+
+    let original_id = message[0..2].collate() as u16;
+    let variables = TsigVariables {
+        key_name: &key.name,
+        algorithm: key.algorithm.name(),
+        time_signed,
+        fudge: DEFAULT_FUDGE,
+        error: 0,
+        other: &[],
+    }
+    .encode();
+
+    let request_mac = request_mac.map(encode_request_mac).unwrap_or_default();
+    let mac = key
+        .algorithm
+        .sign(&key.secret, &[&request_mac, message, &variables]);
+
+    let record = ResourceRecord::new(
+        ResourceRecordMeta {
+            name: key.name.clone(),
+            record_type: RecordType::TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            len: 0,
+        },
+        ResourceRecordData::TSIG {
+            algorithm: key.algorithm.name().into(),
+            time_signed,
+            fudge: DEFAULT_FUDGE,
+            mac: mac.clone(),
+            original_id,
+            error: 0,
+            other: Vec::new(),
+        },
+    );
+    encode_resource_record(message, &record)?;
+
+    let additional_count = message[10..12].collate() as u16 + 1;
+    message[10..12].copy_from_slice(&additional_count.to_be_bytes());
+
+    Ok(mac)
+}
+
+/// Verifies the TSIG record at the end of `message` against `keys` at the time `now` in seconds since the
+/// UNIX epoch and returns it, see https://datatracker.ietf.org/doc/html/rfc8945#section-5.2.
+///
+/// Responses to signed requests have to pass the request's MAC as `request_mac`.
+pub fn verify(
+    message: &[u8],
+    keys: &[TsigKey],
+    now: u64,
+    request_mac: Option<&[u8]>,
+) -> Result<ResourceRecord, TsigError> {
+    // Skip over all sections until we reach the last record, which has to be the TSIG record
+    let mut parser = DnsParser::new(message);
+    let header = parser.parse_header()?;
+    if header.additional_count == 0 {
+        return Err(TsigError::Missing);
+    }
+    for _ in 0..header.question_count {
+        parser.parse_question()?;
+    }
+    let preceding_records = header.answer_count as usize
+        + header.authority_count as usize
+        + header.additional_count as usize
+        - 1;
+    for _ in 0..preceding_records {
+        parser.parse_resource_record()?;
+    }
+    let tsig_start = parser.position();
+    let record = parser.parse_resource_record()?;
+
+    let ResourceRecordData::TSIG {
+        algorithm,
+        time_signed,
+        fudge,
+        mac,
+        original_id,
+        error,
+        other,
+    } = &record.value
+    else {
+        return Err(TsigError::Missing);
+    };
+
+    let key = keys
+        .iter()
+        .find(|key| key.matches(&record.meta.name, algorithm))
+        .ok_or(TsigError::BadKey)?;
+
+    // MAC truncation rules, see https://datatracker.ietf.org/doc/html/rfc8945#section-5.2.2.1
+    let output_len = key.algorithm.output_len();
+    if mac.len() > output_len {
+        return Err(TsigError::FormErr);
+    }
+    if mac.len() < usize::max(10, output_len / 2) {
+        return Err(TsigError::BadTrunc);
+    }
+
+    // The MAC covers the message as it was before the TSIG record was added
+    let mut unsigned = message[..tsig_start].to_vec();
+    unsigned[0..2].copy_from_slice(&original_id.to_be_bytes());
+    unsigned[10..12].copy_from_slice(&(header.additional_count - 1).to_be_bytes());
+
+    let variables = TsigVariables {
+        key_name: &record.meta.name,
+        algorithm,
+        time_signed: *time_signed,
+        fudge: *fudge,
+        error: *error,
+        other,
+    }
+    .encode();
+
+    let request_mac = request_mac.map(encode_request_mac).unwrap_or_default();
+    if !key
+        .algorithm
+        .verify(&key.secret, &[&request_mac, &unsigned, &variables], mac)
+    {
+        return Err(TsigError::BadSig);
+    }
+
+    // Time check, see https://datatracker.ietf.org/doc/html/rfc8945#section-5.2.3
+    if now.abs_diff(*time_signed) > u64::from(*fudge) {
+        return Err(TsigError::BadTime);
+    }
+
+    Ok(record)
+}
+
+/// The TSIG fields that are part of the MAC, see https://datatracker.ietf.org/doc/html/rfc8945#section-4.3.3
+struct TsigVariables<'a> {
+    key_name: &'a str,
+    algorithm: &'a str,
+    time_signed: u64,
+    fudge: u16,
+    error: u16,
+    other: &'a [u8],
+}
+
+impl TsigVariables<'_> {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = encode_domain_name(&canonical_name(self.key_name));
+        encoded.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        encoded.extend_from_slice(&0u32.to_be_bytes());
+        encoded.extend(encode_domain_name(&canonical_name(self.algorithm)));
+        encoded.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        encoded.extend_from_slice(&self.fudge.to_be_bytes());
+        encoded.extend_from_slice(&self.error.to_be_bytes());
+        encoded.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        encoded.extend_from_slice(self.other);
+        encoded
+    }
+}
+
+fn encode_request_mac(mac: &[u8]) -> Vec<u8> {
+    let mut encoded = (mac.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(mac);
+    encoded
+}
+
+/// Names are compared and digested in their canonical, lowercase form without a trailing dot
+fn canonical_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{
+        Error,
+        protocol::{answer::ResourceRecordData, record_type::RecordType, update::UpdateBuilder},
+        serialize::serialize_update,
+        tsig::{TsigAlgorithm, TsigError, TsigKey, sign, verify},
+    };
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn test_key(algorithm: TsigAlgorithm) -> TsigKey {
+        TsigKey::new("dhcp-updater.", algorithm, b"0123456789abcdef".to_vec())
+    }
+
+    /// Returns the signed message, its MAC and the length of the message before signing
+    fn signed_update(key: &TsigKey) -> (Vec<u8>, Vec<u8>, usize) {
+        let update = UpdateBuilder::new(4242, "example.com")
+            .add(
+                "host.example.com",
+                300,
+                ResourceRecordData::A {
+                    ipv4: [192, 0, 2, 10].into(),
+                },
+            )
+            .build()
+            .unwrap();
+        let mut message = serialize_update(&update).unwrap();
+        let unsigned_len = message.len();
+        let mac = sign(&mut message, key, NOW, None).unwrap();
+        (message, mac, unsigned_len)
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        for algorithm in [TsigAlgorithm::HmacSha256, TsigAlgorithm::HmacSha512] {
+            let key = test_key(algorithm);
+            let (message, mac, _) = signed_update(&key);
+            assert_eq!(mac.len(), algorithm.output_len());
+
+            let record = verify(&message, &[key], NOW + 10, None).unwrap();
+            assert!(matches!(
+                record.value,
+                ResourceRecordData::TSIG {
+                    original_id: 4242,
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_known_mac() {
+        // Computed independently with Python's `hmac` module over the request, key name, class, TTL,
+        // algorithm name, time signed, fudge, error and other length
+        let key = test_key(TsigAlgorithm::HmacSha256);
+        let (_, mac, _) = signed_update(&key);
+        assert_eq!(
+            mac,
+            [
+                0x0e, 0xc1, 0x27, 0xd7, 0x0d, 0xb1, 0xce, 0x0c, 0xae, 0xe6, 0xe4, 0xd7, 0x18, 0xa7,
+                0x98, 0x72, 0x84, 0x54, 0x12, 0xf8, 0x5d, 0xff, 0x13, 0x12, 0xe2, 0x40, 0x2f, 0xcd,
+                0x41, 0x57, 0x11, 0x3e
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_response_with_request_mac() {
+        let key = test_key(TsigAlgorithm::HmacSha256);
+        let (_, request_mac, _) = signed_update(&key);
+
+        let mut response = [0x10, 0x92, 0xa8, 0x00, 0, 0, 0, 0, 0, 0, 0, 0].to_vec();
+        sign(&mut response, &key, NOW, Some(&request_mac)).unwrap();
+
+        assert!(
+            verify(
+                &response,
+                core::slice::from_ref(&key),
+                NOW,
+                Some(&request_mac)
+            )
+            .is_ok()
+        );
+        assert_eq!(verify(&response, &[key], NOW, None), Err(TsigError::BadSig));
+    }
+
+    #[test]
+    fn test_verify_failures() {
+        let key = test_key(TsigAlgorithm::HmacSha256);
+        let (message, _, unsigned_len) = signed_update(&key);
+
+        // Tampered message
+        let mut tampered = message.clone();
+        tampered[unsigned_len - 1] ^= 1;
+        assert_eq!(
+            verify(&tampered, core::slice::from_ref(&key), NOW, None),
+            Err(TsigError::BadSig)
+        );
+
+        // Unknown key
+        let other = TsigKey::new("other.", TsigAlgorithm::HmacSha256, b"secret".to_vec());
+        assert_eq!(
+            verify(&message, &[other], NOW, None),
+            Err(TsigError::BadKey)
+        );
+        assert_eq!(
+            verify(&message, &[test_key(TsigAlgorithm::HmacSha512)], NOW, None),
+            Err(TsigError::BadKey)
+        );
+
+        // Signed outside of the fudge window
+        assert_eq!(
+            verify(&message, core::slice::from_ref(&key), NOW + 301, None),
+            Err(TsigError::BadTime)
+        );
+        assert_eq!(
+            verify(&message, core::slice::from_ref(&key), NOW - 301, None),
+            Err(TsigError::BadTime)
+        );
+
+        // Unsigned message
+        let mut unsigned = message[..unsigned_len].to_vec();
+        unsigned[10..12].copy_from_slice(&0u16.to_be_bytes());
+        assert_eq!(
+            verify(&unsigned, core::slice::from_ref(&key), NOW, None),
+            Err(TsigError::Missing)
+        );
+
+        // Message whose TSIG record was cut off
+        assert_eq!(
+            verify(&message[..unsigned_len], &[key], NOW, None),
+            Err(TsigError::Parse(Error::Truncated))
+        );
+    }
+
+    #[test]
+    fn test_verify_truncated_mac() {
+        let key = test_key(TsigAlgorithm::HmacSha256);
+        let (message, _, unsigned_len) = signed_update(&key);
+
+        // The TSIG record starts with the key name (14 bytes), type, class, TTL and RDLENGTH (10 bytes),
+        // followed by the algorithm name (13 bytes), time signed, fudge and the MAC size (10 bytes).
+        let rdlength_start = unsigned_len + 14 + 8;
+        let mac_size_start = rdlength_start + 2 + 13 + 8;
+        let mac_start = mac_size_start + 2;
+        let truncate = |mac_len: usize| {
+            let mut truncated = message[..mac_start + mac_len].to_vec();
+            truncated.extend_from_slice(&message[mac_start + 32..]);
+            let rdlength = (message.len() - rdlength_start - 2 - (32 - mac_len)) as u16;
+            truncated[rdlength_start..rdlength_start + 2].copy_from_slice(&rdlength.to_be_bytes());
+            truncated[mac_size_start..mac_start].copy_from_slice(&(mac_len as u16).to_be_bytes());
+            truncated
+        };
+
+        assert!(verify(&truncate(32), core::slice::from_ref(&key), NOW, None).is_ok());
+        assert!(verify(&truncate(16), core::slice::from_ref(&key), NOW, None).is_ok());
+        assert_eq!(
+            verify(&truncate(15), &[key], NOW, None),
+            Err(TsigError::BadTrunc)
+        );
+    }
+
+    #[test]
+    fn test_verify_large_message() {
+        // Updates sent via TCP exceed the 512 bytes of a plain UDP message
+        let key = test_key(TsigAlgorithm::HmacSha256);
+        let update = (0..50)
+            .fold(UpdateBuilder::new(4242, "example.com"), |update, i| {
+                update.add(
+                    &alloc::format!("host{i}.example.com"),
+                    300,
+                    ResourceRecordData::A {
+                        ipv4: [192, 0, 2, i].into(),
+                    },
+                )
+            })
+            .build()
+            .unwrap();
+        let mut message = serialize_update(&update).unwrap();
+        sign(&mut message, &key, NOW, None).unwrap();
+        assert!(message.len() > 512);
+        assert!(verify(&message, &[key], NOW, None).is_ok());
+    }
+
+    #[test]
+    fn test_verify_invalid_mac_size() {
+        let key = test_key(TsigAlgorithm::HmacSha256);
+        let (message, _, unsigned_len) = signed_update(&key);
+
+        // See `test_verify_truncated_mac` for the layout of the TSIG record
+        let mac_size_start = unsigned_len + 14 + 8 + 2 + 13 + 8;
+        for mac_size in [39, u16::MAX] {
+            let mut invalid = message.clone();
+            invalid[mac_size_start..mac_size_start + 2].copy_from_slice(&mac_size.to_be_bytes());
+            assert_eq!(
+                verify(&invalid, core::slice::from_ref(&key), NOW, None),
+                Err(TsigError::Parse(Error::InvalidRecordLength(
+                    RecordType::TSIG
+                ))),
+                "{mac_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_keys_from_bind_config() {
+        let config = r#"
+            # generated by tsig-keygen
+            key "dhcp-updater." {
+                algorithm hmac-sha256;
+                secret "MDEyMzQ1Njc4OWFiY2RlZg==";
+            };
+            // a second key
+            key transfer { algorithm HMAC-SHA512; /* inline */ secret "c2VjcmV0"; };
+        "#;
+
+        let keys = TsigKey::from_bind_config(config).unwrap();
+        assert_eq!(
+            keys,
+            [
+                test_key(TsigAlgorithm::HmacSha256),
+                TsigKey::new("transfer", TsigAlgorithm::HmacSha512, b"secret".to_vec())
+            ]
+        );
+
+        assert!(matches!(
+            TsigKey::from_bind_config(r#"key "k" { algorithm hmac-md5; secret "c2VjcmV0"; };"#),
+            Err(TsigError::InvalidKeyConfig(_))
+        ));
+        assert!(matches!(
+            TsigKey::from_bind_config(r#"key "k" { algorithm hmac-sha256; };"#),
+            Err(TsigError::InvalidKeyConfig(_))
+        ));
+        assert!(matches!(
+            TsigKey::from_bind_config(r#"options { directory "/var"; };"#),
+            Err(TsigError::InvalidKeyConfig(_))
+        ));
+    }
+}
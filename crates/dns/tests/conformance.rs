@@ -0,0 +1,193 @@
+//! Conformance suite that runs stored DNS wire messages through the parser and serializer.
+//!
+//! Every capture in `tests/captures/<name>.bin` is a single 512 byte DNS packet buffer and is paired with
+//! `tests/captures/<name>.txt`, which holds the expected pretty-printed `DnsPacket` (or `UpdatePacket` for
+//! DNS UPDATE messages) the parser should produce.
+//! Set `DNS_CONFORMANCE_BLESS=1` to (re-)generate the expected output after adding or intentionally changing
+//! a capture, and review the resulting diff before committing it.
+
+use std::{path::PathBuf, time::Duration};
+
+use dns::{
+    Error,
+    parser::{DnsPacketBuffer, DnsParser},
+    protocol::{answer::ResourceRecord, packet::DnsPacket, record_type::RecordType},
+    serialize::serialize_update,
+};
+
+fn capture_path(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("captures")
+        .join(format!("{name}.{extension}"))
+}
+
+fn load_capture(name: &str) -> DnsPacketBuffer {
+    let raw = std::fs::read(capture_path(name, "bin")).unwrap();
+    raw.try_into()
+        .unwrap_or_else(|raw: Vec<u8>| panic!("{name}: expected 512 bytes, got {}", raw.len()))
+}
+
+fn records(packet: &DnsPacket) -> impl Iterator<Item = &ResourceRecord> {
+    packet
+        .answers
+        .iter()
+        .chain(&packet.authorities)
+        .chain(&packet.additional)
+}
+
+fn check_expected_output(name: &str, decoded: &str) {
+    let expected_path = capture_path(name, "txt");
+    if std::env::var_os("DNS_CONFORMANCE_BLESS").is_some() {
+        std::fs::write(&expected_path, decoded).unwrap();
+    }
+    let expected = std::fs::read_to_string(&expected_path)
+        .unwrap_or_else(|_| panic!("{name}: missing expected output {expected_path:?}"));
+    assert_eq!(decoded, expected, "{name}: decoded packet differs");
+}
+
+fn check_capture(name: &str) {
+    let buffer = load_capture(name);
+    let packet = DnsParser::new(&buffer).parse().unwrap();
+
+    // Parser: the decoded packet has to match the stored expectation
+    check_expected_output(name, &format!("{packet:#?}\n"));
+
+    // Serializer: the header has to survive a round trip unchanged
+    let header: [u8; 12] = packet.header.clone().into();
+    assert_eq!(header, buffer[0..12], "{name}: header round trip differs");
+
+    // Serializer: rewriting a cached packet without any TTL reduction must be lossless
+    let unchanged = DnsParser::new(&buffer)
+        .update_cached_packet(Duration::ZERO, packet.header.request_id)
+        .unwrap();
+    assert_eq!(unchanged, buffer, "{name}: cache rewrite is not lossless");
+
+    // Serializer: rewriting a cached packet has to reduce every TTL, except for the EDNS pseudo records
+    let new_request_id = packet.header.request_id.wrapping_add(1);
+    let rewritten = DnsParser::new(&buffer)
+        .update_cached_packet(Duration::from_secs(1), new_request_id)
+        .unwrap();
+    let rewritten_packet = DnsParser::new(&rewritten).parse().unwrap();
+    assert_eq!(rewritten_packet.header.request_id, new_request_id);
+    for (before, after) in records(&packet).zip(records(&rewritten_packet)) {
+        let expected_ttl = match before.meta.record_type {
+            RecordType::OPT => before.meta.ttl,
+            _ => before.meta.ttl.saturating_sub(1),
+        };
+        assert_eq!(
+            after.meta.ttl, expected_ttl,
+            "{name}: unexpected TTL for {:?}",
+            before.meta
+        );
+    }
+
+    // Serializer: capping has to lower every TTL above the limit, except for the EDNS pseudo records
+    let capped = DnsParser::new(&buffer).cap_ttls(1).unwrap();
+    let capped_packet = DnsParser::new(&capped).parse().unwrap();
+    for (before, after) in records(&packet).zip(records(&capped_packet)) {
+        let expected_ttl = match before.meta.record_type {
+            RecordType::OPT => before.meta.ttl,
+            _ => before.meta.ttl.min(1),
+        };
+        assert_eq!(
+            after.meta.ttl, expected_ttl,
+            "{name}: unexpected capped TTL for {:?}",
+            before.meta
+        );
+    }
+}
+
+fn check_update_capture(name: &str) {
+    let buffer = load_capture(name);
+    let packet = DnsParser::new(&buffer).parse_update().unwrap();
+
+    // Parser: the decoded update has to match the stored expectation and must not be mistaken for a query
+    check_expected_output(name, &format!("{packet:#?}\n"));
+    assert_eq!(
+        DnsParser::new(&buffer).parse().unwrap_err(),
+        Error::UnexpectedOpcode(5),
+        "{name}: update parsed as regular packet"
+    );
+
+    // Serializer: re-serializing the update has to yield the same sections again. Since we do not compress
+    // domain names, the raw bytes and `RDLENGTH`s only stay the same from the first serialization onwards.
+    let raw = serialize_update(&packet).unwrap();
+    let mut serialized = [0u8; 512];
+    serialized[..raw.len()].copy_from_slice(&raw);
+    let reparsed = DnsParser::new(&serialized).parse_update().unwrap();
+    assert_eq!(
+        reparsed.zone, packet.zone,
+        "{name}: zone round trip differs"
+    );
+    assert_eq!(
+        reparsed.prerequisites.len(),
+        packet.prerequisites.len(),
+        "{name}: prerequisites round trip differs"
+    );
+    assert_eq!(
+        reparsed.updates.len(),
+        packet.updates.len(),
+        "{name}: updates round trip differs"
+    );
+    assert_eq!(
+        serialize_update(&reparsed).unwrap(),
+        raw,
+        "{name}: update round trip differs"
+    );
+}
+
+macro_rules! conformance {
+    (packets: [$($name:ident),* $(,)?], updates: [$($update:ident),* $(,)?] $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                check_capture(stringify!($name));
+            }
+        )*
+
+        $(
+            #[test]
+            fn $update() {
+                check_update_capture(stringify!($update));
+            }
+        )*
+
+        #[test]
+        fn every_capture_is_checked() {
+            let checked = [$(stringify!($name),)* $(stringify!($update),)*];
+            let directory = capture_path("", "bin").parent().unwrap().to_path_buf();
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|extension| extension == "bin") {
+                    let name = path.file_stem().unwrap().to_str().unwrap();
+                    assert!(checked.contains(&name), "capture {name} is not part of the suite");
+                }
+            }
+        }
+    };
+}
+
+conformance!(
+    packets: [
+        query_a,
+        query_edns_cookie,
+        response_aaaa,
+        response_all_types,
+        response_chaos_class,
+        response_cname_multi_a,
+        response_compression_high_offset,
+        response_dnssec_dnskey,
+        response_dnssec_rrsig,
+        response_edns_badvers,
+        response_https,
+        response_mx,
+        response_ns_referral_glue,
+        response_nxdomain_soa,
+        response_ptr,
+        response_truncated,
+        response_truncated_partial,
+        response_txt,
+    ],
+    updates: [update_dhcp],
+);